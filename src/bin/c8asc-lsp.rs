@@ -0,0 +1,25 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use rchip8::c8asc::lsp::serve;
+use std::io;
+
+fn main() {
+    match serve(io::stdin().lock(), io::stdout().lock()) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("c8asc-lsp: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -11,14 +11,19 @@
 // You should have received a copy of the GNU General Public License along with rchip8.
 // If not, see <https://www.gnu.org/licenses/>.use clap::Parser;
 
-use clap::Parser;
-use lalrpop_util::{lalrpop_mod, lexer::Token, ParseError};
-use rchip8::c8asc::process_prog;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read, Write};
-
-lalrpop_mod!(c8asm);
-use c8asm::ProgramParser;
+use clap::{Parser, Subcommand};
+use rchip8::c8asc::{
+    assemble_object, assemble_sources,
+    diagnostic::{Diagnostic, Severity, Sources},
+    format::format,
+    lint::{Lint, Lints},
+    listing::{debug_info, listing, symbol_map},
+    octo::assemble_octo_file,
+    output::{c_array, cartridge, identifier, intel_hex, octo_source, rust_array, OutputFormat},
+};
+use rchip8::machine::DEFAULT_START;
+use std::fs::File;
+use std::io::{BufReader, Read};
 
 fn load_file(filename: &str) -> std::io::Result<String> {
     let f = File::open(filename)?;
@@ -28,50 +33,200 @@ fn load_file(filename: &str) -> std::io::Result<String> {
     Ok(buf)
 }
 
-fn parse(input: &str) -> Result<Vec<u8>, ParseError<usize, Token, &str>> {
-    let parser = ProgramParser::new();
-    let code = parser.parse(input)?;
-    Ok(process_prog(code))
+/// An assembled ROM, the address it's loaded at and the Octo source of the program if
+/// it's to be written as a cartridge.
+type Output = (Vec<u8>, usize, Option<String>);
+
+/// Write a ROM in a format, naming arrays after the source file.
+fn emit_code(filename: &str, format: OutputFormat, source: &str, output: Output) {
+    let (rom, start, octo) = output;
+    let bytes = match format {
+        OutputFormat::Raw => rom,
+        OutputFormat::Hex => intel_hex(&rom, start).into_bytes(),
+        OutputFormat::C => c_array(&rom, &identifier(source)).into_bytes(),
+        OutputFormat::Rust => rust_array(&rom, &identifier(source)).into_bytes(),
+        OutputFormat::Octo if start != DEFAULT_START => {
+            println!("Octo cartridges can only hold programs that start at 200");
+            std::process::exit(1);
+        }
+        OutputFormat::Octo => cartridge(&octo.unwrap_or_default()),
+    };
+    if let Err(e) = std::fs::write(filename, bytes) {
+        println!("Couldn't emit code: {:?}", e);
+        std::process::exit(1);
+    }
 }
 
-fn emit_code(filename: &str, code: Vec<u8>) -> std::io::Result<()> {
-    let f = OpenOptions::new().write(true).create(true).open(filename)?;
-    let mut w = BufWriter::new(f);
-    for c in code {
-        let c1 = c.to_be_bytes();
-        w.write_all(&c1)?;
+/// Print every error found in the source and exit.
+fn abort(sources: &Sources, diags: &[Diagnostic]) -> ! {
+    for diag in diags {
+        eprint!("{}", sources.render(diag));
+    }
+    eprintln!(
+        "aborting due to {} error{}",
+        diags.len(),
+        if diags.len() == 1 { "" } else { "s" }
+    );
+    std::process::exit(2);
+}
+
+fn write_or_exit(filename: &str, text: String) {
+    if let Err(e) = std::fs::write(filename, text) {
+        println!("Couldn't write {}: {:?}", filename, e);
+        std::process::exit(1);
     }
-    w.flush()
 }
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct CompileArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Assembly file to compile
-    file: String,
+    #[arg(required = true)]
+    file: Option<String>,
     /// Name of ROM file to generate
     #[arg(short, default_value = "a.out")]
     outfile: String,
+    /// Write the ROM as raw bytes, Intel HEX, a C or Rust array, or an Octo cartridge GIF
+    #[arg(long, value_name = "FORMAT", default_value = "raw")]
+    format: OutputFormat,
+    /// Read Octo syntax instead of c8asm (the default for .8o files)
+    #[arg(long)]
+    octo: bool,
+    /// Write an object file for c8ld to link with others instead of a ROM
+    #[arg(short = 'c', conflicts_with_all = ["format", "listing", "symbols", "debug_info"])]
+    object: bool,
+    /// Write a listing of each address and its bytes beside the source to FILE
+    #[arg(long, value_name = "FILE")]
+    listing: Option<String>,
+    /// Write the address of every label to FILE, in the form rchip8 --symbols reads
+    #[arg(long, value_name = "FILE")]
+    symbols: Option<String>,
+    /// Write the source line of each address and every label to FILE, for debugging the
+    /// ROM with rchip8 --debug-info
+    #[arg(long, value_name = "FILE")]
+    debug_info: Option<String>,
+    /// Don't check for LINT (may be repeated)
+    #[arg(short = 'A', long, value_name = "LINT")]
+    allow: Vec<Lint>,
+    /// Make LINT an error instead of a warning (may be repeated)
+    #[arg(short = 'D', long, value_name = "LINT")]
+    deny: Vec<Lint>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lay out c8asm source in columns, rewriting each file in place
+    Fmt {
+        /// Source files to format
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Change nothing, but list the files that aren't formatted and exit with status 1
+        /// if there are any
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+fn run_fmt(files: &[String], check: bool) {
+    let mut unformatted = false;
+    for file in files {
+        let text = load_file(file).unwrap_or_else(|e| {
+            println!("Couldn't load {}: {:?}", file, e);
+            std::process::exit(1);
+        });
+        let formatted = format(&text);
+        if formatted == text {
+            continue;
+        }
+        if check {
+            // Point at the first line that would change.
+            let line = text
+                .lines()
+                .zip(formatted.lines())
+                .take_while(|(a, b)| a == b)
+                .count();
+            println!("{}:{}: not formatted", file, line + 1);
+            unformatted = true;
+        } else {
+            write_or_exit(file, formatted);
+        }
+    }
+    if unformatted {
+        std::process::exit(1);
+    }
 }
 
 fn main() {
     let args = CompileArgs::parse();
-    match load_file(&args.file) {
+    if let Some(Command::Fmt { files, check }) = &args.command {
+        run_fmt(files, *check);
+        return;
+    }
+    let filename = args.file.as_deref().unwrap();
+    let text = match load_file(filename) {
+        Ok(text) => text,
         Err(e) => {
             println!("Couldn't load file: {:?}", e);
             std::process::exit(1);
         }
-        Ok(text) => match parse(&text) {
-            Err(e) => {
-                println!("Failed to parse: {:?}", e);
-                std::process::exit(2);
+    };
+    let mut sources = Sources::new();
+    let file = sources.add(filename, &text);
+    let mut lints = Lints::new();
+    args.allow.iter().for_each(|lint| lints.allow(*lint));
+    args.deny.iter().for_each(|lint| lints.deny(*lint));
+    let octo = args.octo || filename.ends_with(".8o");
+    if octo
+        && (args.listing.is_some()
+            || args.symbols.is_some()
+            || args.debug_info.is_some()
+            || args.object)
+    {
+        println!(
+            "Listings, symbol maps, debug info and object files can only be made from c8asm \
+             source"
+        );
+        std::process::exit(1);
+    }
+    if args.object {
+        match assemble_object(&mut sources, file, &|path| std::fs::read(path)) {
+            Ok(object) => write_or_exit(&args.outfile, object.to_string()),
+            Err(diags) => abort(&sources, &diags),
+        }
+        return;
+    }
+    let cart = args.format == OutputFormat::Octo;
+    let result: Result<Output, _> = if octo {
+        assemble_octo_file(&text, file).map(|rom| (rom, DEFAULT_START, Some(text.clone())))
+    } else {
+        assemble_sources(&mut sources, file, &|path| std::fs::read(path)).and_then(|assembled| {
+            let (errors, warnings): (Vec<_>, Vec<_>) = lints
+                .check(&assembled)
+                .into_iter()
+                .partition(|diag| diag.severity == Severity::Error);
+            for diag in &warnings {
+                eprint!("{}", sources.render(diag));
+            }
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+            if let Some(path) = &args.listing {
+                write_or_exit(path, listing(&assembled, &sources));
+            }
+            if let Some(path) = &args.symbols {
+                write_or_exit(path, symbol_map(&assembled));
+            }
+            if let Some(path) = &args.debug_info {
+                write_or_exit(path, debug_info(&assembled, &sources).to_string());
             }
-            Ok(code) => match emit_code(&args.outfile, code) {
-                Err(e) => {
-                    println!("Couldn't emit code: {:?}", e);
-                    std::process::exit(1);
-                }
-                Ok(_) => (),
-            },
-        },
+            let octo = cart.then(|| octo_source(&assembled));
+            Ok((assembled.rom, assembled.start, octo))
+        })
+    };
+    match result {
+        Err(diags) => abort(&sources, &diags),
+        Ok(output) => emit_code(&args.outfile, args.format, filename, output),
     }
 }
@@ -0,0 +1,64 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use clap::Parser;
+use rchip8::c8asc::object::{link, Object};
+
+#[derive(Parser)]
+struct LinkArgs {
+    /// Object files written by c8asc -c, placed in the ROM in the order given
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// Name of ROM file to generate
+    #[arg(short, default_value = "a.out")]
+    outfile: String,
+}
+
+fn load_object_or_exit(filename: &str) -> Object {
+    let text = std::fs::read_to_string(filename).unwrap_or_else(|e| {
+        println!("Couldn't load {}: {:?}", filename, e);
+        std::process::exit(1);
+    });
+    Object::parse(&text).unwrap_or_else(|e| {
+        println!("{} isn't a valid object file: {}", filename, e);
+        std::process::exit(1);
+    })
+}
+
+fn main() {
+    let args = LinkArgs::parse();
+    let objects: Vec<(String, Object)> = args
+        .files
+        .iter()
+        .map(|file| (file.clone(), load_object_or_exit(file)))
+        .collect();
+    match link(&objects) {
+        Ok(rom) => {
+            if let Err(e) = std::fs::write(&args.outfile, rom) {
+                println!("Couldn't write {}: {:?}", args.outfile, e);
+                std::process::exit(1);
+            }
+        }
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("error: {}", e);
+            }
+            eprintln!(
+                "aborting due to {} error{}",
+                errors.len(),
+                if errors.len() == 1 { "" } else { "s" }
+            );
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, Span};
+use std::collections::HashMap;
+
+/// The byte each character of a `text` string is stored as.
+#[derive(Debug, Default)]
+pub struct Charmap {
+    /// The characters set by the last `charmap`, or `None` for ASCII.
+    map: Option<HashMap<char, u8>>,
+}
+
+impl Charmap {
+    pub fn new() -> Charmap {
+        Charmap::default()
+    }
+
+    /// Replace the map with one storing each character of `chars` as `first` plus its
+    /// position. `span` covers the string of characters.
+    pub fn set(&mut self, chars: &str, first: u8, span: Span) -> Result<(), Diagnostic> {
+        if first as usize + chars.chars().count() > 0x100 {
+            return Err(Diagnostic::error(
+                String::from("the character map runs past #ff"),
+                Some(span),
+            ));
+        }
+        self.map = Some(chars.chars().zip(first..=0xff).collect());
+        Ok(())
+    }
+
+    /// Encode the text of a string literal at `span`, which includes its quotes.
+    pub fn encode(&self, text: &str, span: Span) -> Result<Vec<u8>, Diagnostic> {
+        text.char_indices()
+            .map(|(pos, c)| {
+                let byte = match &self.map {
+                    Some(map) => map.get(&c).copied(),
+                    None if c.is_ascii() => Some(c as u8),
+                    None => None,
+                };
+                byte.ok_or_else(|| {
+                    let start = span.start + 1 + pos;
+                    Diagnostic::error(
+                        format!("`{}` isn't in the character map", c),
+                        Some(Span::new(span.file, start, start + c.len_utf8())),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod charmap_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_ascii() {
+        let charmap = Charmap::new();
+        assert_eq!(Ok(vec![0x48, 0x69]), charmap.encode("Hi", Span::default()));
+    }
+
+    #[rstest]
+    fn test_set() {
+        let mut charmap = Charmap::new();
+        charmap.set("0123456789ABCDEF", 0, Span::default()).unwrap();
+        assert_eq!(
+            Ok(vec![0x1, 0x9, 0xa]),
+            charmap.encode("19A", Span::default())
+        );
+        charmap.set(" AB", 0x20, Span::default()).unwrap();
+        assert_eq!(Ok(vec![0x21, 0x20]), charmap.encode("A ", Span::default()));
+    }
+
+    #[rstest]
+    fn test_missing_character() {
+        let mut charmap = Charmap::new();
+        charmap.set("AB", 0, Span::default()).unwrap();
+        let err = charmap.encode("ABC", Span::new(0, 10, 15)).unwrap_err();
+        assert_eq!("`C` isn't in the character map", err.message);
+        assert_eq!(Some(Span::new(0, 13, 14)), err.span);
+    }
+
+    #[rstest]
+    fn test_not_ascii() {
+        let err = Charmap::new().encode("é", Span::default()).unwrap_err();
+        assert_eq!("`é` isn't in the character map", err.message);
+    }
+
+    #[rstest]
+    fn test_too_long() {
+        let err = Charmap::new()
+            .set("ABC", 0xfe, Span::default())
+            .unwrap_err();
+        assert_eq!("the character map runs past #ff", err.message);
+    }
+}
@@ -0,0 +1,390 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use lalrpop_util::{lexer::Token, ParseError};
+use std::fmt::{self, Write};
+
+/// Index of a file in `Sources`.
+pub type FileId = usize;
+
+/// A range of bytes in a source file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize) -> Span {
+        Span { file, start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in a program, pointing at where in the source it was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Tokens that would have been accepted, for syntax errors.
+    pub expected: Vec<String>,
+    /// Further messages pointing at related places in the source.
+    pub notes: Vec<(Option<Span>, String)>,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            span,
+            expected: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: String, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message, span)
+        }
+    }
+
+    pub fn with_note(mut self, span: Option<Span>, message: String) -> Diagnostic {
+        self.notes.push((span, message));
+        self
+    }
+
+    /// Convert a parser error in `file` into a diagnostic.
+    pub fn from_parse_error(err: ParseError<usize, Token<'_>, &str>, file: FileId) -> Diagnostic {
+        let (message, span, expected) = match err {
+            ParseError::InvalidToken { location } => (
+                String::from("invalid token"),
+                Span::new(file, location, location + 1),
+                Vec::new(),
+            ),
+            ParseError::UnrecognizedEOF { location, expected } => (
+                String::from("unexpected end of file"),
+                Span::new(file, location, location),
+                expected,
+            ),
+            ParseError::UnrecognizedToken {
+                token: (l, tok, r),
+                expected,
+            } => (
+                format!("unexpected {}", describe_found(tok.1)),
+                Span::new(file, l, r),
+                expected,
+            ),
+            ParseError::ExtraToken { token: (l, tok, r) } => (
+                format!("extra token {}", describe_found(tok.1)),
+                Span::new(file, l, r),
+                Vec::new(),
+            ),
+            ParseError::User { error } => (String::from(error), Span::new(file, 0, 0), Vec::new()),
+        };
+        let mut expected: Vec<String> = expected.iter().map(|e| describe_token(e)).collect();
+        expected.sort();
+        expected.dedup();
+        Diagnostic {
+            expected,
+            ..Diagnostic::error(message, Some(span))
+        }
+    }
+
+    /// Convert a character the parser couldn't read, at `location` in the `text` of `file`,
+    /// into a diagnostic. A word at the start of a line, after any label, can only be a
+    /// mistyped instruction or an undefined macro, so the whole word is reported.
+    pub fn invalid_token(text: &str, location: usize, file: FileId) -> Diagnostic {
+        let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        let start = text[..location].rfind(|c| !is_word(c)).map_or(0, |i| i + 1);
+        let end = text[location..]
+            .find(|c| !is_word(c))
+            .map_or(text.len(), |i| location + i);
+        let line = &text[text[..start].rfind('\n').map_or(0, |i| i + 1)..start];
+        let mut before = line.split_whitespace();
+        let leading = match (before.next(), before.next()) {
+            (None, _) => true,
+            (Some(label), None) => label.starts_with('$') && line.ends_with(char::is_whitespace),
+            _ => false,
+        };
+        let word = &text[start..end];
+        if leading && location < end && word.starts_with(|c: char| !c.is_ascii_digit()) {
+            Diagnostic::error(
+                format!("unknown instruction or macro `{}`", word),
+                Some(Span::new(file, start, end)),
+            )
+        } else {
+            Diagnostic::from_parse_error(ParseError::InvalidToken { location }, file)
+        }
+    }
+}
+
+/// Describe the text of a token the parser didn't expect.
+fn describe_found(text: &str) -> String {
+    match text {
+        "\n" => String::from("end of line"),
+        _ => format!("`{}`", text),
+    }
+}
+
+/// Turn a terminal name reported by the parser into something readable.
+fn describe_token(name: &str) -> String {
+    if let Some(literal) = name.strip_prefix('"').and_then(|n| n.strip_suffix('"')) {
+        return format!("`{}`", literal.replace("\\\"", "\""));
+    }
+    let described = if name.starts_with("r#\"\\\"") {
+        "string"
+    } else if name.contains("[.X]") {
+        "sprite row"
+    } else if name.contains("V[") {
+        "register"
+    } else if name.contains("\\$") {
+        "label"
+    } else if name == "Newline" {
+        "end of line"
+    } else if name == "HexDigits" {
+        "hex bytes"
+    } else if name.contains("\"#[") || name.contains("0x[") || name.ends_with("Num") {
+        "number"
+    } else if name.contains("'[") {
+        "character"
+    } else {
+        name
+    };
+    String::from(described)
+}
+
+/// A source file and where its lines start.
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, text: &str) -> SourceFile {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            name: String::from(name),
+            text: String::from(text),
+            line_starts,
+        }
+    }
+
+    /// The 1-based line and column of a byte offset.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|s| *s <= offset) - 1;
+        let start = self.line_starts[line];
+        let offset = offset.min(self.text.len());
+        (line + 1, self.text[start..offset].chars().count() + 1)
+    }
+
+    /// The text of a 1-based line, without its line ending.
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// Longest list of expected tokens worth showing; past this the list is mostly every
+/// mnemonic and says little about the mistake.
+const MAX_EXPECTED: usize = 8;
+
+/// All the files making up a program.
+#[derive(Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources::default()
+    }
+
+    pub fn add(&mut self, name: &str, text: &str) -> FileId {
+        self.files.push(SourceFile::new(name, text));
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+
+    /// Format a diagnostic with the source lines it points at.
+    ///
+    /// ```text
+    /// prog.c8asm:3:18: error: undefined label: $foo
+    ///     3 |          jmp     $foo
+    ///       |                  ^^^^
+    /// ```
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        let mut out = String::new();
+        self.render_message(
+            &mut out,
+            diag.span,
+            &diag.severity.to_string(),
+            &diag.message,
+        );
+        if !diag.expected.is_empty() && diag.expected.len() <= MAX_EXPECTED {
+            writeln!(out, "      = expected one of: {}", diag.expected.join(", ")).unwrap();
+        }
+        for (span, note) in &diag.notes {
+            self.render_message(&mut out, *span, "note", note);
+        }
+        out
+    }
+
+    fn render_message(&self, out: &mut String, span: Option<Span>, kind: &str, message: &str) {
+        let span = match span {
+            Some(span) if span.file < self.files.len() => span,
+            _ => {
+                writeln!(out, "{}: {}", kind, message).unwrap();
+                return;
+            }
+        };
+        let file = &self.files[span.file];
+        let (line, col) = file.line_col(span.start);
+        writeln!(out, "{}:{}:{}: {}: {}", file.name, line, col, kind, message).unwrap();
+
+        let text = file.line(line);
+        let (end_line, end_col) = file.line_col(span.end);
+        let width = if end_line == line && end_col > col {
+            end_col - col
+        } else {
+            1
+        };
+        // Copy tabs from the source so the caret lines up however tabs are displayed
+        let indent: String = text
+            .chars()
+            .take(col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(out, "{:>5} | {}", line, text).unwrap();
+        writeln!(out, "      | {}{}", indent, "^".repeat(width)).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod diagnostic_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case::word("foo V1, 2", 1, ("unknown instruction or macro `foo`", 0, 3))]
+    #[case::indented("clr\n  movx V1, 2\n", 9, ("unknown instruction or macro `movx`", 6, 10))]
+    #[case::labelled("$x bar\n", 5, ("unknown instruction or macro `bar`", 3, 6))]
+    #[case::operand("mov V1, @\n", 8, ("invalid token", 8, 9))]
+    #[case::second_word("mov foo\n", 5, ("invalid token", 5, 6))]
+    fn test_invalid_token(
+        #[case] text: &str,
+        #[case] location: usize,
+        #[case] expected: (&str, usize, usize),
+    ) {
+        let diag = Diagnostic::invalid_token(text, location, 0);
+        assert_eq!(expected.0, diag.message);
+        assert_eq!(Some(Span::new(0, expected.1, expected.2)), diag.span);
+    }
+
+    #[rstest]
+    #[case(0, (1, 1))]
+    #[case(4, (1, 5))]
+    #[case(5, (2, 1))]
+    #[case(7, (2, 3))]
+    #[case(9, (3, 1))]
+    fn test_line_col(#[case] offset: usize, #[case] expected: (usize, usize)) {
+        let f = SourceFile::new("a", "abcd\nefg\n");
+        assert_eq!(expected, f.line_col(offset));
+    }
+
+    #[rstest]
+    fn test_render() {
+        let mut sources = Sources::new();
+        let file = sources.add("prog.c8asm", "$a clr\n\tjmp $b\n");
+        let diag = Diagnostic::error(
+            String::from("undefined label: $b"),
+            Some(Span::new(file, 12, 14)),
+        )
+        .with_note(
+            Some(Span::new(file, 0, 2)),
+            String::from("did you mean $a?"),
+        );
+        let expected = "\
+prog.c8asm:2:6: error: undefined label: $b
+    2 | \tjmp $b
+      | \t    ^^
+prog.c8asm:1:1: note: did you mean $a?
+    1 | $a clr
+      | ^^
+";
+        assert_eq!(expected, sources.render(&diag));
+    }
+
+    #[rstest]
+    fn test_render_expected() {
+        let mut sources = Sources::new();
+        let file = sources.add("p", "mov V1");
+        let mut diag = Diagnostic::error(
+            String::from("unexpected end of file"),
+            Some(Span::new(file, 6, 6)),
+        );
+        diag.expected = vec![String::from("`,`")];
+        let expected = "\
+p:1:7: error: unexpected end of file
+    1 | mov V1
+      |       ^
+      = expected one of: `,`
+";
+        assert_eq!(expected, sources.render(&diag));
+    }
+
+    #[rstest]
+    #[case("\"clr\"", "`clr`")]
+    #[case("\",\"", "`,`")]
+    #[case("r#\"V[0-9a-fA-F]\"#", "register")]
+    #[case("r#\"\\$[a-zA-Z][a-zA-Z0-9_]*\"#", "label")]
+    #[case("r#\"#[0-9a-fA-F]+\"#", "number")]
+    #[case("r#\"0x[0-9a-fA-F]+\"#", "number")]
+    #[case("DecNum", "number")]
+    #[case("HexDigits", "hex bytes")]
+    #[case("r#\"'[^']'\"#", "character")]
+    #[case("Newline", "end of line")]
+    #[case("r#\"\\\"[^\\\"\\\\n]*\\\"\"#", "string")]
+    #[case("r#\"[.X]+\"#", "sprite row")]
+    fn test_describe_token(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(expected, describe_token(name));
+    }
+}
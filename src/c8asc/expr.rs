@@ -0,0 +1,421 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, Span};
+use super::Label;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    And,
+    Or,
+    Shl,
+    Shr,
+}
+
+impl BinOp {
+    fn apply(self, l: i64, r: i64) -> Option<i64> {
+        match self {
+            BinOp::Add => l.checked_add(r),
+            BinOp::Sub => l.checked_sub(r),
+            BinOp::Mul => l.checked_mul(r),
+            BinOp::Div => l.checked_div(r),
+            BinOp::And => Some(l & r),
+            BinOp::Or => Some(l | r),
+            BinOp::Shl => u32::try_from(r).ok().and_then(|r| l.checked_shl(r)),
+            BinOp::Shr => u32::try_from(r).ok().and_then(|r| l.checked_shr(r)),
+        }
+    }
+}
+
+/// A constant expression, evaluated once the address of every label is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(i64),
+    Name(Label),
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(op: BinOp, l: Expr, r: Expr) -> Expr {
+        Expr::Binary(op, Box::new(l), Box::new(r))
+    }
+
+    /// Add every label and constant named in the expression to `names`.
+    pub fn names<'a>(&'a self, names: &mut Vec<&'a Label>) {
+        match self {
+            Expr::Num(_) => (),
+            Expr::Name(lbl) => names.push(lbl),
+            Expr::Neg(e) => e.names(names),
+            Expr::Binary(_, l, r) => {
+                l.names(names);
+                r.names(names);
+            }
+        }
+    }
+
+    /// Evaluate the expression, which covers `span` of the source.
+    pub fn eval(&self, symbols: &Symbols, span: Span) -> Result<i64, Diagnostic> {
+        self.eval_within(symbols, span, &mut Vec::new())
+    }
+
+    /// Evaluate the expression while working out the value of each constant in `outer`.
+    fn eval_within<'a>(
+        &'a self,
+        symbols: &Symbols<'a>,
+        span: Span,
+        outer: &mut Vec<&'a str>,
+    ) -> Result<i64, Diagnostic> {
+        match self {
+            Expr::Num(n) => Ok(*n),
+            Expr::Name(lbl) => match symbols.defs.get(lbl.name.as_str()) {
+                None => Err(Diagnostic::error(
+                    format!("undefined label: {}", lbl.name),
+                    Some(lbl.span),
+                )),
+                Some(Symbol::Address(addr)) => Ok(*addr as i64),
+                // The linker adds the address of the label to the value.
+                Some(Symbol::External) => Ok(0),
+                Some(Symbol::Unplaced) => Err(Diagnostic::error(
+                    format!("the address of {} isn't known yet at this point", lbl.name),
+                    Some(lbl.span),
+                )),
+                Some(Symbol::Const(_, _)) if outer.contains(&lbl.name.as_str()) => {
+                    Err(Diagnostic::error(
+                        format!("{} is defined in terms of itself", lbl.name),
+                        Some(lbl.span),
+                    ))
+                }
+                Some(Symbol::Const(expr, expr_span)) => {
+                    outer.push(&lbl.name);
+                    let value = expr.eval_within(symbols, *expr_span, outer);
+                    outer.pop();
+                    value
+                }
+            },
+            Expr::Neg(e) => e
+                .eval_within(symbols, span, outer)?
+                .checked_neg()
+                .ok_or_else(|| overflow(span)),
+            Expr::Binary(op, l, r) => {
+                let l = l.eval_within(symbols, span, outer)?;
+                let r = r.eval_within(symbols, span, outer)?;
+                match (op, r) {
+                    (BinOp::Div, 0) => Err(Diagnostic::error(
+                        String::from("division by zero"),
+                        Some(span),
+                    )),
+                    _ => op.apply(l, r).ok_or_else(|| overflow(span)),
+                }
+            }
+        }
+    }
+
+    /// Work out what the value of the expression, which covers `span` of the source, is
+    /// relative to in an object file. The linker can only move a value by adding an
+    /// address to it, so at most one label can be added, and only a number added to or
+    /// taken from that.
+    pub fn base<'a>(&'a self, symbols: &Symbols<'a>, span: Span) -> Result<Base<'a>, Diagnostic> {
+        self.base_within(symbols, span, &mut Vec::new())
+    }
+
+    fn base_within<'a>(
+        &'a self,
+        symbols: &Symbols<'a>,
+        span: Span,
+        outer: &mut Vec<&'a str>,
+    ) -> Result<Base<'a>, Diagnostic> {
+        match self {
+            Expr::Num(_) => Ok(Base::Absolute),
+            Expr::Name(lbl) => match symbols.defs.get(lbl.name.as_str()) {
+                Some(Symbol::Address(_)) => Ok(Base::Module),
+                Some(Symbol::External) => Ok(Base::External(&lbl.name)),
+                Some(Symbol::Const(expr, expr_span)) if !outer.contains(&lbl.name.as_str()) => {
+                    outer.push(&lbl.name);
+                    let base = expr.base_within(symbols, *expr_span, outer);
+                    outer.pop();
+                    base
+                }
+                // Evaluating the expression reports anything else.
+                _ => Ok(Base::Absolute),
+            },
+            Expr::Neg(e) => match e.base_within(symbols, span, outer)? {
+                Base::Absolute => Ok(Base::Absolute),
+                _ => Err(not_relocatable(span)),
+            },
+            Expr::Binary(op, l, r) => {
+                let l = l.base_within(symbols, span, outer)?;
+                let r = r.base_within(symbols, span, outer)?;
+                match (op, l, r) {
+                    (_, Base::Absolute, Base::Absolute) => Ok(Base::Absolute),
+                    (BinOp::Add, base, Base::Absolute)
+                    | (BinOp::Add, Base::Absolute, base)
+                    | (BinOp::Sub, base, Base::Absolute) => Ok(base),
+                    // The distance between two labels moved by the same amount is fixed.
+                    (BinOp::Sub, l, r) if l == r => Ok(Base::Absolute),
+                    _ => Err(not_relocatable(span)),
+                }
+            }
+        }
+    }
+}
+
+fn not_relocatable(span: Span) -> Diagnostic {
+    Diagnostic::error(
+        String::from("the linker can only relocate an address plus or minus a number"),
+        Some(span),
+    )
+}
+
+fn overflow(span: Span) -> Diagnostic {
+    Diagnostic::error(String::from("arithmetic overflow"), Some(span))
+}
+
+/// Write a value the way c8asm literals are written.
+fn hex(value: i64) -> String {
+    if value < 0 {
+        format!("-#{:x}", value.unsigned_abs())
+    } else {
+        format!("#{:x}", value)
+    }
+}
+
+/// The value a name stands for.
+pub enum Symbol<'a> {
+    Address(u16),
+    /// A label whose address is still being worked out.
+    Unplaced,
+    /// An `equ` constant, with the span of its expression.
+    Const(&'a Expr, Span),
+    /// A label defined in another module, whose address the linker fills in.
+    External,
+}
+
+/// What a value in an object file is relative to, and so what the linker adds to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base<'a> {
+    /// A number, which stays as it is.
+    Absolute,
+    /// An address in the module, moved by wherever the module is placed.
+    Module,
+    /// An offset from the address of a label in another module.
+    External(&'a str),
+}
+
+/// Every label and constant defined in a program.
+#[derive(Default)]
+pub struct Symbols<'a> {
+    defs: HashMap<&'a str, Symbol<'a>>,
+}
+
+impl<'a> Symbols<'a> {
+    pub fn new() -> Symbols<'a> {
+        Symbols::default()
+    }
+
+    pub fn define(&mut self, name: &'a str, symbol: Symbol<'a>) {
+        self.defs.insert(name, symbol);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol<'a>> {
+        self.defs.get(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+}
+
+/// A numeric operand filling the low `bits` bits of an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operand {
+    pub expr: Expr,
+    pub span: Span,
+    pub bits: u32,
+}
+
+impl Operand {
+    pub fn new(expr: Expr, span: Span, bits: u32) -> Operand {
+        Operand { expr, span, bits }
+    }
+
+    /// Work out what the operand is relative to in an object file.
+    pub fn base<'a>(&'a self, symbols: &Symbols<'a>) -> Result<Base<'a>, Diagnostic> {
+        self.expr.base(symbols, self.span)
+    }
+
+    /// Evaluate the operand and check it fits its field.
+    ///
+    /// Negative values are accepted for data operands and stored in two's complement, so
+    /// `add V0, -1` subtracts one, but addresses must lie within memory.
+    pub fn value(&self, symbols: &Symbols) -> Result<u16, Diagnostic> {
+        let value = self.expr.eval(symbols, self.span)?;
+        let max = (1 << self.bits) - 1;
+        let min = if self.bits == 12 {
+            0
+        } else {
+            -(1 << (self.bits - 1))
+        };
+        let bound = if value > max {
+            format!("the most is {}", hex(max))
+        } else if value < min {
+            format!("the least is {}", hex(min))
+        } else {
+            return Ok((value & max) as u16);
+        };
+        Err(Diagnostic::error(
+            format!(
+                "{} doesn't fit in {} bits ({})",
+                hex(value),
+                self.bits,
+                bound
+            ),
+            Some(self.span),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+    use rstest::*;
+
+    fn name(name: &str) -> Expr {
+        Expr::Name(Label {
+            name: String::from(name),
+            span: Span::new(0, 1, 2),
+        })
+    }
+
+    fn num(n: i64) -> Expr {
+        Expr::Num(n)
+    }
+
+    fn eval(expr: &Expr, symbols: &Symbols) -> Result<i64, String> {
+        expr.eval(symbols, Span::default()).map_err(|d| d.message)
+    }
+
+    #[rstest]
+    #[case(Expr::binary(BinOp::Add, num(2), num(3)), 5)]
+    #[case(Expr::binary(BinOp::Sub, num(2), num(3)), -1)]
+    #[case(Expr::binary(BinOp::Mul, num(2), num(3)), 6)]
+    #[case(Expr::binary(BinOp::Div, num(7), num(2)), 3)]
+    #[case(Expr::binary(BinOp::And, num(0b1100), num(0b1010)), 0b1000)]
+    #[case(Expr::binary(BinOp::Or, num(0b1100), num(0b1010)), 0b1110)]
+    #[case(Expr::binary(BinOp::Shl, num(1), num(4)), 16)]
+    #[case(Expr::binary(BinOp::Shr, num(0x300), num(8)), 3)]
+    #[case(Expr::Neg(Box::new(num(4))), -4)]
+    fn test_eval(#[case] expr: Expr, #[case] expected: i64) {
+        assert_eq!(Ok(expected), eval(&expr, &Symbols::new()));
+    }
+
+    #[rstest]
+    #[case(Expr::binary(BinOp::Div, num(1), num(0)), "division by zero")]
+    #[case(Expr::binary(BinOp::Shl, num(1), num(-1)), "arithmetic overflow")]
+    #[case(Expr::binary(BinOp::Mul, num(i64::MAX), num(2)), "arithmetic overflow")]
+    #[case(name("$x"), "undefined label: $x")]
+    fn test_eval_errors(#[case] expr: Expr, #[case] expected: &str) {
+        assert_eq!(Err(String::from(expected)), eval(&expr, &Symbols::new()));
+    }
+
+    #[rstest]
+    fn test_symbols() {
+        let width = Expr::binary(BinOp::Add, name("$base"), num(8));
+        let mut symbols = Symbols::new();
+        symbols.define("$base", Symbol::Address(0x300));
+        symbols.define("$end", Symbol::Const(&width, Span::default()));
+        assert_eq!(Ok(0x308), eval(&name("$end"), &symbols));
+    }
+
+    #[rstest]
+    fn test_unplaced_label() {
+        let mut symbols = Symbols::new();
+        symbols.define("$later", Symbol::Unplaced);
+        assert_eq!(
+            Err(String::from(
+                "the address of $later isn't known yet at this point"
+            )),
+            eval(&name("$later"), &symbols)
+        );
+    }
+
+    #[rstest]
+    fn test_cyclic_constants() {
+        let a = name("$b");
+        let b = Expr::binary(BinOp::Add, name("$a"), num(1));
+        let mut symbols = Symbols::new();
+        symbols.define("$a", Symbol::Const(&a, Span::default()));
+        symbols.define("$b", Symbol::Const(&b, Span::default()));
+        assert_eq!(
+            Err(String::from("$a is defined in terms of itself")),
+            eval(&name("$a"), &symbols)
+        );
+    }
+
+    #[rstest]
+    #[case::number(num(3), Ok(Base::Absolute))]
+    #[case::label(name("$here"), Ok(Base::Module))]
+    #[case::external(
+        Expr::binary(BinOp::Sub, name("$ext"), num(2)),
+        Ok(Base::External("$ext"))
+    )]
+    #[case::via_const(name("$size"), Ok(Base::External("$ext")))]
+    #[case::distance(
+        Expr::binary(BinOp::Sub, name("$here"), name("$here")),
+        Ok(Base::Absolute)
+    )]
+    #[case::mixed(
+        Expr::binary(BinOp::Sub, name("$ext"), name("$here")),
+        Err("the linker can only relocate an address plus or minus a number")
+    )]
+    #[case::negated(
+        Expr::Neg(Box::new(name("$here"))),
+        Err("the linker can only relocate an address plus or minus a number")
+    )]
+    fn test_base(#[case] expr: Expr, #[case] expected: Result<Base, &str>) {
+        let size = Expr::binary(BinOp::Add, name("$ext"), num(4));
+        let mut symbols = Symbols::new();
+        symbols.define("$here", Symbol::Address(0x204));
+        symbols.define("$ext", Symbol::External);
+        symbols.define("$size", Symbol::Const(&size, Span::default()));
+        let base = expr.base(&symbols, Span::default()).map_err(|d| d.message);
+        assert_eq!(expected.map_err(String::from), base);
+    }
+
+    #[rstest]
+    #[case(4, 15, Ok(0xf))]
+    #[case(4, -1, Ok(0xf))]
+    #[case(8, -128, Ok(0x80))]
+    #[case(12, 0xfff, Ok(0xfff))]
+    #[case(8, 256, Err("#100 doesn't fit in 8 bits (the most is #ff)"))]
+    #[case(8, -129, Err("-#81 doesn't fit in 8 bits (the least is -#80)"))]
+    #[case(12, -1, Err("-#1 doesn't fit in 12 bits (the least is #0)"))]
+    fn test_operand_range(
+        #[case] bits: u32,
+        #[case] value: i64,
+        #[case] expected: Result<u16, &str>,
+    ) {
+        let operand = Operand::new(num(value), Span::default(), bits);
+        let result = operand.value(&Symbols::new()).map_err(|d| d.message);
+        assert_eq!(expected.map_err(String::from), result);
+    }
+}
@@ -0,0 +1,256 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::preprocess::comment_start;
+
+/// Width of the column labels are written in.
+const LABEL_WIDTH: usize = 10;
+/// Width of the column mnemonics are written in, after the labels.
+const MNEMONIC_WIDTH: usize = 9;
+/// Column trailing comments start at, unless the code runs past it.
+const COMMENT_COLUMN: usize = 35;
+
+/// Lay out c8asm source in columns of labels, mnemonics, operands and comments, with
+/// registers and hex digits in lower case after their `V` or `#`.
+///
+/// ```text
+/// $start    input    V0              ; n
+///           mov      Va, #1f
+/// ```
+///
+/// Only whitespace and the case of registers and hex digits change, so the source
+/// assembles to the same bytes. Each line is formatted on its own, without being parsed,
+/// so source with errors is formatted as far as it can be.
+pub fn format(source: &str) -> String {
+    let mut out = String::new();
+    let mut sprite_rows = false;
+    for line in source.lines() {
+        let line = line.trim_end();
+        let (code, comment) = line.split_at(comment_start(line));
+        let code = code.trim();
+        let mut formatted = if code.is_empty() {
+            // Indented comments on lines of their own line up with the mnemonics.
+            let indent = if line.starts_with(char::is_whitespace) {
+                LABEL_WIDTH
+            } else {
+                0
+            };
+            format!("{:indent$}", "")
+        } else if sprite_rows {
+            // More sprite rows after a line ending in `/` line up with the rows above.
+            format!(
+                "{:indent$}{}",
+                "",
+                operands(code, false),
+                indent = LABEL_WIDTH + MNEMONIC_WIDTH
+            )
+        } else {
+            statement(code)
+        };
+        if !code.is_empty() {
+            sprite_rows = code.ends_with('/');
+        }
+        if !comment.is_empty() {
+            if !code.is_empty() {
+                formatted = pad(&formatted, COMMENT_COLUMN);
+            }
+            formatted.push_str(comment);
+        }
+        out.push_str(&formatted);
+        out.push('\n');
+    }
+    let end = out.trim_end().len();
+    out.truncate(end);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Format the code of a line holding a statement, or the start or end of a macro.
+fn statement(code: &str) -> String {
+    let (first, rest) = split_word(code);
+    match first {
+        "macro" => {
+            let (name, params) = split_word(rest);
+            let line = format!("macro {} {}", name, operands(params, false));
+            return String::from(line.trim_end());
+        }
+        "endm" => return String::from(first),
+        _ => (),
+    }
+    let (label, rest) = if first.starts_with('$') {
+        (first, rest)
+    } else {
+        ("", code)
+    };
+    let (mut mnemonic, mut args) = split_word(rest);
+    // `data:` needn't be followed by a space.
+    let joined;
+    if let Some(digits) = mnemonic.strip_prefix("data:").filter(|d| !d.is_empty()) {
+        joined = format!("{} {}", digits, args);
+        (mnemonic, args) = ("data:", joined.trim());
+    }
+    let mut out = pad(label, LABEL_WIDTH);
+    if args.is_empty() {
+        out.push_str(mnemonic);
+    } else {
+        out = pad(&(out + mnemonic), LABEL_WIDTH + MNEMONIC_WIDTH);
+        out.push_str(&operands(args, mnemonic == "data:"));
+    }
+    String::from(out.trim_end())
+}
+
+/// Split the first word from the rest of some code.
+fn split_word(code: &str) -> (&str, &str) {
+    let code = code.trim_start();
+    match code.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (code, ""),
+    }
+}
+
+/// Pad text with spaces to a width, or with one space if it's already that wide.
+fn pad(text: &str, width: usize) -> String {
+    if text.is_empty() {
+        format!("{:width$}", "")
+    } else if text.chars().count() < width {
+        format!("{:width$}", text)
+    } else {
+        format!("{} ", text)
+    }
+}
+
+/// Format operands with one space after each comma and none before, a single space
+/// wherever there was other whitespace, and the case of each word fixed. Strings and
+/// characters in quotes are kept as they are. For `data:` lines every hex digit is
+/// made lower case.
+fn operands(text: &str, data: bool) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let mut quote = None;
+    for c in text.trim().chars() {
+        if let Some(q) = quote {
+            out.push(c);
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        if c.is_alphanumeric() || matches!(c, '$' | '#' | '_' | '@') {
+            word.push(c);
+            continue;
+        }
+        out.push_str(&fix_case(&word, data));
+        word.clear();
+        match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                out.push(c);
+            }
+            ',' => {
+                out.truncate(out.trim_end().len());
+                out.push_str(", ");
+            }
+            c if c.is_whitespace() => {
+                if !out.ends_with(' ') {
+                    out.push(' ');
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out.push_str(&fix_case(&word, data));
+    out
+}
+
+/// Write registers as `V` and a lower case digit, and hex numbers in lower case.
+fn fix_case(word: &str, data: bool) -> String {
+    let is_hex = |digits: &str| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_hexdigit());
+    let register = word
+        .strip_prefix('V')
+        .filter(|digit| digit.len() == 1 && is_hex(digit));
+    if let Some(digit) = register {
+        format!("V{}", digit.to_ascii_lowercase())
+    } else if word.strip_prefix('#').is_some_and(is_hex)
+        || word.strip_prefix("0x").is_some_and(is_hex)
+        || (data && is_hex(word))
+    {
+        word.to_ascii_lowercase()
+    } else {
+        String::from(word)
+    }
+}
+
+#[cfg(test)]
+mod format_tests {
+    use super::*;
+    use crate::c8asc::assemble;
+    use rstest::*;
+
+    #[rstest]
+    #[case::columns("$start input V0\n  clr\n", "$start    input    V0\n          clr\n")]
+    #[case::long_label("$long_label jmp $long_label\n", "$long_label jmp    $long_label\n")]
+    #[case::operands(
+        "mov VA,#1F\ndraw V0 ,V1,  5\n",
+        "          mov      Va, #1f\n          draw     V0, V1, 5\n"
+    )]
+    #[case::prefixed_hex("mov I, 0xABC + $Vb\n", "          mov      I, 0xabc + $Vb\n")]
+    #[case::quotes(
+        "text   \"A,  B;\"  ;  said\n",
+        "          text     \"A,  B;\"        ;  said\n"
+    )]
+    #[case::data("$d data:0A0b\n", "$d        data:    0a0b\n")]
+    #[case::comments(
+        "; header\n   // indented\n\n\n\n",
+        "; header\n          // indented\n"
+    )]
+    #[case::long_comment_column(
+        "mov V1, $some_long_label_name ; n\n",
+        "          mov      V1, $some_long_label_name ; n\n"
+    )]
+    #[case::macro_definition(
+        "macro   wait  $r,$n\n  mov $r, $n\nendm\n",
+        "macro wait $r, $n\n          mov      $r, $n\nendm\n"
+    )]
+    #[case::macro_use("print_bcd V3,10,2\n", "          print_bcd V3, 10, 2\n")]
+    #[case::sprite_rows(
+        "$s sprite ..XX.. /\n .XXXX.  / ; middle\n XX..XX\n",
+        "$s        sprite   ..XX.. /\n                   .XXXX. /        ; middle\n\
+         \x20                  XX..XX\n"
+    )]
+    fn test_format(#[case] src: &str, #[case] expected: &str) {
+        assert_eq!(expected, format(src));
+        assert_eq!(expected, format(expected));
+    }
+
+    #[rstest]
+    fn test_example_is_formatted() {
+        let src = include_str!("../../test/natural-numbers.c8asm");
+        assert_eq!(src, format(src));
+    }
+
+    #[rstest]
+    fn test_same_bytes() {
+        let src = "\
+$main mov VA,#0A ; x
+  mov I,$spr+#2
+$loop draw VA,VB,#5
+ add VA,0x1F
+     jmp $loop
+$spr data:F0F0 ; sprite
+text \"Hi, V1\"
+";
+        assert_eq!(assemble(src).unwrap(), assemble(&format(src)).unwrap());
+    }
+}
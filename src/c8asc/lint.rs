@@ -0,0 +1,428 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, Severity};
+use super::{Assembled, Statement, StatementKind};
+use crate::machine::{cfg::is_skip, insts::Chip8Inst, trace::CodeMap, Chip8Machine};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Bytes of memory a program has to itself, from #200 to the end.
+const PROGRAM_AREA: usize = 0xe00;
+
+/// A check for something that assembles but is probably a mistake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// The ROM is larger than the program area.
+    RomSize,
+    /// Execution can run on into data.
+    FallThrough,
+    /// An instruction starts at an odd address.
+    OddAddress,
+    /// A skip is followed by a two-word XO-CHIP instruction, of which it skips only half.
+    SkipLong,
+    /// `retn` in a program that never calls a subroutine.
+    ReturnWithoutCall,
+    /// `store` writes over code.
+    SelfModifying,
+    /// Code that can't be reached from the start of the program.
+    Unreachable,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::RomSize,
+        Lint::FallThrough,
+        Lint::OddAddress,
+        Lint::SkipLong,
+        Lint::ReturnWithoutCall,
+        Lint::SelfModifying,
+        Lint::Unreachable,
+    ];
+
+    /// The name the lint is given on the command line and in its messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::RomSize => "rom-size",
+            Lint::FallThrough => "fall-through",
+            Lint::OddAddress => "odd-address",
+            Lint::SkipLong => "skip-long",
+            Lint::ReturnWithoutCall => "return-without-call",
+            Lint::SelfModifying => "self-modifying",
+            Lint::Unreachable => "unreachable",
+        }
+    }
+
+    fn check(&self, program: &Program) -> Vec<Diagnostic> {
+        match self {
+            Lint::RomSize => rom_size(program),
+            Lint::FallThrough => fall_through(program),
+            Lint::OddAddress => odd_address(program),
+            Lint::SkipLong => skip_long(program),
+            Lint::ReturnWithoutCall => return_without_call(program),
+            Lint::SelfModifying => self_modifying(program),
+            Lint::Unreachable => unreachable(program),
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .into_iter()
+            .find(|l| l.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Lint::ALL.iter().map(|l| l.name()).collect();
+                format!("Unknown lint '{}' (known lints: {})", s, names.join(", "))
+            })
+    }
+}
+
+/// Which lints are checked and whether they give warnings or errors.
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    /// Lints turned off, or made errors. The rest give warnings.
+    levels: HashMap<Lint, Option<Severity>>,
+}
+
+impl Lints {
+    pub fn new() -> Lints {
+        Lints::default()
+    }
+
+    /// Stop checking a lint.
+    pub fn allow(&mut self, lint: Lint) {
+        self.levels.insert(lint, None);
+    }
+
+    /// Report a lint as an error instead of a warning.
+    pub fn deny(&mut self, lint: Lint) {
+        self.levels.insert(lint, Some(Severity::Error));
+    }
+
+    /// Check an assembled program with every lint that isn't allowed. Each message ends
+    /// with the name of the lint that found it.
+    pub fn check(&self, assembled: &Assembled) -> Vec<Diagnostic> {
+        let program = Program::new(assembled);
+        let mut diags = Vec::new();
+        for lint in Lint::ALL {
+            let severity = match self.levels.get(&lint) {
+                Some(None) => continue,
+                Some(Some(severity)) => *severity,
+                None => Severity::Warning,
+            };
+            diags.extend(lint.check(&program).into_iter().map(|diag| Diagnostic {
+                severity,
+                message: format!("{} [{}]", diag.message, lint),
+                ..diag
+            }));
+        }
+        diags.sort_by_key(|d| d.span.map(|s| (s.file, s.start)));
+        diags
+    }
+}
+
+/// An assembled program with its instructions decoded and its code traced.
+struct Program<'a> {
+    assembled: &'a Assembled,
+    /// Every instruction statement and what it decodes to.
+    insts: Vec<(&'a Statement, Chip8Inst)>,
+    code: CodeMap,
+}
+
+impl Program<'_> {
+    fn new(assembled: &Assembled) -> Program<'_> {
+        let program = Program {
+            assembled,
+            insts: Vec::new(),
+            code: CodeMap::trace(&assembled.rom, assembled.start),
+        };
+        let insts = assembled
+            .statements
+            .iter()
+            .filter(|s| s.kind == StatementKind::Instruction)
+            .filter_map(|s| Some((s, Chip8Machine::decode(program.word(s.addr)?).ok()?)))
+            .collect();
+        Program { insts, ..program }
+    }
+
+    /// The word of the ROM at an address.
+    fn word(&self, addr: usize) -> Option<u16> {
+        let offset = addr.checked_sub(self.assembled.start)?;
+        let bytes = self.assembled.rom.get(offset..offset + 2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+fn rom_size(program: &Program) -> Vec<Diagnostic> {
+    let assembled = program.assembled;
+    if assembled.rom.len() <= PROGRAM_AREA {
+        return Vec::new();
+    }
+    let limit = assembled.start + PROGRAM_AREA;
+    let span = assembled
+        .statements
+        .iter()
+        .find(|s| s.addr + s.len > limit && s.len > 0)
+        .map(|s| s.span);
+    vec![Diagnostic::warning(
+        format!(
+            "the ROM is {} bytes, more than the {} bytes of the program area",
+            assembled.rom.len(),
+            PROGRAM_AREA
+        ),
+        span,
+    )]
+}
+
+fn fall_through(program: &Program) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let mut in_reached_data = false;
+    for s in program.assembled.statements.iter().filter(|s| s.len > 0) {
+        let reached = s.kind == StatementKind::Data
+            && (s.addr..s.addr + s.len).any(|addr| program.code.inst(addr).is_some());
+        if reached && !in_reached_data {
+            diags.push(Diagnostic::warning(
+                String::from("execution can run on into this data"),
+                Some(s.span),
+            ));
+        }
+        in_reached_data = reached;
+    }
+    diags
+}
+
+fn odd_address(program: &Program) -> Vec<Diagnostic> {
+    program
+        .assembled
+        .statements
+        .iter()
+        .filter(|s| s.kind == StatementKind::Instruction && s.addr % 2 == 1)
+        .map(|s| {
+            Diagnostic::warning(
+                format!("instruction at odd address #{:x}", s.addr),
+                Some(s.span),
+            )
+        })
+        .collect()
+}
+
+fn skip_long(program: &Program) -> Vec<Diagnostic> {
+    program
+        .insts
+        .iter()
+        .filter(|(s, inst)| is_skip(*inst) && program.word(s.addr + 2) == Some(0xf000))
+        .map(|(s, _)| {
+            Diagnostic::warning(
+                String::from("this skips only the first word of the two-word instruction after it"),
+                Some(s.span),
+            )
+        })
+        .collect()
+}
+
+fn return_without_call(program: &Program) -> Vec<Diagnostic> {
+    if program
+        .insts
+        .iter()
+        .any(|(_, inst)| matches!(inst, Chip8Inst::SubCall(_)))
+    {
+        return Vec::new();
+    }
+    program
+        .insts
+        .iter()
+        .filter(|(_, inst)| *inst == Chip8Inst::SubReturn)
+        .map(|(s, _)| {
+            Diagnostic::warning(
+                String::from("`retn` in a program that never calls a subroutine"),
+                Some(s.span),
+            )
+        })
+        .collect()
+}
+
+/// Follows I from each `mov I` to the `store`s after it, up to the next jump or change to
+/// I, so only catches writes made close to where I is set.
+fn self_modifying(program: &Program) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let mut index = None;
+    for (s, inst) in &program.insts {
+        match *inst {
+            Chip8Inst::SetIndex(addr) => index = Some(addr),
+            Chip8Inst::AddIndex(_)
+            | Chip8Inst::Jump(_)
+            | Chip8Inst::JumpReg(_)
+            | Chip8Inst::SubReturn => index = None,
+            Chip8Inst::StoreMem(x) => {
+                let Some(start) = index else { continue };
+                let end = start + x + 1;
+                let overwritten = program
+                    .insts
+                    .iter()
+                    .find(|(code, _)| code.addr < end && start < code.addr + code.len);
+                if let Some((code, _)) = overwritten {
+                    diags.push(
+                        Diagnostic::warning(
+                            format!("`store` writes over the code at #{:x}", code.addr),
+                            Some(s.span),
+                        )
+                        .with_note(Some(code.span), String::from("which is here")),
+                    );
+                }
+            }
+            _ => (),
+        }
+    }
+    diags
+}
+
+/// Reports the first instruction of each run that can't be reached by following jumps,
+/// calls, skips and returns from the start. The targets of `jmpv` aren't followed.
+fn unreachable(program: &Program) -> Vec<Diagnostic> {
+    let mut diags = Vec::new();
+    let mut in_unreached = false;
+    for s in &program.assembled.statements {
+        match s.kind {
+            StatementKind::Instruction => {
+                let reached = program.code.inst(s.addr).is_some();
+                if !reached && !in_unreached {
+                    diags.push(Diagnostic::warning(
+                        String::from("this code is never reached"),
+                        Some(s.span),
+                    ));
+                }
+                in_unreached = !reached;
+            }
+            StatementKind::Data => in_unreached = false,
+            StatementKind::Directive | StatementKind::Reserve => (),
+        }
+    }
+    diags
+}
+
+#[cfg(test)]
+mod lint_tests {
+    use super::*;
+    use crate::c8asc::{assemble_sources, diagnostic::Sources};
+    use rstest::*;
+
+    fn check(src: &str, lints: &Lints) -> Vec<Diagnostic> {
+        let mut sources = Sources::new();
+        let file = sources.add("prog.c8asm", src);
+        let assembled = assemble_sources(&mut sources, file, &|path| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                path.display().to_string(),
+            ))
+        })
+        .unwrap();
+        lints.check(&assembled)
+    }
+
+    fn warnings(src: &str) -> Vec<(usize, String)> {
+        check(src, &Lints::new())
+            .into_iter()
+            .map(|d| (d.span.map_or(0, |s| s.start), d.message))
+            .collect()
+    }
+
+    #[rstest]
+    #[case::clean("$a call $b\njmp $a\n$b mov I, $d\nload V1\nretn\n$d data: 0102\n")]
+    #[case::data_after_jump("$a jmp $a\ndata: 1234\n")]
+    #[case::store_into_data("mov I, $d\nstore V1\n$a jmp $a\n$d reserve 2\n")]
+    fn test_no_warnings(#[case] src: &str) {
+        assert_eq!(Vec::<(usize, String)>::new(), warnings(src));
+    }
+
+    #[rstest]
+    #[case::rom_size(
+        "org #100\njmp $end\nspace 3584\n$end jmp $end\n",
+        (18, "the ROM is 3588 bytes, more than the 3584 bytes of the program area [rom-size]")
+    )]
+    #[case::fall_through(
+        "clr\ndata: 1234\ndata: 5678\n",
+        (4, "execution can run on into this data [fall-through]")
+    )]
+    #[case::odd_address(
+        "$a jmp $b\ndata: 00\n$b jmp $b\n",
+        (19, "instruction at odd address #203 [odd-address]")
+    )]
+    #[case::return_without_call(
+        "clr\nretn\n",
+        (4, "`retn` in a program that never calls a subroutine [return-without-call]")
+    )]
+    #[case::self_modifying(
+        "$a mov I, $b\nstore V1\n$b jmp $a\n",
+        (13, "`store` writes over the code at #204 [self-modifying]")
+    )]
+    #[case::unreachable(
+        "$a jmp $a\nclr\nclr\n",
+        (10, "this code is never reached [unreachable]")
+    )]
+    fn test_lints(#[case] src: &str, #[case] expected: (usize, &str)) {
+        assert_eq!(vec![(expected.0, String::from(expected.1))], warnings(src));
+    }
+
+    #[rstest]
+    fn test_skip_long() {
+        // The second word is data as far as the assembler knows, so the skip that lands
+        // in the middle of it is found as well.
+        let src = "$a skipeq V0, 1\nword #f000, #0300\njmp $a\n";
+        let expected = vec![
+            (
+                0,
+                String::from(
+                    "this skips only the first word of the two-word instruction after it \
+                     [skip-long]",
+                ),
+            ),
+            (
+                16,
+                String::from("execution can run on into this data [fall-through]"),
+            ),
+        ];
+        assert_eq!(expected, warnings(src));
+    }
+
+    #[rstest]
+    fn test_allow_and_deny() {
+        let src = "$a jmp $a\nclr\nclr\n";
+        let mut lints = Lints::new();
+        lints.deny(Lint::Unreachable);
+        let diags = check(src, &lints);
+        assert_eq!(1, diags.len());
+        assert_eq!(Severity::Error, diags[0].severity);
+        lints.allow(Lint::Unreachable);
+        assert!(check(src, &lints).is_empty());
+    }
+
+    #[rstest]
+    fn test_from_str() {
+        for lint in Lint::ALL {
+            assert_eq!(Ok(lint), lint.name().parse());
+        }
+        assert!(Lint::from_str("frob")
+            .unwrap_err()
+            .starts_with("Unknown lint 'frob' (known lints: rom-size, "));
+    }
+}
@@ -0,0 +1,217 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::Sources;
+use super::{Assembled, StatementKind};
+use crate::machine::debug_info::{DebugInfo, SourceLine};
+use crate::machine::labels::Symbols;
+use std::fmt::Write;
+
+/// Bytes shown on each line of a listing.
+const BYTES_PER_LINE: usize = 4;
+
+/// List the address and bytes of every statement beside its source line.
+///
+/// ```text
+/// ; prog.c8asm
+/// 200  61 02        $a mov V1, #2
+/// 202  12 00           jmp $a
+/// ```
+///
+/// Statements that take no space have no address, reserved space has an address but no
+/// bytes, and longer data carries on over further lines. A comment names the source file wherever it changes.
+pub fn listing(assembled: &Assembled, sources: &Sources) -> String {
+    let mut out = String::new();
+    let mut file = None;
+    for statement in &assembled.statements {
+        let span = statement.span;
+        let source = sources.get(span.file);
+        if file != Some(span.file) {
+            writeln!(out, "; {}", source.name).unwrap();
+            file = Some(span.file);
+        }
+        let text = source.line(source.line_col(span.start).0);
+        if statement.len == 0 {
+            writeln!(out, "{:<18}{}", "", text).unwrap();
+            continue;
+        }
+        // Reserved space has an address but no value.
+        if statement.kind == StatementKind::Reserve {
+            writeln!(out, "{:03x}  {:<13}{}", statement.addr, "", text).unwrap();
+            continue;
+        }
+        let offset = statement.addr - assembled.start;
+        let bytes = &assembled.rom[offset..offset + statement.len];
+        let mut chunks = bytes.chunks(BYTES_PER_LINE);
+        let first = chunks.next().unwrap_or_default();
+        writeln!(out, "{:03x}  {:<13}{}", statement.addr, hex(first), text).unwrap();
+        for (i, chunk) in chunks.enumerate() {
+            let addr = statement.addr + (i + 1) * BYTES_PER_LINE;
+            writeln!(out, "{:03x}  {}", addr, hex(chunk)).unwrap();
+        }
+    }
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    hex.join(" ")
+}
+
+/// Name the address of every label, with the first label given to it.
+///
+/// Labels local to a use of a macro are left out, as they can't be written in the source.
+pub fn symbols(assembled: &Assembled) -> Symbols {
+    let mut names = Symbols::new();
+    for (name, addr) in &assembled.labels {
+        if !name.contains('@') {
+            names.entry(*addr as usize).or_insert_with(|| name.clone());
+        }
+    }
+    names
+}
+
+/// Write the address of every label in the form read by the disassembler's `--symbols`.
+pub fn symbol_map(assembled: &Assembled) -> String {
+    symbols(assembled)
+        .into_iter()
+        .map(|(addr, name)| format!("{:03x} {}\n", addr, name))
+        .collect()
+}
+
+/// Record the source line of every statement that was given bytes, and every label, for
+/// debugging the ROM in rchip8.
+pub fn debug_info(assembled: &Assembled, sources: &Sources) -> DebugInfo {
+    let mut info = DebugInfo::default();
+    let mut files = Vec::new();
+    for statement in assembled.statements.iter().filter(|s| s.len > 0) {
+        let span = statement.span;
+        let file = match files.iter().position(|f| *f == span.file) {
+            Some(file) => file,
+            None => {
+                files.push(span.file);
+                info.files.push(sources.get(span.file).name.clone());
+                files.len() - 1
+            }
+        };
+        let source = sources.get(span.file);
+        let line = source.line_col(span.start).0;
+        info.lines.push(SourceLine {
+            addr: statement.addr,
+            len: statement.len,
+            file,
+            line,
+            text: String::from(source.line(line).trim()),
+        });
+    }
+    info.labels = assembled
+        .labels
+        .iter()
+        .map(|(name, addr)| (*addr as usize, name.clone()))
+        .collect();
+    info
+}
+
+#[cfg(test)]
+mod listing_tests {
+    use super::*;
+    use crate::c8asc::assemble_sources;
+    use crate::machine::labels::parse_symbols;
+    use rstest::*;
+
+    fn assemble(src: &str) -> (Assembled, Sources) {
+        let mut sources = Sources::new();
+        let file = sources.add("prog.c8asm", src);
+        let assembled = assemble_sources(&mut sources, file, &|path| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                path.display().to_string(),
+            ))
+        })
+        .unwrap();
+        (assembled, sources)
+    }
+
+    #[rstest]
+    fn test_listing() {
+        let src = "\
+; start
+$a mov V1, #2   ; count
+const $n = 3
+
+   jmp $a
+$d byte 1, 2, 3, 4, 5
+";
+        let expected = "\
+; prog.c8asm
+200  61 02        $a mov V1, #2   ; count
+                  const $n = 3
+202  12 00           jmp $a
+204  01 02 03 04  $d byte 1, 2, 3, 4, 5
+208  05
+";
+        let (assembled, sources) = assemble(src);
+        assert_eq!(expected, listing(&assembled, &sources));
+    }
+
+    #[rstest]
+    fn test_listing_reserved() {
+        let (assembled, sources) = assemble("clr\n$buf reserve 2\nretn\n");
+        let expected = "\
+; prog.c8asm
+200  00 e0        clr
+202               $buf reserve 2
+204  00 ee        retn
+";
+        assert_eq!(expected, listing(&assembled, &sources));
+    }
+
+    #[rstest]
+    fn test_listing_reserved_end() {
+        let (assembled, sources) = assemble("clr\n$buf reserve 2\n");
+        let expected = "; prog.c8asm\n200  00 e0        clr\n202               $buf reserve 2\n";
+        assert_eq!(expected, listing(&assembled, &sources));
+    }
+
+    #[rstest]
+    fn test_debug_info() {
+        let (assembled, sources) = assemble("const $n = 2\n$a   clr\n\n     jmp $a\n");
+        let info = debug_info(&assembled, &sources);
+        let expected = "\
+file prog.c8asm
+line 200 2 0 2 $a   clr
+line 202 2 0 4 jmp $a
+label 200 $a
+";
+        assert_eq!(expected, info.to_string());
+        assert_eq!(Ok(0x202), info.resolve("prog.c8asm:4"));
+    }
+
+    #[rstest]
+    fn test_symbol_map() {
+        let src = "\
+macro wait
+clr
+$w jmp $w
+endm
+$main clr
+$loop wait
+$also jmp $loop
+";
+        let (assembled, _) = assemble(src);
+        let map = symbol_map(&assembled);
+        assert_eq!("200 $main\n202 $loop\n206 $also\n", map);
+        let symbols = parse_symbols(&map).unwrap();
+        assert_eq!(Some(&String::from("$loop")), symbols.get(&0x202));
+    }
+}
@@ -0,0 +1,668 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, FileId, Severity, Sources, Span};
+use super::lint::Lints;
+use super::preprocess::KEYWORDS;
+use super::{assemble_sources, names, Label};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// The language reference, whose instruction table describes each mnemonic on hover.
+const REFERENCE: &str = include_str!("../../c8asm.md");
+
+/// JSON-RPC error codes.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// LSP kinds of completion item.
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+
+/// A language server for c8asm. Every request assembles the document again, reading
+/// included files from the open documents where the client has them and from disk where
+/// it doesn't.
+#[derive(Default)]
+pub struct Server {
+    /// The text of each open document, by URI.
+    docs: HashMap<String, String>,
+    shutdown: bool,
+    /// The status to exit with, once the client has said to exit.
+    exit: Option<i32>,
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    /// The status to exit with, if the client has said to exit.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit
+    }
+
+    /// Handle a message from the client, giving the messages to send back.
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                return Vec::new();
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.docs.insert(String::from(uri), String::from(text));
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didChange" => {
+                // Only whole documents are synchronised, so the last change is the text.
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes.and_then(|c| c.last()?["text"].as_str()) {
+                    self.docs.insert(String::from(uri), String::from(text));
+                }
+                return vec![self.diagnostics(uri)];
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(uri);
+                return vec![publish(uri, Vec::new())];
+            }
+            _ => (),
+        }
+        let id = match msg.get("id") {
+            Some(id) => id,
+            None => return Vec::new(),
+        };
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => {
+                let message = format!("unknown method: {}", method);
+                return vec![error(id, METHOD_NOT_FOUND, &message)];
+            }
+        };
+        vec![json!({"jsonrpc": "2.0", "id": id, "result": result})]
+    }
+
+    /// The sources of an open document, as the only file so far.
+    fn sources(&self, uri: &str) -> Option<(Sources, FileId)> {
+        let text = self.docs.get(uri)?;
+        let mut sources = Sources::new();
+        let file = sources.add(&uri_to_path(uri), text);
+        Some((sources, file))
+    }
+
+    /// Read an included file from the client if it's open there, or from disk.
+    fn load(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.docs.get(&path_to_uri(&path.to_string_lossy())) {
+            Some(text) => Ok(text.clone().into_bytes()),
+            None => std::fs::read(path),
+        }
+    }
+
+    /// Assemble a document and check it with the lints, giving the notification that
+    /// publishes what was found. Problems in included files are shown at the start of
+    /// the document.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let (mut sources, file) = match self.sources(uri) {
+            Some(found) => found,
+            None => return publish(uri, Vec::new()),
+        };
+        let diags = match assemble_sources(&mut sources, file, &|path| self.load(path)) {
+            Ok(assembled) => Lints::new().check(&assembled),
+            Err(diags) => diags,
+        };
+        let items = diags
+            .into_iter()
+            .map(|mut diag| {
+                if let Some(span) = diag.span.filter(|s| s.file != file) {
+                    let source = sources.get(span.file);
+                    let line = source.line_col(span.start).0;
+                    diag.message = format!("{}:{}: {}", source.name, line, diag.message);
+                    diag.span = Some(Span::new(file, 0, 0));
+                }
+                diagnostic(&sources, &diag)
+            })
+            .collect();
+        publish(uri, items)
+    }
+
+    /// The open document a request is about, the offset in it of the position the request
+    /// gives, and every name defined and used in it.
+    fn lookup(&self, params: &Value) -> Option<(Sources, FileId, usize, super::Names)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let (mut sources, file) = self.sources(uri)?;
+        let offset = offset_at(&sources.get(file).text, &params["position"])?;
+        let names = names(&mut sources, file, &|path| self.load(path));
+        Some((sources, file, offset, names))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let (sources, file, offset, names) = match self.lookup(params) {
+            Some(found) => found,
+            None => return Value::Null,
+        };
+        match name_at(&names.defs, &names.uses, file, offset) {
+            Some(name) => locations(&sources, &names.defs, name),
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let (sources, file, offset, names) = match self.lookup(params) {
+            Some(found) => found,
+            None => return Value::Null,
+        };
+        let name = match name_at(&names.defs, &names.uses, file, offset) {
+            Some(name) => name,
+            None => return Value::Null,
+        };
+        let mut labels = names.uses.clone();
+        if params["context"]["includeDeclaration"].as_bool() == Some(true) {
+            labels.extend(names.defs.iter().cloned());
+            labels.sort_by_key(|lbl| (lbl.span.file, lbl.span.start));
+        }
+        locations(&sources, &labels, name)
+    }
+
+    /// Describe the mnemonic under the cursor with its rows of the instruction table.
+    fn hover(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match self.docs.get(uri) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let (start, end) = match offset_at(text, &params["position"]) {
+            Some(offset) => word_at(text, offset),
+            None => return Value::Null,
+        };
+        let rows: Vec<String> = instructions(&text[start..end])
+            .map(|(opcode, inst, meaning)| format!("`{}` ({}): {}", inst, opcode, meaning))
+            .collect();
+        if rows.is_empty() {
+            return Value::Null;
+        }
+        json!({
+            "contents": {"kind": "markdown", "value": rows.join("\n\n")},
+            "range": {"start": position(text, start), "end": position(text, end)},
+        })
+    }
+
+    /// Offer every label after a `$`, and every mnemonic and label anywhere else.
+    fn completion(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match self.docs.get(uri) {
+            Some(text) => text,
+            None => return Value::Null,
+        };
+        let offset = match offset_at(text, &params["position"]) {
+            Some(offset) => offset,
+            None => return Value::Null,
+        };
+        let (start, _) = word_at(text, offset);
+        // The line being typed is left out, as it would often stop the source parsing.
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+        let mut sources = Sources::new();
+        let others = format!("{}{}", &text[..line_start], &text[line_end..]);
+        let file = sources.add(&uri_to_path(uri), &others);
+        let names = names(&mut sources, file, &|path| self.load(path));
+        let mut items = Vec::new();
+        if !text[start..].starts_with('$') {
+            let mnemonics = KEYWORDS
+                .iter()
+                .filter(|k| k.chars().any(char::is_lowercase));
+            items.extend(mnemonics.map(|k| json!({"label": k, "kind": COMPLETION_KEYWORD})));
+        }
+        let labels: BTreeSet<&str> = names.defs.iter().map(|lbl| lbl.name.as_str()).collect();
+        items.extend(
+            labels
+                .into_iter()
+                .map(|name| json!({"label": name, "kind": COMPLETION_VARIABLE})),
+        );
+        Value::Array(items)
+    }
+}
+
+/// What the server can do, in answer to `initialize`.
+fn capabilities() -> Value {
+    json!({
+        "capabilities": {
+            // Whole documents are sent on every change.
+            "textDocumentSync": 1,
+            "definitionProvider": true,
+            "referencesProvider": true,
+            "hoverProvider": true,
+            "completionProvider": {"triggerCharacters": ["$"]},
+        },
+        "serverInfo": {"name": "c8asc-lsp", "version": env!("CARGO_PKG_VERSION")},
+    })
+}
+
+fn error(id: &Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+
+fn diagnostic(sources: &Sources, diag: &Diagnostic) -> Value {
+    let span = diag.span.unwrap_or_default();
+    let related: Vec<Value> = diag
+        .notes
+        .iter()
+        .filter_map(|(span, message)| {
+            Some(json!({"location": location(sources, (*span)?), "message": message}))
+        })
+        .collect();
+    json!({
+        "range": range(sources, span),
+        "severity": match diag.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2,
+        },
+        "source": "c8asc",
+        "message": diag.message,
+        "relatedInformation": related,
+    })
+}
+
+fn range(sources: &Sources, span: Span) -> Value {
+    let text = &sources.get(span.file).text;
+    json!({"start": position(text, span.start), "end": position(text, span.end)})
+}
+
+fn location(sources: &Sources, span: Span) -> Value {
+    let uri = path_to_uri(&sources.get(span.file).name);
+    json!({"uri": uri, "range": range(sources, span)})
+}
+
+/// The location of every label in `labels` with a name.
+fn locations(sources: &Sources, labels: &[Label], name: &str) -> Value {
+    labels
+        .iter()
+        .filter(|lbl| lbl.name == name)
+        .map(|lbl| location(sources, lbl.span))
+        .collect()
+}
+
+/// The name of the label at an offset in `file`, whether defined or used there.
+fn name_at<'a>(
+    defs: &'a [Label],
+    uses: &'a [Label],
+    file: FileId,
+    offset: usize,
+) -> Option<&'a str> {
+    defs.iter()
+        .chain(uses)
+        .find(|lbl| lbl.span.file == file && lbl.span.start <= offset && offset <= lbl.span.end)
+        .map(|lbl| lbl.name.as_str())
+}
+
+/// The LSP position of a byte offset, counting characters in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Value {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    json!({
+        "line": before.matches('\n').count(),
+        "character": before[line_start..].encode_utf16().count(),
+    })
+}
+
+/// The byte offset of an LSP position, which is clamped to the end of its line.
+fn offset_at(text: &str, position: &Value) -> Option<usize> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    let start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1)?.0 + 1,
+    };
+    let mut units = 0;
+    for (i, c) in text[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return Some(start + i);
+        }
+        units += c.len_utf16();
+    }
+    Some(text.len())
+}
+
+/// The range of the word, mnemonic or label around an offset.
+fn word_at(text: &str, offset: usize) -> (usize, usize) {
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '$' | ':');
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map_or(offset, |(i, _)| i);
+    let end = text[offset..]
+        .char_indices()
+        .find(|(_, c)| !is_word(*c))
+        .map_or(text.len(), |(i, _)| offset + i);
+    (start, end)
+}
+
+/// The rows of the reference's instruction table for a mnemonic, as the opcode, the
+/// instruction with its operands and what it does.
+fn instructions(mnemonic: &str) -> impl Iterator<Item = (&'static str, String, &'static str)> + '_ {
+    REFERENCE.lines().filter_map(move |line| {
+        let cells: Vec<&str> = line.split('|').map(str::trim).collect();
+        match cells[..] {
+            ["", opcode, inst, meaning, ""] if opcode.len() == 4 && inst.starts_with('`') => {
+                let inst: Vec<&str> = inst.trim_matches('`').split_whitespace().collect();
+                (inst.first() == Some(&mnemonic)).then(|| (opcode, inst.join(" "), meaning))
+            }
+            _ => None,
+        }
+    })
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => path,
+        None => return String::from(uri),
+    };
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|_| b == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+/// Read the body of a message framed with a `Content-Length` header, or `None` at the
+/// end of the input.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "message has no Content-Length")
+    })?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn write_message(output: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// Serve a client talking over `input` and `output` until it says to exit, giving the
+/// status to exit with.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<i32> {
+    let mut server = Server::new();
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match serde_json::from_slice(&body) {
+            Ok(msg) => server.handle(&msg),
+            Err(e) => vec![error(&Value::Null, PARSE_ERROR, &e.to_string())],
+        };
+        for reply in replies {
+            write_message(&mut output, &reply)?;
+        }
+        if let Some(code) = server.exit_code() {
+            return Ok(code);
+        }
+    }
+    // The client went away without saying to exit.
+    Ok(1)
+}
+
+#[cfg(test)]
+mod lsp_tests {
+    use super::*;
+    use rstest::*;
+
+    const URI: &str = "file:///work/prog.c8asm";
+
+    /// Play messages to a server, giving its replies and exit status.
+    fn session(messages: &[Value]) -> (Vec<Value>, i32) {
+        let mut input = Vec::new();
+        for msg in messages {
+            write_message(&mut input, msg).unwrap();
+        }
+        let mut output = Vec::new();
+        let code = serve(&input[..], &mut output).unwrap();
+        let mut replies = Vec::new();
+        let mut output = &output[..];
+        while let Some(body) = read_message(&mut output).unwrap() {
+            replies.push(serde_json::from_slice(&body).unwrap());
+        }
+        (replies, code)
+    }
+
+    fn open(text: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": {
+                "textDocument": {"uri": URI, "languageId": "c8asm", "version": 1, "text": text},
+            },
+        })
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": {"uri": URI},
+                "position": {"line": line, "character": character},
+                "context": {"includeDeclaration": false},
+            },
+        })
+    }
+
+    /// The result of a request about a position in a document.
+    fn ask(text: &str, method: &str, line: u32, character: u32) -> Value {
+        let (replies, _) = session(&[open(text), request(1, method, line, character)]);
+        replies[1]["result"].clone()
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Value {
+        json!({
+            "start": {"line": start.0, "character": start.1},
+            "end": {"line": end.0, "character": end.1},
+        })
+    }
+
+    #[rstest]
+    fn test_lifecycle() {
+        let (replies, code) = session(&[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ]);
+        assert_eq!(2, replies.len());
+        assert_eq!(1, replies[0]["id"]);
+        assert_eq!(true, replies[0]["result"]["capabilities"]["hoverProvider"]);
+        assert_eq!(
+            json!({"jsonrpc": "2.0", "id": 2, "result": null}),
+            replies[1]
+        );
+        assert_eq!(0, code);
+    }
+
+    #[rstest]
+    fn test_exit_without_shutdown() {
+        let (_, code) = session(&[json!({"jsonrpc": "2.0", "method": "exit"})]);
+        assert_eq!(1, code);
+    }
+
+    #[rstest]
+    fn test_unknown_method() {
+        let (replies, _) = session(&[json!({"jsonrpc": "2.0", "id": 7, "method": "frob"})]);
+        assert_eq!(METHOD_NOT_FOUND, replies[0]["error"]["code"]);
+        assert_eq!(7, replies[0]["id"]);
+    }
+
+    #[rstest]
+    fn test_diagnostics() {
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": URI, "version": 2},
+                "contentChanges": [{"text": "$a jmp $a\nclr\n"}],
+            },
+        });
+        let (replies, _) = session(&[open("clr\nmov V1, #100\n"), change]);
+        assert_eq!("textDocument/publishDiagnostics", replies[0]["method"]);
+        let diag = &replies[0]["params"]["diagnostics"][0];
+        assert_eq!(
+            "#100 doesn't fit in 8 bits (the most is #ff)",
+            diag["message"]
+        );
+        assert_eq!(1, diag["severity"]);
+        assert_eq!(range((1, 8), (1, 12)), diag["range"]);
+        let diag = &replies[1]["params"]["diagnostics"][0];
+        assert_eq!("this code is never reached [unreachable]", diag["message"]);
+        assert_eq!(2, diag["severity"]);
+    }
+
+    #[rstest]
+    fn test_definition() {
+        let src = "$loop  clr\n       jmp $loop\n";
+        let expected = json!([{"uri": URI, "range": range((0, 0), (0, 5))}]);
+        assert_eq!(expected, ask(src, "textDocument/definition", 1, 14));
+        assert_eq!(Value::Null, ask(src, "textDocument/definition", 0, 8));
+    }
+
+    #[rstest]
+    fn test_definition_in_macro() {
+        let src = "macro wait\n$w jmp $w\nendm\nwait\nwait\n";
+        let expected = json!([{"uri": URI, "range": range((1, 0), (1, 2))}]);
+        assert_eq!(expected, ask(src, "textDocument/definition", 1, 8));
+    }
+
+    #[rstest]
+    fn test_references() {
+        let src = "const $n = 2\nmov V0, $n\n$a add V0, $n + $n\njmp $a\n";
+        let expected = json!([
+            {"uri": URI, "range": range((1, 8), (1, 10))},
+            {"uri": URI, "range": range((2, 11), (2, 13))},
+            {"uri": URI, "range": range((2, 16), (2, 18))},
+        ]);
+        assert_eq!(expected, ask(src, "textDocument/references", 0, 7));
+    }
+
+    #[rstest]
+    fn test_hover() {
+        let hover = ask("  mov V1, 2\n", "textDocument/hover", 0, 3);
+        let text = hover["contents"]["value"].as_str().unwrap();
+        assert!(text.starts_with("`mov Vx, nn` (6xnn): set Vx to nn\n\n`mov Vx, Vy` (8xy0)"));
+        assert_eq!(6, text.matches("`mov ").count());
+        assert_eq!(range((0, 2), (0, 5)), hover["range"]);
+        assert_eq!(
+            Value::Null,
+            ask("  mov V1, 2\n", "textDocument/hover", 0, 7)
+        );
+    }
+
+    #[rstest]
+    fn test_completion() {
+        let src = "$start clr\nconst $n = 1\njmp $\n";
+        let labels = ask(src, "textDocument/completion", 2, 5);
+        assert_eq!(
+            json!([{"label": "$n", "kind": 6}, {"label": "$start", "kind": 6}]),
+            labels
+        );
+        let all = ask(src, "textDocument/completion", 2, 1);
+        let all = all.as_array().unwrap();
+        assert!(all.contains(&json!({"label": "jmp", "kind": 14})));
+        assert!(all.contains(&json!({"label": "$start", "kind": 6})));
+        assert!(!all.contains(&json!({"label": "I", "kind": 14})));
+    }
+
+    #[rstest]
+    #[case("file:///work/my%20game.c8asm", "/work/my game.c8asm")]
+    #[case("untitled:1", "untitled:1")]
+    fn test_uri_to_path(#[case] uri: &str, #[case] path: &str) {
+        assert_eq!(path, uri_to_path(uri));
+        if uri.starts_with("file:") {
+            assert_eq!(uri, path_to_uri(path));
+        }
+    }
+
+    #[rstest]
+    #[case("ab\ncd\n", 1, 1, 4)]
+    #[case("ab\ncd\n", 0, 9, 2)]
+    #[case("é😀x\n", 0, 3, 6)]
+    fn test_offset_at(
+        #[case] text: &str,
+        #[case] line: u32,
+        #[case] character: u32,
+        #[case] expected: usize,
+    ) {
+        let pos = json!({"line": line, "character": character});
+        assert_eq!(Some(expected), offset_at(text, &pos));
+    }
+}
@@ -11,43 +11,203 @@
 // You should have received a copy of the GNU General Public License along with rchip8.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use crate::machine::MEMORY_SIZE;
+use charmap::Charmap;
+use diagnostic::{Diagnostic, FileId, Sources, Span};
+use expr::{Base, Expr, Operand, Symbol, Symbols};
+use lalrpop_util::{lalrpop_mod, ParseError};
+use object::{Field, Object, Relocation};
+use preprocess::Loader;
+use std::collections::{HashMap, HashSet};
+use std::io;
+
+lalrpop_mod!(pub c8asm);
+
+pub mod charmap;
+pub mod diagnostic;
+pub mod expr;
+pub mod format;
+pub mod lint;
+pub mod listing;
+pub mod lsp;
+pub mod object;
+pub mod octo;
+pub mod output;
+pub mod preprocess;
+
+/// A label as written in the source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub name: String,
+    pub span: Span,
+}
 
 pub enum ProgElement {
     Data(Vec<u8>),
     Instr(u16),
-    Jump(String),
-    Call(String),
-    JumpV(String),
-    LabelInstr(String, Box<ProgElement>),
+    /// An instruction with a numeric operand, given as its opcode with the operand bits
+    /// clear.
+    WithOperand(u16, Operand),
+    LabelInstr(Label, Box<ProgElement>),
+    /// An `equ` constant, with the span of its value.
+    Const(Label, Expr, Span),
+    /// Carry on placing the program at an address.
+    Org(Operand),
+    /// Pad with zeros up to a multiple of a number of bytes.
+    Align(Operand),
+    /// A number of copies of a byte, or of zero if no byte is given.
+    Fill(Operand, Option<Operand>),
+    /// Leave a number of bytes free without giving them a value.
+    Reserve(Operand),
+    /// A list of bytes or words, each as wide as its operand.
+    Values(Vec<Operand>),
+    /// A string, with the span of its literal, stored using the character map.
+    Text(String, Span),
+    /// Store the characters of a string, with the span of its literal, as consecutive
+    /// bytes from a number, which is 0 if not given.
+    Charmap(String, Span, Option<Operand>),
 }
 
 impl ProgElement {
-    fn into_bytes(&self, locs: &HashMap<String, u16>) -> Vec<u8> {
+    /// Number of bytes this element assembles to, for those whose size is fixed.
+    fn size(&self) -> usize {
         match self {
-            ProgElement::LabelInstr(_, elem) => elem.into_bytes(locs),
-            ProgElement::Instr(op) => Vec::from(op.to_be_bytes()),
-            ProgElement::Data(data) => data.clone(),
-            ProgElement::Jump(loc) => {
-                if let Some(addr) = locs.get(loc) {
-                    Vec::from((0x1000 | addr).to_be_bytes())
-                } else {
-                    panic!("Jump to undefined location: {}", loc);
-                }
+            ProgElement::LabelInstr(_, elem) => elem.size(),
+            ProgElement::Data(bytes) => bytes.len(),
+            ProgElement::Instr(_) | ProgElement::WithOperand(..) => 2,
+            ProgElement::Values(values) => values.iter().map(|v| v.bits as usize / 8).sum(),
+            ProgElement::Text(text, _) => text.chars().count(),
+            _ => 0,
+        }
+    }
+
+    /// Add the name this element defines to `defs` and every name it uses to `uses`.
+    fn names<'a>(&'a self, defs: &mut Vec<&'a Label>, uses: &mut Vec<&'a Label>) {
+        let operands: Vec<&Operand> = match self {
+            ProgElement::LabelInstr(lbl, elem) => {
+                defs.push(lbl);
+                return elem.names(defs, uses);
+            }
+            ProgElement::Const(lbl, expr, _) => {
+                defs.push(lbl);
+                return expr.names(uses);
+            }
+            ProgElement::WithOperand(_, operand)
+            | ProgElement::Org(operand)
+            | ProgElement::Align(operand)
+            | ProgElement::Reserve(operand) => vec![operand],
+            ProgElement::Fill(count, value) => [Some(count), value.as_ref()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            ProgElement::Values(values) => values.iter().collect(),
+            ProgElement::Charmap(_, _, first) => first.iter().collect(),
+            ProgElement::Data(_) | ProgElement::Instr(_) | ProgElement::Text(..) => Vec::new(),
+        };
+        for operand in operands {
+            operand.expr.names(uses);
+        }
+    }
+
+    /// The fields of this element that the linker has to add an address to when it's part
+    /// of an object file, as their offset in the element, their width and what they're
+    /// relative to. Only 12-bit addresses and words can be moved; every other operand
+    /// must be a number.
+    fn relocations<'a>(
+        &'a self,
+        symbols: &Symbols<'a>,
+    ) -> Result<Vec<(usize, Field, Base<'a>)>, Diagnostic> {
+        let fixed = |operand: &Operand| match operand.base(symbols)? {
+            Base::Absolute => Ok(()),
+            _ => Err(Diagnostic::error(
+                String::from("only 12-bit addresses and words can be relocated by the linker"),
+                Some(operand.span),
+            )),
+        };
+        let mut found = Vec::new();
+        match self {
+            ProgElement::LabelInstr(_, elem) => return elem.relocations(symbols),
+            ProgElement::WithOperand(_, operand) if operand.bits == 12 => {
+                found.push((0, Field::Addr12, operand.base(symbols)?));
             }
-            ProgElement::Call(loc) => {
-                if let Some(addr) = locs.get(loc) {
-                    Vec::from((0x2000 | addr).to_be_bytes())
-                } else {
-                    panic!("Call to undefined location: {}", loc);
+            ProgElement::Values(values) => {
+                let mut offset = 0;
+                for value in values {
+                    if value.bits == 16 {
+                        found.push((offset, Field::Word, value.base(symbols)?));
+                    } else {
+                        fixed(value)?;
+                    }
+                    offset += value.bits as usize / 8;
                 }
             }
-            ProgElement::JumpV(loc) => {
-                if let Some(addr) = locs.get(loc) {
-                    Vec::from((0xb000 | addr).to_be_bytes())
-                } else {
-                    panic!("Jump to undefined location: {}", loc);
+            ProgElement::Org(operand) => {
+                return Err(Diagnostic::error(
+                    String::from("org can't be used in an object file"),
+                    Some(operand.span),
+                ))
+            }
+            ProgElement::WithOperand(_, operand)
+            | ProgElement::Align(operand)
+            | ProgElement::Reserve(operand)
+            | ProgElement::Fill(operand, None)
+            | ProgElement::Charmap(_, _, Some(operand)) => fixed(operand)?,
+            ProgElement::Fill(count, Some(value)) => {
+                fixed(count)?;
+                fixed(value)?;
+            }
+            ProgElement::Data(_)
+            | ProgElement::Instr(_)
+            | ProgElement::Const(..)
+            | ProgElement::Text(..)
+            | ProgElement::Charmap(_, _, None) => (),
+        }
+        found.retain(|(_, _, base)| *base != Base::Absolute);
+        Ok(found)
+    }
+
+    fn kind(&self) -> StatementKind {
+        match self {
+            ProgElement::LabelInstr(_, elem) => elem.kind(),
+            ProgElement::Instr(_) | ProgElement::WithOperand(..) => StatementKind::Instruction,
+            ProgElement::Data(_)
+            | ProgElement::Values(_)
+            | ProgElement::Text(..)
+            | ProgElement::Fill(..) => StatementKind::Data,
+            ProgElement::Reserve(_) => StatementKind::Reserve,
+            _ => StatementKind::Directive,
+        }
+    }
+
+    /// The bytes of this element, which was given `len` bytes when laid out, with text
+    /// stored using `charmap`.
+    fn into_bytes(
+        &self,
+        symbols: &Symbols,
+        charmap: &Charmap,
+        len: usize,
+    ) -> Result<Vec<u8>, Diagnostic> {
+        match self {
+            ProgElement::LabelInstr(_, elem) => elem.into_bytes(symbols, charmap, len),
+            ProgElement::Instr(op) => Ok(Vec::from(op.to_be_bytes())),
+            ProgElement::Data(data) => Ok(data.clone()),
+            ProgElement::WithOperand(op, operand) => {
+                Ok(Vec::from((op | operand.value(symbols)?).to_be_bytes()))
+            }
+            ProgElement::Const(_, expr, span) => expr.eval(symbols, *span).map(|_| Vec::new()),
+            ProgElement::Fill(_, Some(value)) => Ok(vec![value.value(symbols)? as u8; len]),
+            ProgElement::Fill(_, None) | ProgElement::Align(_) => Ok(vec![0; len]),
+            ProgElement::Values(values) => {
+                let mut bytes = Vec::new();
+                for value in values {
+                    let width = value.bits as usize / 8;
+                    bytes.extend_from_slice(&value.value(symbols)?.to_be_bytes()[2 - width..]);
                 }
+                Ok(bytes)
+            }
+            ProgElement::Text(text, span) => charmap.encode(text, *span),
+            ProgElement::Org(_) | ProgElement::Reserve(_) | ProgElement::Charmap(..) => {
+                Ok(Vec::new())
             }
         }
     }
@@ -56,106 +216,951 @@ impl ProgElement {
 #[cfg(test)]
 mod into_bytes_tests {
     use super::*;
+    use expr::BinOp;
     use rstest::*;
 
-    #[fixture]
-    fn empty_locs() -> HashMap<String, u16> {
-        HashMap::new()
+    fn label(name: &str) -> Label {
+        Label {
+            name: String::from(name),
+            span: Span::new(0, 4, 9),
+        }
+    }
+
+    fn operand(expr: Expr, bits: u32) -> Operand {
+        Operand::new(expr, Span::new(0, 4, 16), bits)
+    }
+
+    fn offset(name: &str, offset: i64) -> Expr {
+        Expr::binary(BinOp::Add, Expr::Name(label(name)), Expr::Num(offset))
     }
 
     #[rstest]
-    fn test_instr_into_bytes(empty_locs: HashMap<String, u16>) {
+    fn test_instr_into_bytes() {
         let inst = ProgElement::Instr(0xa1b2);
-        assert_eq!(vec![0xa1, 0xb2], inst.into_bytes(&empty_locs));
+        assert_eq!(
+            vec![0xa1, 0xb2],
+            inst.into_bytes(&Symbols::new(), &Charmap::new(), 2)
+                .unwrap()
+        );
     }
 
     #[rstest]
-    fn test_data_into_bytes(empty_locs: HashMap<String, u16>) {
+    fn test_data_into_bytes() {
         let inst = ProgElement::Data(vec![1, 2, 3, 4, 5]);
-        assert_eq!(vec![1, 2, 3, 4, 5], inst.into_bytes(&empty_locs));
+        assert_eq!(
+            vec![1, 2, 3, 4, 5],
+            inst.into_bytes(&Symbols::new(), &Charmap::new(), 2)
+                .unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::jump(0x1000, 0x123, vec![0x11, 0x23])]
+    #[case::call(0x2000, 0xa14, vec![0x2a, 0x14])]
+    #[case::jumpv(0xb000, 0x33e, vec![0xb3, 0x3e])]
+    #[case::set_index(0xa000, 0x3f0, vec![0xa3, 0xf0])]
+    #[case::machine_inst(0x0000, 0x3f0, vec![0x03, 0xf0])]
+    fn test_addressed_into_bytes(#[case] op: u16, #[case] addr: u16, #[case] expected: Vec<u8>) {
+        let mut symbols = Symbols::new();
+        symbols.define("test", Symbol::Address(addr));
+        let inst = ProgElement::WithOperand(op, operand(Expr::Name(label("test")), 12));
+        assert_eq!(
+            expected,
+            inst.into_bytes(&symbols, &Charmap::new(), 2).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(0x0a, vec![0xa3, 0x0a])]
+    #[case(-0x100, vec![0xa2, 0x00])]
+    fn test_label_offset(#[case] off: i64, #[case] expected: Vec<u8>) {
+        let mut symbols = Symbols::new();
+        symbols.define("test", Symbol::Address(0x300));
+        let inst = ProgElement::WithOperand(0xa000, operand(offset("test", off), 12));
+        assert_eq!(
+            expected,
+            inst.into_bytes(&symbols, &Charmap::new(), 2).unwrap()
+        );
     }
 
     #[rstest]
-    fn test_jump_into_bytes(mut empty_locs: HashMap<String, u16>) {
-        empty_locs.insert(String::from("test"), 0x123);
-        let inst = ProgElement::Jump(String::from("test"));
-        assert_eq!(vec![0x11, 0x23], inst.into_bytes(&empty_locs));
+    #[case(0xd00, "#1000 doesn't fit in 12 bits (the most is #fff)")]
+    #[case(-0x301, "-#1 doesn't fit in 12 bits (the least is #0)")]
+    fn test_offset_out_of_memory(#[case] off: i64, #[case] expected: &str) {
+        let mut symbols = Symbols::new();
+        symbols.define("test", Symbol::Address(0x300));
+        let inst = ProgElement::WithOperand(0x1000, operand(offset("test", off), 12));
+        let err = inst.into_bytes(&symbols, &Charmap::new(), 2).unwrap_err();
+        assert_eq!(expected, err.message);
+        assert_eq!(Some(Span::new(0, 4, 16)), err.span);
     }
 
     #[rstest]
-    fn test_call_into_bytes(mut empty_locs: HashMap<String, u16>) {
-        empty_locs.insert(String::from("test"), 0xa14);
-        let inst = ProgElement::Call(String::from("test"));
-        assert_eq!(vec![0x2a, 0x14], inst.into_bytes(&empty_locs));
+    fn test_byte_operand_into_bytes() {
+        let inst = ProgElement::WithOperand(0x6100, operand(Expr::Num(-2), 8));
+        assert_eq!(
+            vec![0x61, 0xfe],
+            inst.into_bytes(&Symbols::new(), &Charmap::new(), 2)
+                .unwrap()
+        );
     }
 
     #[rstest]
-    fn test_jumpv_into_bytes(mut empty_locs: HashMap<String, u16>) {
-        empty_locs.insert(String::from("test"), 0x33e);
-        let inst = ProgElement::JumpV(String::from("test"));
-        assert_eq!(vec![0xb3, 0x3e], inst.into_bytes(&empty_locs));
+    fn test_undefined_label() {
+        let inst = ProgElement::WithOperand(0x2000, operand(Expr::Name(label("$missing")), 12));
+        let err = inst
+            .into_bytes(&Symbols::new(), &Charmap::new(), 2)
+            .unwrap_err();
+        assert_eq!("undefined label: $missing", err.message);
+        assert_eq!(Some(Span::new(0, 4, 9)), err.span);
     }
 }
 
-fn label_addresses(elems: &Vec<ProgElement>) -> HashMap<String, u16> {
-    let mut addrs = HashMap::new();
-    let mut pc = 0x200;
+/// Where an element of a program was placed.
+struct Placement {
+    /// Address of the first byte.
+    start: usize,
+    len: usize,
+    /// Index of the part of the program, started by an `org`, holding the element.
+    region: usize,
+}
+
+/// Where every element of a program goes and what its names stand for.
+struct Layout<'a> {
+    symbols: Symbols<'a>,
+    placements: Vec<Placement>,
+    /// The `org` operand starting each region, with none for the first.
+    regions: Vec<Option<Span>>,
+    /// The address the ROM is loaded at.
+    start: usize,
+}
+
+/// Give every element an address and every label its value, collecting every constant
+/// and reporting names defined more than once.
+///
+/// The operands of layout directives are worked out as they're met, so can only use
+/// labels that come before them.
+fn layout<'a>(elems: &'a [ProgElement], diags: &mut Vec<Diagnostic>) -> Layout<'a> {
+    let mut symbols = Symbols::new();
+    let mut defs: HashMap<&str, Span> = HashMap::new();
+    let mut labels = Vec::new();
     for elem in elems {
-        if let ProgElement::LabelInstr(lbl, _) = elem {
-            if addrs.contains_key(lbl) {
-                panic!("Duplicate label: {}", lbl);
+        let (lbl, symbol) = match elem {
+            ProgElement::LabelInstr(lbl, _) => (lbl, Symbol::Unplaced),
+            ProgElement::Const(lbl, expr, span) => (lbl, Symbol::Const(expr, *span)),
+            _ => {
+                labels.push(None);
+                continue;
+            }
+        };
+        if let Some(first) = defs.get(lbl.name.as_str()) {
+            diags.push(
+                Diagnostic::error(format!("duplicate label: {}", lbl.name), Some(lbl.span))
+                    .with_note(Some(*first), String::from("first defined here")),
+            );
+            labels.push(None);
+        } else {
+            defs.insert(&lbl.name, lbl.span);
+            symbols.define(&lbl.name, symbol);
+            labels.push(Some(lbl.name.as_str()));
+        }
+    }
+
+    let mut layout = Layout {
+        symbols,
+        placements: Vec::new(),
+        regions: vec![None],
+        start: 0x200,
+    };
+    let mut pc: usize = 0x200;
+    let mut placed_any = false;
+    for (elem, label) in elems.iter().zip(labels) {
+        let inner = match elem {
+            ProgElement::LabelInstr(_, inner) => inner.as_ref(),
+            _ => elem,
+        };
+        let mut value = |operand: &Operand| match operand.value(&layout.symbols) {
+            Ok(value) => value as usize,
+            Err(diag) => {
+                diags.push(diag);
+                0
             }
-            addrs.insert(lbl.clone(), pc as u16);
+        };
+        let (start, len, label_addr) = match inner {
+            ProgElement::Org(operand) => {
+                let addr = value(operand);
+                if !placed_any {
+                    layout.start = addr;
+                }
+                layout.regions.push(Some(operand.span));
+                (addr, 0, addr)
+            }
+            ProgElement::Align(n) => match n.value(&layout.symbols) {
+                Err(diag) => {
+                    diags.push(diag);
+                    (pc, 0, pc)
+                }
+                Ok(0) => {
+                    diags.push(Diagnostic::error(
+                        String::from("can't align to a multiple of 0"),
+                        Some(n.span),
+                    ));
+                    (pc, 0, pc)
+                }
+                Ok(n) => {
+                    let n = n as usize;
+                    let aligned = pc.div_ceil(n) * n;
+                    (pc, aligned - pc, aligned)
+                }
+            },
+            ProgElement::Fill(count, _) | ProgElement::Reserve(count) => (pc, value(count), pc),
+            _ => (pc, inner.size(), pc),
+        };
+        if len > 0 || matches!(inner, ProgElement::Reserve(_)) {
+            placed_any = true;
         }
-        match elem {
-            ProgElement::Data(bytes) => pc += bytes.len(),
-            _ => pc += 2,
+        if let Some(name) = label {
+            if !matches!(elem, ProgElement::Const(..)) {
+                layout
+                    .symbols
+                    .define(name, Symbol::Address(label_addr.min(0xffff) as u16));
+            }
         }
+        layout.placements.push(Placement {
+            start,
+            len,
+            region: layout.regions.len() - 1,
+        });
+        pc = start + len;
     }
-    addrs
+    layout
 }
 
 #[cfg(test)]
-mod label_addresses_tests {
+mod layout_tests {
     use super::*;
     use rstest::*;
 
+    fn label(name: &str, start: usize) -> Label {
+        Label {
+            name: String::from(name),
+            span: Span::new(0, start, start + name.len()),
+        }
+    }
+
+    fn address(symbols: &Symbols, name: &str) -> Option<u16> {
+        match symbols.get(name) {
+            Some(Symbol::Address(addr)) => Some(*addr),
+            _ => None,
+        }
+    }
+
     #[rstest]
     fn test_no_duplicate_labels() {
         let elems = vec![
             ProgElement::Instr(0),
-            ProgElement::LabelInstr(String::from("l1"), Box::new(ProgElement::Instr(0))),
+            ProgElement::LabelInstr(label("l1", 0), Box::new(ProgElement::Instr(0))),
             ProgElement::Data(vec![2, 4]),
-            ProgElement::LabelInstr(String::from("l2"), Box::new(ProgElement::Instr(0))),
-            ProgElement::Jump(String::from("l3")),
+            ProgElement::LabelInstr(label("l2", 10), Box::new(ProgElement::Instr(0))),
+            ProgElement::WithOperand(
+                0x1000,
+                Operand::new(Expr::Name(label("l3", 20)), Span::new(0, 20, 22), 12),
+            ),
+        ];
+
+        let mut diags = Vec::new();
+        let symbols = layout(&elems, &mut diags).symbols;
+        assert!(diags.is_empty());
+        assert_eq!(2, symbols.len());
+        assert_eq!(Some(0x202), address(&symbols, "l1"));
+        assert_eq!(Some(0x206), address(&symbols, "l2"));
+    }
+
+    #[rstest]
+    fn test_labelled_data_size() {
+        let elems = vec![
+            ProgElement::LabelInstr(label("l1", 0), Box::new(ProgElement::Data(vec![1]))),
+            ProgElement::LabelInstr(label("l2", 10), Box::new(ProgElement::Instr(0))),
+        ];
+
+        let symbols = layout(&elems, &mut Vec::new()).symbols;
+        assert_eq!(Some(0x201), address(&symbols, "l2"));
+    }
+
+    #[rstest]
+    fn test_constants_take_no_space() {
+        let elems = vec![
+            ProgElement::Const(label("c", 0), Expr::Num(3), Span::new(0, 6, 7)),
+            ProgElement::LabelInstr(label("l", 10), Box::new(ProgElement::Instr(0))),
         ];
 
-        let lbls = label_addresses(&elems);
-        assert_eq!(2, lbls.len());
-        let k1 = String::from("l1");
-        assert!(lbls.contains_key(&k1));
-        let k2 = String::from("l2");
-        assert!(lbls.contains_key(&k2));
+        let symbols = layout(&elems, &mut Vec::new()).symbols;
+        assert!(matches!(
+            symbols.get("c"),
+            Some(Symbol::Const(Expr::Num(3), _))
+        ));
+        assert_eq!(Some(0x200), address(&symbols, "l"));
     }
 
     #[rstest]
-    #[should_panic]
     fn test_duplicate_labels() {
         let elems = vec![
-            ProgElement::LabelInstr(String::from("l1"), Box::new(ProgElement::Instr(0))),
-            ProgElement::LabelInstr(String::from("l1"), Box::new(ProgElement::Instr(0))),
+            ProgElement::LabelInstr(label("l1", 0), Box::new(ProgElement::Instr(0))),
+            ProgElement::Const(label("l1", 10), Expr::Num(3), Span::new(0, 16, 17)),
         ];
 
-        label_addresses(&elems);
+        let mut diags = Vec::new();
+        let symbols = layout(&elems, &mut diags).symbols;
+        assert_eq!(Some(0x200), address(&symbols, "l1"));
+        assert_eq!(1, diags.len());
+        assert_eq!("duplicate label: l1", diags[0].message);
+        assert_eq!(Some(Span::new(0, 10, 12)), diags[0].span);
+        assert_eq!(Some(Span::new(0, 0, 2)), diags[0].notes[0].0);
+    }
+}
+
+/// What a statement puts in the ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatementKind {
+    Instruction,
+    Data,
+    /// Constants, layout directives and other statements that give no bytes of their own.
+    Directive,
+    /// Space set aside by `reserve`, whose bytes have no value.
+    Reserve,
+}
+
+/// A statement of the source and where it was placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub addr: usize,
+    pub len: usize,
+    pub span: Span,
+    pub kind: StatementKind,
+}
+
+/// An assembled program, with where each part of it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembled {
+    pub rom: Vec<u8>,
+    /// The address the ROM is loaded at.
+    pub start: usize,
+    /// Every statement in the order written.
+    pub statements: Vec<Statement>,
+    /// Every label and its address, in the order defined.
+    pub labels: Vec<(String, u16)>,
+}
+
+/// Resolve labels and produce the bytes of a program, given as its statements and their
+/// spans, collecting every error found.
+///
+/// The ROM runs from the load address, which is 200 unless the program starts with an
+/// `org`, up to the last byte given a value.
+pub fn process_prog(prog: Vec<(ProgElement, Span)>) -> Result<Assembled, Vec<Diagnostic>> {
+    process(prog, false).map(|(assembled, _)| assembled)
+}
+
+/// Assemble a program like `process_prog`, or as a module of an object file if `object`
+/// is set. A module can use labels it doesn't define, which the linker finds in other
+/// modules, and every field that holds an address is given to the linker to fix.
+fn process(
+    prog: Vec<(ProgElement, Span)>,
+    object: bool,
+) -> Result<(Assembled, Vec<Relocation>), Vec<Diagnostic>> {
+    let (prog, spans): (Vec<_>, Vec<_>) = prog.into_iter().unzip();
+    let mut diags = Vec::new();
+    let mut layout = layout(&prog, &mut diags);
+    if object {
+        let mut uses = Vec::new();
+        for elem in &prog {
+            elem.names(&mut Vec::new(), &mut uses);
+        }
+        for lbl in uses {
+            if layout.symbols.get(&lbl.name).is_none() {
+                layout.symbols.define(&lbl.name, Symbol::External);
+            }
+        }
+    }
+    let mut relocations = Vec::new();
+    let mut memory = vec![0; MEMORY_SIZE];
+    let mut owners: Vec<Option<usize>> = vec![None; MEMORY_SIZE];
+    let mut reported = HashSet::new();
+    let mut end = layout.start;
+    let mut charmap = Charmap::new();
+    for (elem, place) in prog.iter().zip(&layout.placements) {
+        let inner = match elem {
+            ProgElement::LabelInstr(_, inner) => inner.as_ref(),
+            _ => elem,
+        };
+        // A character map applies to the text after it.
+        if let ProgElement::Charmap(chars, span, first) = inner {
+            let first = first.as_ref().map_or(Ok(0), |f| f.value(&layout.symbols));
+            if let Err(diag) = first.and_then(|f| charmap.set(chars, f as u8, *span)) {
+                diags.push(diag);
+            }
+        }
+        let bytes = elem
+            .into_bytes(&layout.symbols, &charmap, place.len)
+            .unwrap_or_else(|diag| {
+                diags.push(diag);
+                vec![0; place.len]
+            });
+        // Anything placed before the start is reported below, and has no offset.
+        if object && place.start >= layout.start {
+            match elem.relocations(&layout.symbols) {
+                Ok(found) => relocations.extend(found.into_iter().map(|(offset, field, base)| {
+                    let symbol = match base {
+                        Base::External(name) => Some(String::from(name)),
+                        _ => None,
+                    };
+                    Relocation {
+                        offset: place.start + offset - layout.start,
+                        field,
+                        symbol,
+                    }
+                })),
+                Err(diag) => diags.push(diag),
+            }
+        }
+        // Reserved bytes are claimed but given no value.
+        for (i, addr) in (place.start..place.start + place.len).enumerate() {
+            let clash = if addr >= MEMORY_SIZE {
+                Some((
+                    None,
+                    String::from("the program runs past the end of memory"),
+                ))
+            } else if addr < layout.start {
+                let message = format!(
+                    "#{:x} is before the start of the program at #{:x}",
+                    addr, layout.start
+                );
+                Some((None, message))
+            } else {
+                match owners[addr] {
+                    Some(owner) if owner != place.region => {
+                        let message = format!(
+                            "#{:x} is already used by an earlier part of the program",
+                            addr
+                        );
+                        Some((Some(owner), message))
+                    }
+                    _ => None,
+                }
+            };
+            match clash {
+                Some((owner, message)) => {
+                    if reported.insert((place.region, owner)) {
+                        let mut diag = Diagnostic::error(message, layout.regions[place.region]);
+                        if let Some(Some(span)) = owner.map(|o| layout.regions[o]) {
+                            diag = diag.with_note(Some(span), String::from("which starts here"));
+                        }
+                        diags.push(diag);
+                    }
+                    if addr >= MEMORY_SIZE {
+                        break;
+                    }
+                }
+                None => {
+                    owners[addr] = Some(place.region);
+                    if let Some(byte) = bytes.get(i) {
+                        memory[addr] = *byte;
+                        end = end.max(addr + 1);
+                    }
+                }
+            }
+        }
+    }
+    if !diags.is_empty() {
+        return Err(diags);
+    }
+    let statements = layout
+        .placements
+        .iter()
+        .zip(spans)
+        .zip(&prog)
+        .map(|((place, span), elem)| Statement {
+            addr: place.start,
+            len: place.len,
+            span,
+            kind: elem.kind(),
+        })
+        .collect();
+    let labels = prog
+        .iter()
+        .filter_map(|elem| match elem {
+            ProgElement::LabelInstr(lbl, _) => match layout.symbols.get(&lbl.name) {
+                Some(Symbol::Address(addr)) => Some((lbl.name.clone(), *addr)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    memory.truncate(end);
+    let assembled = Assembled {
+        rom: memory.split_off(layout.start),
+        start: layout.start,
+        statements,
+        labels,
+    };
+    Ok((assembled, relocations))
+}
+
+/// The labels and constants defined in a program and the uses of each, with spans
+/// pointing into the original sources.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Names {
+    pub defs: Vec<Label>,
+    pub uses: Vec<Label>,
+}
+
+/// Find the names defined and used in the source of `file`, reading any files it includes
+/// with `load` and adding them to `sources`.
+///
+/// Parsing carries on past errors, so names are found in source that doesn't assemble.
+/// A label local to a macro is found once, in the macro, without the suffix given to it
+/// for each use.
+pub fn names(sources: &mut Sources, file: FileId, load: Loader) -> Names {
+    let expanded = preprocess::expand(sources, file, load, &mut Vec::new());
+    let prog = c8asm::ProgramParser::new()
+        .parse(file, &mut Vec::new(), &expanded.text)
+        .unwrap_or_default();
+    let (mut defs, mut uses) = (Vec::new(), Vec::new());
+    for (elem, _) in &prog {
+        elem.names(&mut defs, &mut uses);
+    }
+    let original = |labels: Vec<&Label>| {
+        let mut labels: Vec<Label> = labels
+            .into_iter()
+            .map(|lbl| {
+                let name = lbl.name.split('@').next().unwrap_or_default();
+                let start = expanded.origin(lbl.span);
+                Label {
+                    name: String::from(name),
+                    span: Span::new(start.file, start.start, start.start + name.len()),
+                }
+            })
+            .collect();
+        labels.sort_by_key(|lbl| (lbl.span.file, lbl.span.start));
+        labels.dedup();
+        labels
+    };
+    Names {
+        defs: original(defs),
+        uses: original(uses),
+    }
+}
+
+/// Parse c8asm source and assemble it into ROM bytes.
+///
+/// Parsing carries on after syntax errors so that every error in the program is reported.
+/// The source can't include other files.
+pub fn assemble(input: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut sources = Sources::new();
+    let file = sources.add("", input);
+    let assembled = assemble_sources(&mut sources, file, &|path| {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("can't include {}", path.display()),
+        ))
+    })?;
+    Ok(assembled.rom)
+}
+
+/// Assemble the source of `file`, reading any files it includes with `load` and adding
+/// them to `sources`. The spans of the statements point into `sources`.
+pub fn assemble_sources(
+    sources: &mut Sources,
+    file: FileId,
+    load: Loader,
+) -> Result<Assembled, Vec<Diagnostic>> {
+    assemble_with(sources, file, load, false).map(|(assembled, _)| assembled)
+}
+
+/// Assemble the source of `file` into a module of an object file, for `object::link` to
+/// join with others. Labels it uses but doesn't define are looked for in the other
+/// modules when they're linked.
+pub fn assemble_object(
+    sources: &mut Sources,
+    file: FileId,
+    load: Loader,
+) -> Result<Object, Vec<Diagnostic>> {
+    let (assembled, relocations) = assemble_with(sources, file, load, true)?;
+    Object::new(assembled, relocations).map_err(|e| vec![Diagnostic::error(e, None)])
+}
+
+fn assemble_with(
+    sources: &mut Sources,
+    file: FileId,
+    load: Loader,
+    object: bool,
+) -> Result<(Assembled, Vec<Relocation>), Vec<Diagnostic>> {
+    let mut pre_diags = Vec::new();
+    let expanded = preprocess::expand(sources, file, load, &mut pre_diags);
+    let mut diags = Vec::new();
+    let result = match c8asm::ProgramParser::new().parse(file, &mut diags, &expanded.text) {
+        Ok(code) => process(code, object),
+        Err(ParseError::InvalidToken { location }) => Err(vec![Diagnostic::invalid_token(
+            &expanded.text,
+            location,
+            file,
+        )]),
+        Err(e) => Err(vec![Diagnostic::from_parse_error(e, file)]),
+    };
+    match result {
+        Ok((mut assembled, relocations)) if diags.is_empty() && pre_diags.is_empty() => {
+            for statement in &mut assembled.statements {
+                statement.span = expanded.origin(statement.span);
+            }
+            Ok((assembled, relocations))
+        }
+        Ok(_) => Err(finish(diags, &expanded, pre_diags)),
+        Err(mut errs) => {
+            diags.append(&mut errs);
+            Err(finish(diags, &expanded, pre_diags))
+        }
+    }
+}
+
+/// Move diagnostics found in the expanded source back to the original and put them in
+/// order.
+fn finish(
+    diags: Vec<Diagnostic>,
+    expanded: &preprocess::Expanded,
+    mut pre_diags: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    let mut diags: Vec<Diagnostic> = diags.into_iter().map(|d| expanded.map(d)).collect();
+    diags.append(&mut pre_diags);
+    diags.sort_by_key(|d| d.span.map(|s| (s.file, s.start)));
+    // An error inside a constant is found again wherever the constant is used.
+    diags.dedup();
+    diags
+}
+
+/// Read a number literal written in `radix`, reporting one too large to use anywhere.
+pub(crate) fn literal(diags: &mut Vec<Diagnostic>, span: Span, digits: &str, radix: u32) -> Expr {
+    match i64::from_str_radix(digits, radix) {
+        Ok(n) => Expr::Num(n),
+        Err(_) => {
+            diags.push(Diagnostic::error(
+                String::from("number is too large"),
+                Some(span),
+            ));
+            Expr::Num(0)
+        }
+    }
+}
+
+/// Read a string of hex digit pairs as bytes.
+pub(crate) fn hex_bytes(diags: &mut Vec<Diagnostic>, span: Span, digits: &str) -> Vec<u8> {
+    if digits.len() % 2 == 1 {
+        diags.push(Diagnostic::error(
+            String::from("data must be whole bytes of two hex digits each"),
+            Some(span),
+        ));
     }
+    (0..digits.len() / 2)
+        .map(|i| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Read the rows of a sprite drawn with `X` for each pixel set and `.` for each clear,
+/// left to right.
+pub(crate) fn sprite_rows(diags: &mut Vec<Diagnostic>, rows: Vec<(&str, Span)>) -> Vec<u8> {
+    rows.into_iter()
+        .map(|(row, span)| {
+            if row.len() > 8 {
+                diags.push(Diagnostic::error(
+                    String::from("sprite rows can be at most 8 pixels wide"),
+                    Some(span),
+                ));
+            }
+            row.bytes()
+                .take(8)
+                .enumerate()
+                .filter(|(_, c)| *c == b'X')
+                .fold(0, |byte, (i, _)| byte | 0x80 >> i)
+        })
+        .collect()
 }
 
-pub fn process_prog(prog: Vec<ProgElement>) -> Vec<u8> {
-    let lbls = label_addresses(&prog);
-    let mut bytes = Vec::new();
-    for elem in prog {
-        let mut elem_bytes = elem.into_bytes(&lbls);
-        bytes.append(&mut elem_bytes);
+#[cfg(test)]
+mod assemble_tests {
+    use super::*;
+    use rstest::*;
+
+    fn messages(src: &str) -> Vec<(usize, String)> {
+        assemble(src)
+            .unwrap_err()
+            .into_iter()
+            .map(|d| (d.span.unwrap().start, d.message))
+            .collect()
+    }
+
+    #[rstest]
+    fn test_assemble() {
+        let src = "$a mov V1, #2\n   draw V1, V1, #f\n   jmp $a\n";
+        assert_eq!(
+            vec![0x61, 0x02, 0xd1, 0x1f, 0x12, 0x00],
+            assemble(src).unwrap()
+        );
+    }
+
+    #[rstest]
+    fn test_label_operands() {
+        let src = "mov I, $spr + #2\nmc $spr\njmp $spr - #1\n$spr data: 0102\n";
+        assert_eq!(
+            vec![0xa2, 0x08, 0x02, 0x06, 0x12, 0x05, 0x01, 0x02],
+            assemble(src).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case::hex("mov V0, #1f", 0x1f)]
+    #[case::prefixed_hex("mov V0, 0x1f", 0x1f)]
+    #[case::decimal("mov V0, 31", 0x1f)]
+    #[case::binary("mov V0, 0b01100110", 0x66)]
+    #[case::char("mov V0, 'A'", 0x41)]
+    #[case::negative("mov V0, -1", 0xff)]
+    #[case::precedence("mov V0, 1 + 2 * 3", 7)]
+    #[case::parentheses("mov V0, (1 + 2) * 3", 9)]
+    #[case::shifts("mov V0, 1 << 4 | 3 >> 1", 0x11)]
+    #[case::and_before_or("mov V0, 6 & 3 | 8", 0xa)]
+    #[case::left_associative("mov V0, 10 - 4 - 3", 3)]
+    fn test_expressions(#[case] src: &str, #[case] value: u8) {
+        assert_eq!(vec![0x60, value], assemble(src).unwrap());
+    }
+
+    #[rstest]
+    fn test_constants() {
+        let src = "const $rows = $height / 8\n$height equ 32\nmov V0, $rows\n\
+                   mov I, $end - $height\n$end data: 00\n";
+        assert_eq!(vec![0x60, 0x04, 0xa1, 0xe4, 0x00], assemble(src).unwrap());
+    }
+
+    #[rstest]
+    fn test_data_digits() {
+        let src = "data: 12\ndata: 0b01\ndata: 00e0\n";
+        assert_eq!(vec![0x12, 0x0b, 0x01, 0x00, 0xe0], assemble(src).unwrap());
+    }
+
+    #[rstest]
+    fn test_constant_errors() {
+        let src = "$a equ $b\n$b equ $a + 1\nmov V0, $a\ndraw V0, V0, 16\ndata: 123\n";
+        assert_eq!(
+            vec![
+                (7, String::from("$b is defined in terms of itself")),
+                (17, String::from("$a is defined in terms of itself")),
+                (
+                    48,
+                    String::from("#10 doesn't fit in 4 bits (the most is #f)")
+                ),
+                (
+                    57,
+                    String::from("data must be whole bytes of two hex digits each")
+                ),
+            ],
+            messages(src)
+        );
+    }
+
+    #[rstest]
+    fn test_macros() {
+        let src = "\
+macro print_bcd $r, $x, $y
+          mov      I, $scratch
+          bcd      $r
+          load     V2
+          mov      Va, $x
+          mov      Vb, $y
+$digits   sprite   V0
+          draw     Va, Vb, 5
+          jmp      $digits
+endm
+$main     print_bcd V5, 8, 2 * 4
+          print_bcd V6, 20, 8
+$scratch  data:    000000
+";
+        let rom = assemble(src).unwrap();
+        assert_eq!(0x23, rom.len());
+        assert_eq!([0xa2, 0x20, 0xf5, 0x33], rom[0..4]);
+        assert_eq!([0x6b, 0x08, 0xf0, 0x29, 0xda, 0xb5, 0x12, 0x0a], rom[8..16]);
+        assert_eq!([0xf6, 0x33], rom[18..20]);
+        assert_eq!([0x6a, 0x14, 0x6b, 0x08], rom[22..26]);
+        assert_eq!([0x12, 0x1a], rom[30..32]);
+    }
+
+    #[rstest]
+    fn test_macro_errors_point_at_use_and_definition() {
+        let src = "macro set $v\nmov V0, $v\nendm\nset #100\n";
+        let diags = assemble(src).unwrap_err();
+        assert_eq!(1, diags.len());
+        assert_eq!(
+            "#100 doesn't fit in 8 bits (the most is #ff)",
+            diags[0].message
+        );
+        assert_eq!(Some(Span::new(0, 33, 37)), diags[0].span);
+        assert_eq!(
+            vec![
+                (
+                    Some(Span::new(0, 6, 9)),
+                    String::from("macro `set` is defined here")
+                ),
+                (
+                    Some(Span::new(0, 29, 37)),
+                    String::from("in this use of macro `set`")
+                ),
+            ],
+            diags[0].notes
+        );
+    }
+
+    #[rstest]
+    #[case::org_start("org #600\nclr\n", vec![0x00, 0xe0])]
+    #[case::org_gap("clr\norg #204\nretn\n", vec![0x00, 0xe0, 0, 0, 0x00, 0xee])]
+    #[case::org_label("jmp $a\n$a org #204\nclr\n", vec![0x12, 0x04, 0, 0, 0x00, 0xe0])]
+    #[case::align("data: 01\n$a align 2\njmp $a\n", vec![0x01, 0x00, 0x12, 0x02])]
+    #[case::aligned("clr\nalign 2\nclr\n", vec![0x00, 0xe0, 0x00, 0xe0])]
+    #[case::fill("fill 3, #ff\nspace 2\n", vec![0xff, 0xff, 0xff, 0, 0])]
+    #[case::reserve("mov I, $buf\n$buf reserve 8\n", vec![0xa2, 0x02])]
+    #[case::reserve_gap("reserve 2\nclr\n", vec![0, 0, 0x00, 0xe0])]
+    #[case::eti660("org #600\n$l jmp $l\n", vec![0x16, 0x00])]
+    fn test_layout_directives(#[case] src: &str, #[case] expected: Vec<u8>) {
+        assert_eq!(expected, assemble(src).unwrap());
+    }
+
+    #[rstest]
+    fn test_overlap() {
+        let diags = assemble("org #300\n$buf reserve 4\norg #302\ndata: 0102\n").unwrap_err();
+        assert_eq!(1, diags.len());
+        assert_eq!(
+            "#302 is already used by an earlier part of the program",
+            diags[0].message
+        );
+        assert_eq!(String::from("which starts here"), diags[0].notes[0].1);
+    }
+
+    #[rstest]
+    #[case(
+        "org #300\nclr\norg #2fe\nclr\n",
+        (17, "#2fe is before the start of the program at #300")
+    )]
+    #[case("org #ffe\ndata: 010203\n", (4, "the program runs past the end of memory"))]
+    #[case("align 0\n", (6, "can't align to a multiple of 0"))]
+    #[case("align 65535\n", (6, "#ffff doesn't fit in 12 bits (the most is #fff)"))]
+    #[case("org $a\n$a clr\n", (4, "the address of $a isn't known yet at this point"))]
+    fn test_layout_errors(#[case] src: &str, #[case] expected: (usize, &str)) {
+        assert_eq!(vec![(expected.0, String::from(expected.1))], messages(src));
+    }
+
+    #[rstest]
+    fn test_collects_all_errors() {
+        let src = "mov V1, #100\njmp $nowhere\n$a clr\n$a clr\nmc #1000\n";
+        assert_eq!(
+            vec![
+                (
+                    8,
+                    String::from("#100 doesn't fit in 8 bits (the most is #ff)")
+                ),
+                (17, String::from("undefined label: $nowhere")),
+                (33, String::from("duplicate label: $a")),
+                (
+                    43,
+                    String::from("#1000 doesn't fit in 12 bits (the most is #fff)")
+                ),
+            ],
+            messages(src)
+        );
+    }
+
+    #[rstest]
+    fn test_unknown_instruction() {
+        let diags = assemble("clr\n\tfoo V1, 2\nretn\n").unwrap_err();
+        assert_eq!(1, diags.len());
+        assert_eq!("unknown instruction or macro `foo`", diags[0].message);
+        assert_eq!(Some(Span::new(0, 5, 8)), diags[0].span);
+    }
+
+    #[rstest]
+    fn test_recovers_from_syntax_errors() {
+        let src = "mov V1 #2\nclr\nskipeq V1, V2, V3\nretn\n";
+        let diags = assemble(src).unwrap_err();
+        assert_eq!(2, diags.len());
+        assert_eq!("unexpected `#2`", diags[0].message);
+        assert!(diags[0].expected.contains(&String::from("`,`")));
+        assert_eq!(Some(Span::new(0, 7, 9)), diags[0].span);
+        assert_eq!("unexpected `,`", diags[1].message);
+    }
+
+    #[rstest]
+    #[case::comments("; setup\nclr ; clear\n// done\nretn // back\n", vec![0x00, 0xe0, 0x00, 0xee])]
+    #[case::blank_lines("\n\nclr\n\n\nretn", vec![0x00, 0xe0, 0x00, 0xee])]
+    #[case::crlf("clr\r\nretn\r\n", vec![0x00, 0xe0, 0x00, 0xee])]
+    #[case::char_literal("mov V0, ';' ; semicolon\n", vec![0x60, 0x3b])]
+    #[case::division("mov V0, 8/2 // half\n", vec![0x60, 0x04])]
+    #[case::empty("; nothing here\n", vec![])]
+    fn test_free_form(#[case] src: &str, #[case] expected: Vec<u8>) {
+        assert_eq!(expected, assemble(src).unwrap());
+    }
+
+    #[rstest]
+    fn test_label_on_its_own_line() {
+        let diags = assemble("$a\nclr\n").unwrap_err();
+        assert_eq!("unexpected end of line", diags[0].message);
+        assert_eq!(Some(Span::new(0, 2, 3)), diags[0].span);
+    }
+
+    #[rstest]
+    fn test_one_statement_per_line() {
+        let diags = assemble("clr retn\n").unwrap_err();
+        assert_eq!("unexpected `retn`", diags[0].message);
+        assert!(diags[0].expected.contains(&String::from("end of line")));
+    }
+
+    #[rstest]
+    #[case::bytes("byte 1, -1, 'A', $a >> 8\n$a clr\n", vec![1, 0xff, 0x41, 0x02, 0x00, 0xe0])]
+    #[case::words("word #1234, $a\n$a byte 7\n", vec![0x12, 0x34, 0x02, 0x04, 7])]
+    #[case::text("text \"Hi; there\" ; greeting\n", b"Hi; there".to_vec())]
+    #[case::charmap("charmap \"0123456789\"\ntext \"42\"\n", vec![4, 2])]
+    #[case::charmap_start("charmap \" AB\", #20\ntext \"BA \"\n", vec![0x22, 0x21, 0x20])]
+    #[case::sprite("sprite ..XX.. / .X..X. / XXXXXXXX\n", vec![0x30, 0x48, 0xff])]
+    #[case::sprite_lines(
+        "$s sprite X... /\n          .X.. /\n          ..X.\nmov I, $s\n",
+        vec![0x80, 0x40, 0x20, 0xa2, 0x00]
+    )]
+    #[case::font_sprite("sprite V3\n", vec![0xf3, 0x29])]
+    fn test_data_directives(#[case] src: &str, #[case] expected: Vec<u8>) {
+        assert_eq!(expected, assemble(src).unwrap());
+    }
+
+    #[rstest]
+    #[case("byte 256\n", (5, "#100 doesn't fit in 8 bits (the most is #ff)"))]
+    #[case("charmap \"AB\"\ntext \"ABC\"\n", (21, "`C` isn't in the character map"))]
+    #[case("sprite X........\n", (7, "sprite rows can be at most 8 pixels wide"))]
+    fn test_data_errors(#[case] src: &str, #[case] expected: (usize, &str)) {
+        assert_eq!(vec![(expected.0, String::from(expected.1))], messages(src));
+    }
+
+    #[rstest]
+    fn test_unexpected_end() {
+        let diags = assemble("mov V1,\nclr\n").unwrap_err();
+        assert_eq!("unexpected end of line", diags[0].message);
+        assert!(diags[0].expected.contains(&String::from("number")));
+        assert!(diags[0].expected.contains(&String::from("register")));
+    }
+
+    #[rstest]
+    fn test_names() {
+        let src = "macro wait\n$w jmp $w\nendm\nconst $n = 2\n$a clr\nwait\nmov V0, $n\njmp $a\n";
+        let mut sources = Sources::new();
+        let file = sources.add("", src);
+        let names = names(&mut sources, file, &|_| Err(io::ErrorKind::NotFound.into()));
+        let label = |name: &str, start: usize| Label {
+            name: String::from(name),
+            span: Span::new(file, start, start + name.len()),
+        };
+        let defs = vec![label("$w", 11), label("$n", 32), label("$a", 39)];
+        assert_eq!(defs, names.defs);
+        let uses = vec![label("$w", 18), label("$n", 59), label("$a", 66)];
+        assert_eq!(uses, names.uses);
     }
-    bytes
 }
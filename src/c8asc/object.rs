@@ -0,0 +1,441 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::Assembled;
+use crate::machine::{DEFAULT_START, MEMORY_SIZE};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Bytes of code written on each `code` line of an object file.
+const CODE_LINE_LEN: usize = 32;
+
+/// A field of a module's code that the linker adds an address to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    /// The low 12 bits of an instruction.
+    Addr12,
+    /// A 16-bit word.
+    Word,
+}
+
+impl Field {
+    fn name(&self) -> &'static str {
+        match self {
+            Field::Addr12 => "addr12",
+            Field::Word => "word",
+        }
+    }
+}
+
+/// A field holding an address, which the linker fixes once it knows where everything is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    /// Offset of the field from the start of the module.
+    pub offset: usize,
+    pub field: Field,
+    /// The label in another module whose address is added to the field, or `None` to add
+    /// the address of this module.
+    pub symbol: Option<String>,
+}
+
+impl Relocation {
+    /// Add an address, which may be negative, to the field in `code`.
+    pub fn apply(&self, code: &mut [u8], addr: i64) -> Result<(), String> {
+        let end = self.offset.saturating_add(2);
+        let bytes = code.get_mut(self.offset..end).ok_or_else(|| {
+            format!(
+                "relocation at #{:x} is past the end of the code",
+                self.offset
+            )
+        })?;
+        let word = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let (value, max) = match self.field {
+            Field::Addr12 => (word & 0xfff, 0xfff),
+            Field::Word => (word, 0xffff),
+        };
+        let value = value as i64 + addr;
+        if !(0..=max).contains(&value) {
+            return Err(format!(
+                "#{:x} doesn't fit in the {} at #{:x}",
+                value,
+                self.field.name(),
+                self.offset
+            ));
+        }
+        let word = (word & !(max as u16)) | value as u16;
+        bytes.copy_from_slice(&word.to_be_bytes());
+        Ok(())
+    }
+}
+
+/// A separately assembled module of a program, as written by `c8asc -c`.
+///
+/// The file is line-based text:
+///
+/// ```text
+/// code 6001221e1200
+/// label 000 $start
+/// reloc 002 addr12 $draw
+/// reloc 004 addr12
+/// ```
+///
+/// `code` lines give the module's bytes in hex, continuing from one line to the next.
+/// `label` lines give the hex offset of each label from the start of the module. `reloc`
+/// lines give the offset and width of each field the linker adds an address to: that of
+/// the label named, which may be in another module, or that of the module itself if none
+/// is named. The fields hold offsets from those addresses until they're linked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub code: Vec<u8>,
+    pub labels: Vec<(usize, String)>,
+    pub relocations: Vec<Relocation>,
+}
+
+impl Object {
+    /// Make an object from a module assembled at its load address, whose fields holding
+    /// addresses in the module are moved to hold offsets from its start.
+    pub fn new(assembled: Assembled, relocations: Vec<Relocation>) -> Result<Object, String> {
+        let start = assembled.start;
+        let mut code = assembled.rom;
+        for reloc in relocations.iter().filter(|r| r.symbol.is_none()) {
+            reloc.apply(&mut code, -(start as i64))?;
+        }
+        // Labels local to a use of a macro can't be named from another module.
+        let labels = assembled
+            .labels
+            .into_iter()
+            .filter(|(name, _)| !name.contains('@'))
+            .map(|(name, addr)| (addr as usize - start, name))
+            .collect();
+        Ok(Object {
+            code,
+            labels,
+            relocations,
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Object, String> {
+        let mut object = Object::default();
+        // The line of each label and relocation, and the end of what it refers to, which
+        // must be in the code.
+        let mut ends = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let mut fields = rest.split(' ');
+            let mut offset = || {
+                fields
+                    .next()
+                    .and_then(|f| usize::from_str_radix(f, 16).ok())
+                    .ok_or_else(|| err("expected an offset"))
+            };
+            match kind {
+                "code" => {
+                    let bytes = (0..rest.len())
+                        .step_by(2)
+                        .map(|i| {
+                            rest.get(i..i + 2)
+                                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                        })
+                        .collect::<Option<Vec<u8>>>()
+                        .ok_or_else(|| err("expected bytes in hex"))?;
+                    object.code.extend(bytes);
+                }
+                "label" => {
+                    let offset = offset()?;
+                    ends.push((i, Some(offset)));
+                    match fields.next() {
+                        Some(name) => object.labels.push((offset, String::from(name))),
+                        None => return Err(err("expected an offset and a name")),
+                    }
+                }
+                "reloc" => {
+                    let offset = offset()?;
+                    ends.push((i, offset.checked_add(2)));
+                    let field = match fields.next() {
+                        Some("addr12") => Field::Addr12,
+                        Some("word") => Field::Word,
+                        _ => return Err(err("expected addr12 or word")),
+                    };
+                    object.relocations.push(Relocation {
+                        offset,
+                        field,
+                        symbol: fields.next().map(String::from),
+                    });
+                }
+                _ => return Err(err(&format!("unknown record: {}", kind))),
+            }
+        }
+        for (i, end) in ends {
+            if end.is_none_or(|end| end > object.code.len()) {
+                return Err(format!(
+                    "line {}: offset is past the end of the code",
+                    i + 1
+                ));
+            }
+        }
+        Ok(object)
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.code.chunks(CODE_LINE_LEN) {
+            let hex: String = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(f, "code {}", hex)?;
+        }
+        for (offset, name) in &self.labels {
+            writeln!(f, "label {:03x} {}", offset, name)?;
+        }
+        for reloc in &self.relocations {
+            write!(f, "reloc {:03x} {}", reloc.offset, reloc.field.name())?;
+            match &reloc.symbol {
+                Some(name) => writeln!(f, " {}", name)?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Link modules, each given with the name of its file, into a ROM loaded at 200.
+///
+/// The modules are placed one after another in the order given, each at an even address
+/// so that instructions stay aligned. Every label used must be defined in exactly one
+/// module; all the problems found are reported together.
+pub fn link(objects: &[(String, Object)]) -> Result<Vec<u8>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut bases = Vec::new();
+    let mut addr = DEFAULT_START;
+    for (_, object) in objects {
+        bases.push(addr);
+        addr += object.code.len();
+        addr += addr % 2;
+    }
+    let mut symbols: HashMap<&str, (usize, &str)> = HashMap::new();
+    for ((file, object), base) in objects.iter().zip(&bases) {
+        for (offset, name) in &object.labels {
+            match symbols.get(name.as_str()) {
+                Some((_, first)) => errors.push(format!(
+                    "duplicate symbol {} in {} and {}",
+                    name, first, file
+                )),
+                None => {
+                    symbols.insert(name, (base + offset, file));
+                }
+            }
+        }
+    }
+    let mut rom = Vec::new();
+    let mut undefined = HashSet::new();
+    for ((file, object), base) in objects.iter().zip(&bases) {
+        let mut code = object.code.clone();
+        for reloc in &object.relocations {
+            let addr = match &reloc.symbol {
+                None => *base,
+                Some(name) => match symbols.get(name.as_str()) {
+                    Some((addr, _)) => *addr,
+                    None => {
+                        if undefined.insert((file, name)) {
+                            errors.push(format!("{}: undefined symbol {}", file, name));
+                        }
+                        continue;
+                    }
+                },
+            };
+            if let Err(e) = reloc.apply(&mut code, addr as i64) {
+                errors.push(format!("{}: {}", file, e));
+            }
+        }
+        rom.resize(base - DEFAULT_START, 0);
+        rom.extend(code);
+    }
+    if DEFAULT_START + rom.len() > MEMORY_SIZE {
+        errors.push(String::from("the program runs past the end of memory"));
+    }
+    if errors.is_empty() {
+        Ok(rom)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod object_tests {
+    use super::*;
+    use crate::c8asc::assemble_object;
+    use crate::c8asc::diagnostic::Sources;
+    use rstest::*;
+
+    fn object(src: &str) -> Object {
+        let mut sources = Sources::new();
+        let file = sources.add("", src);
+        assemble_object(&mut sources, file, &|_| unreachable!()).unwrap()
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        let mut sources = Sources::new();
+        let file = sources.add("", src);
+        let diags = assemble_object(&mut sources, file, &|_| unreachable!()).unwrap_err();
+        diags.into_iter().map(|d| d.message).collect()
+    }
+
+    fn reloc(offset: usize, field: Field, symbol: Option<&str>) -> Relocation {
+        Relocation {
+            offset,
+            field,
+            symbol: symbol.map(String::from),
+        }
+    }
+
+    #[rstest]
+    fn test_assemble_object() {
+        let src = "\
+$start call $draw
+$loop jmp $loop + 2
+word $table
+mov I, $end - $start
+$end retn
+";
+        let obj = object(src);
+        assert_eq!(
+            vec![0x20, 0x00, 0x10, 0x04, 0x00, 0x00, 0xa0, 0x08, 0x00, 0xee],
+            obj.code
+        );
+        let labels = vec![
+            (0, String::from("$start")),
+            (2, String::from("$loop")),
+            (8, String::from("$end")),
+        ];
+        assert_eq!(labels, obj.labels);
+        let relocations = vec![
+            reloc(0, Field::Addr12, Some("$draw")),
+            reloc(2, Field::Addr12, None),
+            reloc(4, Field::Word, Some("$table")),
+        ];
+        assert_eq!(relocations, obj.relocations);
+    }
+
+    #[rstest]
+    fn test_object_before_start() {
+        let src = "org #300\n$a jmp $a\norg #200\n$b jmp $b\n";
+        let expected = vec![
+            "org can't be used in an object file",
+            "#200 is before the start of the program at #300",
+        ];
+        assert_eq!(expected, errors(src));
+    }
+
+    #[rstest]
+    #[case::org("org #300\nclr\n", "org can't be used in an object file")]
+    #[case::byte(
+        "mov V0, $x\n",
+        "only 12-bit addresses and words can be relocated by the linker"
+    )]
+    #[case::two_labels(
+        "jmp $a + $b\n",
+        "the linker can only relocate an address plus or minus a number"
+    )]
+    #[case::scaled(
+        "$a jmp $a * 2\n",
+        "the linker can only relocate an address plus or minus a number"
+    )]
+    fn test_object_errors(#[case] src: &str, #[case] expected: &str) {
+        assert_eq!(vec![String::from(expected)], errors(src));
+    }
+
+    #[rstest]
+    fn test_round_trip() {
+        let obj = object("$a jmp $a + 40\ncall $ext\nsprite V0\n");
+        let text = obj.to_string();
+        assert_eq!(Ok(obj), Object::parse(&text));
+    }
+
+    #[rstest]
+    #[case("code 0g\n", "line 1: expected bytes in hex")]
+    #[case("code 00\nreloc 000 addr8\n", "line 2: expected addr12 or word")]
+    #[case("label $a\n", "line 1: expected an offset")]
+    #[case("section text\n", "line 1: unknown record: section")]
+    #[case(
+        "code 00\nlabel 002 $a\n",
+        "line 2: offset is past the end of the code"
+    )]
+    #[case(
+        "code 0000\nreloc 001 word\n",
+        "line 2: offset is past the end of the code"
+    )]
+    #[case(
+        "code 0000\nreloc ffffffffffffffff addr12\n",
+        "line 2: offset is past the end of the code"
+    )]
+    fn test_parse_errors(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(Err(String::from(expected)), Object::parse(text));
+    }
+
+    #[rstest]
+    fn test_apply_past_end() {
+        let reloc = reloc(usize::MAX, Field::Word, None);
+        assert!(reloc.apply(&mut [0, 0], 0x200).is_err());
+    }
+
+    #[rstest]
+    fn test_link() {
+        let main = object("$start call $draw\n$loop jmp $loop\n");
+        let draw = object("byte 1\n$draw mov I, $sprite\ndraw V0, V1, 1\nretn\n$sprite byte #80\n");
+        let files = vec![
+            (String::from("main.o"), main),
+            (String::from("draw.o"), draw),
+        ];
+        let expected = vec![
+            0x22, 0x05, 0x12, 0x02, // main at 200
+            0x01, 0xa2, 0x0b, 0xd0, 0x11, 0x00, 0xee, 0x80, // draw at 204
+        ];
+        assert_eq!(Ok(expected), link(&files));
+    }
+
+    #[rstest]
+    fn test_modules_start_at_even_addresses() {
+        let files = vec![
+            (String::from("a.o"), object("$a byte 1\n")),
+            (String::from("b.o"), object("$b jmp $a\n")),
+        ];
+        assert_eq!(Ok(vec![0x01, 0x00, 0x12, 0x00]), link(&files));
+    }
+
+    #[rstest]
+    fn test_link_errors() {
+        let files = vec![
+            (String::from("a.o"), object("$x jmp $y\ncall $y\n")),
+            (String::from("b.o"), object("$x clr\n")),
+        ];
+        let expected = vec![
+            String::from("duplicate symbol $x in a.o and b.o"),
+            String::from("a.o: undefined symbol $y"),
+        ];
+        assert_eq!(Err(expected), link(&files));
+    }
+
+    #[rstest]
+    fn test_relocation_out_of_range() {
+        let mut code = vec![0x1f, 0xf0];
+        let err = reloc(0, Field::Addr12, None).apply(&mut code, 0x20);
+        assert_eq!(
+            Err(String::from("#1010 doesn't fit in the addr12 at #0")),
+            err
+        );
+    }
+}
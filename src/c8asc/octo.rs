@@ -0,0 +1,601 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, FileId, Span};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    span: Span,
+}
+
+impl Token<'_> {
+    fn error(&self, message: String) -> Diagnostic {
+        Diagnostic::error(message, Some(self.span))
+    }
+}
+
+/// Split source into whitespace-separated tokens, dropping `#` comments.
+fn tokenize(src: &str, file: FileId) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut line_start = 0;
+    for line in src.split_inclusive('\n') {
+        let code = line.split('#').next().unwrap_or("");
+        let mut start = None;
+        for (pos, c) in code.char_indices().chain([(code.len(), ' ')]) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    tokens.push(Token {
+                        text: &code[s..pos],
+                        span: Span::new(file, line_start + s, line_start + pos),
+                    });
+                    start = None;
+                }
+                (false, None) => start = Some(pos),
+                _ => (),
+            }
+        }
+        line_start += line.len();
+    }
+    tokens
+}
+
+/// Open control-flow structures waiting for their closing keyword.
+enum Block {
+    /// `if ... begin`, with the jump taken when the condition is false.
+    If(usize),
+    /// `else`, with the jump taken at the end of the true branch.
+    Else(usize),
+    /// `loop`, with its start address and the exit jumps of any `while`s.
+    Loop(usize, Vec<usize>),
+}
+
+const KEYWORDS: [&str; 30] = [
+    ":", ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=", "==", "!=", ";", "clear",
+    "return", "jump", "jump0", "if", "then", "begin", "else", "end", "loop", "again", "while",
+    "key", "-key", "random", "delay", "buzzer",
+];
+
+struct Assembler<'a> {
+    file: FileId,
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    rom: Vec<u8>,
+    labels: HashMap<&'a str, usize>,
+    consts: HashMap<&'a str, i64>,
+    aliases: HashMap<&'a str, u8>,
+    /// Offsets of instructions whose low 12 bits are the address of a label.
+    fixups: Vec<(usize, Token<'a>)>,
+    blocks: Vec<(Block, Token<'a>)>,
+}
+
+/// Assemble a program written in Octo syntax.
+///
+/// Programs are placed at 200. As in Octo, if `main` is defined anywhere but the start of
+/// the program a `jump main` is placed at 200 first. Assembly stops at the first syntax
+/// error, but every undefined label is reported.
+pub fn assemble_octo(src: &str) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    assemble_octo_file(src, 0)
+}
+
+/// Assemble the Octo source of `file`, giving its id in the spans of any diagnostics.
+pub fn assemble_octo_file(src: &str, file: FileId) -> Result<Vec<u8>, Vec<Diagnostic>> {
+    let mut asm = Assembler {
+        file,
+        tokens: tokenize(src, file),
+        pos: 0,
+        rom: Vec::new(),
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    if let Some(main) = asm.main_needs_jump() {
+        asm.fixups.push((0, main));
+        asm.emit(0x1000);
+    }
+    while asm.pos < asm.tokens.len() {
+        asm.statement().map_err(|e| vec![e])?;
+    }
+    if let Some((_, tok)) = asm.blocks.last() {
+        return Err(vec![tok.error(format!("'{}' is never closed", tok.text))]);
+    }
+    let mut diags = Vec::new();
+    for (offset, tok) in &asm.fixups {
+        match asm.labels.get(tok.text) {
+            Some(addr) => {
+                asm.rom[*offset] |= (addr >> 8) as u8;
+                asm.rom[*offset + 1] |= *addr as u8;
+            }
+            None => diags.push(tok.error(format!("undefined label: {}", tok.text))),
+        }
+    }
+    if diags.is_empty() {
+        Ok(asm.rom)
+    } else {
+        Err(diags)
+    }
+}
+
+impl<'a> Assembler<'a> {
+    /// If `main` is defined after some code, the token naming it.
+    fn main_needs_jump(&self) -> Option<Token<'a>> {
+        let mut i = 0;
+        while let Some(tok) = self.tokens.get(i) {
+            match tok.text {
+                ":const" | ":alias" => i += 3,
+                ":" if self.tokens.get(i + 1).map(|t| t.text) == Some("main") => return None,
+                _ => break,
+            }
+        }
+        self.tokens
+            .windows(2)
+            .find(|w| w[0].text == ":" && w[1].text == "main")
+            .map(|w| w[1])
+    }
+
+    fn here(&self) -> usize {
+        0x200 + self.rom.len()
+    }
+
+    fn emit(&mut self, op: u16) {
+        self.rom.extend(op.to_be_bytes());
+    }
+
+    fn next(&mut self) -> Result<Token<'a>, Diagnostic> {
+        match self.tokens.get(self.pos) {
+            Some(tok) => {
+                self.pos += 1;
+                Ok(*tok)
+            }
+            None => {
+                let end = self.tokens.last().map_or(0, |t| t.span.end);
+                Err(Diagnostic::error(
+                    String::from("unexpected end of file"),
+                    Some(Span::new(self.file, end, end)),
+                ))
+            }
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), Diagnostic> {
+        let tok = self.next()?;
+        if tok.text == text {
+            Ok(())
+        } else {
+            Err(tok.error(format!("expected '{}' but found '{}'", text, tok.text)))
+        }
+    }
+
+    fn name(&mut self) -> Result<Token<'a>, Diagnostic> {
+        let tok = self.next()?;
+        if KEYWORDS.contains(&tok.text)
+            || parse_number(tok.text).is_some()
+            || register(tok.text).is_some()
+        {
+            Err(tok.error(format!("'{}' can't be used as a name", tok.text)))
+        } else {
+            Ok(tok)
+        }
+    }
+
+    fn register_of(&self, tok: Token) -> Option<u8> {
+        register(tok.text).or_else(|| self.aliases.get(tok.text).copied())
+    }
+
+    fn register(&mut self) -> Result<u8, Diagnostic> {
+        let tok = self.next()?;
+        self.register_of(tok)
+            .ok_or_else(|| tok.error(format!("expected a register but found '{}'", tok.text)))
+    }
+
+    fn value_of(&self, tok: Token) -> Option<i64> {
+        parse_number(tok.text).or_else(|| self.consts.get(tok.text).copied())
+    }
+
+    fn number(&mut self, min: i64, max: i64) -> Result<i64, Diagnostic> {
+        let tok = self.next()?;
+        match self.value_of(tok) {
+            Some(n) if (min..=max).contains(&n) => Ok(n),
+            Some(n) => Err(tok.error(format!("{} is out of range {} to {}", n, min, max))),
+            None => Err(tok.error(format!("expected a number but found '{}'", tok.text))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u16, Diagnostic> {
+        Ok(self.number(-128, 255)? as u8 as u16)
+    }
+
+    /// Emit an instruction taking a 12-bit address given as a number or label.
+    fn emit_address(&mut self, op: u16) -> Result<(), Diagnostic> {
+        let tok = self.next()?;
+        match self.value_of(tok) {
+            Some(n) if (0..=0xfff).contains(&n) => self.emit(op | n as u16),
+            Some(n) => return Err(tok.error(format!("address {} is out of range", n))),
+            None => {
+                self.fixups.push((self.rom.len(), tok));
+                self.emit(op);
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit the skip that jumps over the next instruction when a condition is false, or
+    /// when it is true if `invert` is set.
+    fn condition(&mut self, invert: bool) -> Result<(), Diagnostic> {
+        let x = self.register()? as u16;
+        let op = self.next()?;
+        let skip_eq = match (op.text, invert) {
+            ("==", false) | ("!=", true) => false,
+            ("!=", false) | ("==", true) => true,
+            ("key", _) | ("-key", _) => {
+                let skip_pressed = (op.text == "-key") != invert;
+                self.emit(if skip_pressed { 0xe09e } else { 0xe0a1 } | x << 8);
+                return Ok(());
+            }
+            _ => return Err(op.error(format!("expected a comparison but found '{}'", op.text))),
+        };
+        let tok = self.next()?;
+        if let Some(y) = self.register_of(tok) {
+            self.emit(if skip_eq { 0x5000 } else { 0x9000 } | x << 8 | (y as u16) << 4);
+        } else {
+            self.pos -= 1;
+            let nn = self.byte()?;
+            self.emit(if skip_eq { 0x3000 } else { 0x4000 } | x << 8 | nn);
+        }
+        Ok(())
+    }
+
+    /// Emit a jump whose address is filled in later by `patch`.
+    fn placeholder_jump(&mut self) -> usize {
+        self.emit(0x1000);
+        self.rom.len() - 2
+    }
+
+    fn patch(&mut self, offset: usize, addr: usize) {
+        self.rom[offset] |= (addr >> 8) as u8;
+        self.rom[offset + 1] |= addr as u8;
+    }
+
+    fn statement(&mut self) -> Result<(), Diagnostic> {
+        let tok = self.next()?;
+        match tok.text {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name.text, self.here()).is_some() {
+                    return Err(name.error(format!("duplicate label: {}", name.text)));
+                }
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.number(-0x8000, 0xffff)?;
+                self.consts.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text, reg);
+            }
+            ":call" => self.emit_address(0x2000)?,
+            "clear" => self.emit(0x00e0),
+            "return" | ";" => self.emit(0x00ee),
+            "jump" => self.emit_address(0x1000)?,
+            "jump0" => self.emit_address(0xb000)?,
+            "bcd" => {
+                let x = self.register()? as u16;
+                self.emit(0xf033 | x << 8);
+            }
+            "save" => {
+                let x = self.register()? as u16;
+                self.emit(0xf055 | x << 8);
+            }
+            "load" => {
+                let x = self.register()? as u16;
+                self.emit(0xf065 | x << 8);
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.number(0, 15)? as u16;
+                self.emit(0xd000 | x << 8 | y << 4 | n);
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()? as u16;
+                self.emit(if tok.text == "delay" { 0xf015 } else { 0xf018 } | x << 8);
+            }
+            "i" => self.index()?,
+            "if" => {
+                let start = self.pos;
+                self.condition(false)?;
+                let then = self.next()?;
+                match then.text {
+                    "then" => (),
+                    "begin" => {
+                        // Re-emit the skip inverted so that the jump past the block is
+                        // skipped when the condition is true
+                        self.rom.truncate(self.rom.len() - 2);
+                        self.pos = start;
+                        self.condition(true)?;
+                        self.pos += 1;
+                        let jump = self.placeholder_jump();
+                        self.blocks.push((Block::If(jump), tok));
+                    }
+                    _ => {
+                        return Err(then.error(format!(
+                            "expected 'then' or 'begin' but found '{}'",
+                            then.text
+                        )))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::If(jump), _)) => {
+                    let end = self.placeholder_jump();
+                    self.patch(jump, self.here());
+                    self.blocks.push((Block::Else(end), tok));
+                }
+                _ => return Err(tok.error(String::from("'else' without 'if ... begin'"))),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If(jump), _)) | Some((Block::Else(jump), _)) => {
+                    self.patch(jump, self.here())
+                }
+                _ => return Err(tok.error(String::from("'end' without 'if ... begin'"))),
+            },
+            "loop" => self
+                .blocks
+                .push((Block::Loop(self.here(), Vec::new()), tok)),
+            "while" => {
+                self.condition(true)?;
+                let jump = self.placeholder_jump();
+                match self.blocks.iter_mut().rev().find_map(|(b, _)| match b {
+                    Block::Loop(_, whiles) => Some(whiles),
+                    _ => None,
+                }) {
+                    Some(whiles) => whiles.push(jump),
+                    None => return Err(tok.error(String::from("'while' outside a loop"))),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, whiles), _)) => {
+                    self.emit(0x1000 | start as u16);
+                    for jump in whiles {
+                        self.patch(jump, self.here());
+                    }
+                }
+                _ => return Err(tok.error(String::from("'again' without 'loop'"))),
+            },
+            _ => {
+                if let Some(x) = self.register_of(tok) {
+                    self.register_op(x as u16)?;
+                } else if self.value_of(tok).is_some() {
+                    self.pos -= 1;
+                    let b = self.byte()?;
+                    self.rom.push(b as u8);
+                } else if KEYWORDS.contains(&tok.text) || tok.text.starts_with(':') {
+                    return Err(tok.error(format!("unexpected '{}'", tok.text)));
+                } else {
+                    self.fixups.push((self.rom.len(), tok));
+                    self.emit(0x2000);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&mut self) -> Result<(), Diagnostic> {
+        let op = self.next()?;
+        match op.text {
+            ":=" => {
+                let tok = self.next()?;
+                if tok.text == "hex" {
+                    let x = self.register()? as u16;
+                    self.emit(0xf029 | x << 8);
+                } else {
+                    self.pos -= 1;
+                    self.emit_address(0xa000)?;
+                }
+            }
+            "+=" => {
+                let x = self.register()? as u16;
+                self.emit(0xf01e | x << 8);
+            }
+            _ => return Err(op.error(format!("expected ':=' or '+=' but found '{}'", op.text))),
+        }
+        Ok(())
+    }
+
+    fn register_op(&mut self, x: u16) -> Result<(), Diagnostic> {
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let y = self.register_of(rhs).map(|y| (y as u16) << 4);
+        let code = match (op.text, rhs.text, y) {
+            (":=", "random", _) => 0xc000 | self.byte()?,
+            (":=", "key", _) => 0xf00a,
+            (":=", "delay", _) => 0xf007,
+            (":=", _, Some(y)) => 0x8000 | y,
+            ("|=", _, Some(y)) => 0x8001 | y,
+            ("&=", _, Some(y)) => 0x8002 | y,
+            ("^=", _, Some(y)) => 0x8003 | y,
+            ("+=", _, Some(y)) => 0x8004 | y,
+            ("-=", _, Some(y)) => 0x8005 | y,
+            (">>=", _, Some(y)) => 0x8006 | y,
+            ("=-", _, Some(y)) => 0x8007 | y,
+            ("<<=", _, Some(y)) => 0x800e | y,
+            (":=", _, None) | ("+=", _, None) | ("-=", _, None) => {
+                self.pos -= 1;
+                let nn = self.byte()?;
+                match op.text {
+                    ":=" => 0x6000 | nn,
+                    "+=" => 0x7000 | nn,
+                    _ => 0x7000 | (nn as u8).wrapping_neg() as u16,
+                }
+            }
+            _ => return Err(op.error(format!("can't use '{}' with '{}'", op.text, rhs.text))),
+        };
+        self.emit(code | x << 8);
+        Ok(())
+    }
+}
+
+/// Parse a register name, `v0` to `vf`.
+fn register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() == 1 {
+        u8::from_str_radix(digit, 16).ok()
+    } else {
+        None
+    }
+}
+
+/// Parse a decimal, `0x` hex or `0b` binary number, optionally negative.
+fn parse_number(text: &str) -> Option<i64> {
+    let (neg, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let n = if let Some(hex) = text.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()?
+    } else if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) {
+        text.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if neg { -n } else { n })
+}
+
+#[cfg(test)]
+mod octo_tests {
+    use super::*;
+    use crate::c8asc::diagnostic::SourceFile;
+    use rstest::*;
+
+    fn words(rom: &[u8]) -> Vec<u16> {
+        rom.chunks(2)
+            .map(|c| (c[0] as u16) << 8 | c[1] as u16)
+            .collect()
+    }
+
+    #[rstest]
+    #[case("clear", 0x00e0)]
+    #[case("return", 0x00ee)]
+    #[case(";", 0x00ee)]
+    #[case("jump 0x2a4", 0x12a4)]
+    #[case("jump0 0x300", 0xb300)]
+    #[case(":call 0x31c", 0x231c)]
+    #[case("if v3 == 0x1a then", 0x431a)]
+    #[case("if v3 != 26 then", 0x331a)]
+    #[case("if v3 == v4 then", 0x9340)]
+    #[case("if v3 != v4 then", 0x5340)]
+    #[case("if v5 key then", 0xe5a1)]
+    #[case("if v5 -key then", 0xe59e)]
+    #[case("v2 := 5", 0x6205)]
+    #[case("v2 += 0b11", 0x7203)]
+    #[case("v2 -= 1", 0x72ff)]
+    #[case("vA := -1", 0x6aff)]
+    #[case("v2 := v3", 0x8230)]
+    #[case("v2 |= v3", 0x8231)]
+    #[case("v2 &= v3", 0x8232)]
+    #[case("v2 ^= v3", 0x8233)]
+    #[case("v2 += v3", 0x8234)]
+    #[case("v2 -= v3", 0x8235)]
+    #[case("v2 >>= v3", 0x8236)]
+    #[case("v2 =- v3", 0x8237)]
+    #[case("v2 <<= v3", 0x823e)]
+    #[case("i := 0x3f0", 0xa3f0)]
+    #[case("i := hex v7", 0xf729)]
+    #[case("i += v7", 0xf71e)]
+    #[case("v1 := random 0x0f", 0xc10f)]
+    #[case("v1 := key", 0xf10a)]
+    #[case("v1 := delay", 0xf107)]
+    #[case("delay := v1", 0xf115)]
+    #[case("buzzer := v1", 0xf118)]
+    #[case("sprite v1 v2 5", 0xd125)]
+    #[case("bcd v9", 0xf933)]
+    #[case("save v9", 0xf955)]
+    #[case("load v9", 0xf965)]
+    fn test_instruction(#[case] src: &str, #[case] expected: u16) {
+        assert_eq!(vec![expected], words(&assemble_octo(src).unwrap()));
+    }
+
+    #[rstest]
+    fn test_labels_and_main() {
+        let src = "
+            : draw      # a subroutine before main
+                i := spr
+                sprite v0 v1 2
+            ;
+            : main
+                draw
+                jump main
+            : spr 0x3c 0b01000010
+        ";
+        let rom = assemble_octo(src).unwrap();
+        assert_eq!(
+            vec![0x1208, 0xa20c, 0xd012, 0x00ee, 0x2202, 0x1208, 0x3c42],
+            words(&rom)
+        );
+    }
+
+    #[rstest]
+    fn test_main_first_needs_no_jump() {
+        let src = ":alias x v3 :const five 5 : main x := five jump main";
+        assert_eq!(vec![0x6305, 0x1200], words(&assemble_octo(src).unwrap()));
+    }
+
+    #[rstest]
+    fn test_if_begin_else_end() {
+        let src = "if v0 == 1 begin v1 := 1 else v1 := 2 end clear";
+        assert_eq!(
+            vec![0x3001, 0x1208, 0x6101, 0x120a, 0x6102, 0x00e0],
+            words(&assemble_octo(src).unwrap())
+        );
+    }
+
+    #[rstest]
+    fn test_loop_while_again() {
+        let src = "loop v0 += 1 while v0 != 10 again clear";
+        assert_eq!(
+            vec![0x7001, 0x400a, 0x1208, 0x1200, 0x00e0],
+            words(&assemble_octo(src).unwrap())
+        );
+    }
+
+    #[rstest]
+    #[case("jump nowhere", "1:6: undefined label: nowhere")]
+    #[case(": a : a", "1:7: duplicate label: a")]
+    #[case("v0 := 256", "1:7: 256 is out of range -128 to 255")]
+    #[case("sprite v0 vg 1", "1:11: expected a register but found 'vg'")]
+    #[case("loop v0 += 1", "1:1: 'loop' is never closed")]
+    #[case("again", "1:1: 'again' without 'loop'")]
+    #[case("\n  if v0 < 3 then", "2:9: expected a comparison but found '<'")]
+    #[case("v0 :=", "1:6: unexpected end of file")]
+    #[case(": v1", "1:3: 'v1' can't be used as a name")]
+    fn test_errors(#[case] src: &str, #[case] expected: &str) {
+        let diag = &assemble_octo(src).unwrap_err()[0];
+        let (line, col) = SourceFile::new("", src).line_col(diag.span.unwrap().start);
+        assert_eq!(expected, format!("{}:{}: {}", line, col, diag.message));
+    }
+
+    #[rstest]
+    fn test_all_undefined_labels() {
+        let diags = assemble_octo("jump a\nb\n: c").unwrap_err();
+        let messages: Vec<&str> = diags.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(vec!["undefined label: a", "undefined label: b"], messages);
+    }
+}
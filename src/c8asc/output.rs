@@ -0,0 +1,678 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::listing::symbols;
+use super::Assembled;
+use crate::machine::disassemble::{disassemble_traced, Syntax};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Bytes written on each line of an Intel HEX file.
+const HEX_RECORD_LEN: usize = 16;
+/// Bytes written on each line of a C or Rust array.
+const ARRAY_LINE_LEN: usize = 12;
+
+/// Names a C array can't have: the keywords of C23 and `main`.
+const C_RESERVED: [&str; 46] = [
+    "alignas",
+    "alignof",
+    "auto",
+    "bool",
+    "break",
+    "case",
+    "char",
+    "const",
+    "constexpr",
+    "continue",
+    "default",
+    "do",
+    "double",
+    "else",
+    "enum",
+    "extern",
+    "false",
+    "float",
+    "for",
+    "goto",
+    "if",
+    "inline",
+    "int",
+    "long",
+    "main",
+    "nullptr",
+    "register",
+    "restrict",
+    "return",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "static_assert",
+    "struct",
+    "switch",
+    "thread_local",
+    "true",
+    "typedef",
+    "typeof",
+    "typeof_unqual",
+    "union",
+    "unsigned",
+    "void",
+    "volatile",
+    "while",
+];
+
+/// Keywords of Rust, reserved or not.
+const RUST_RESERVED: [&str; 51] = [
+    "as", "abstract", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Size of each frame of an Octo cartridge.
+const CART_WIDTH: usize = 160;
+const CART_HEIGHT: usize = 128;
+/// The four colours of a cartridge's label. Each is repeated four times in the palette,
+/// so that the low two bits of each pixel are free to carry the program.
+const CART_COLOURS: [[u8; 3]; 4] = [
+    [0x00, 0x00, 0x00],
+    [0x66, 0x22, 0x00],
+    [0xff, 0xcc, 0x00],
+    [0xff, 0x66, 0x00],
+];
+
+/// A way of writing an assembled ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// The bytes of the ROM.
+    Raw,
+    /// Intel HEX records, addressed from where the ROM is loaded.
+    Hex,
+    /// C source defining an array of the bytes and its length.
+    C,
+    /// Rust source defining a constant array of the bytes.
+    Rust,
+    /// An Octo cartridge: a GIF with the program's Octo source hidden in its pixels.
+    Octo,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 5] = [
+        OutputFormat::Raw,
+        OutputFormat::Hex,
+        OutputFormat::C,
+        OutputFormat::Rust,
+        OutputFormat::Octo,
+    ];
+
+    /// The name the format is given on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Raw => "raw",
+            OutputFormat::Hex => "hex",
+            OutputFormat::C => "c",
+            OutputFormat::Rust => "rust",
+            OutputFormat::Octo => "octo",
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = OutputFormat::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unknown format '{}' (known formats: {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Write a ROM loaded at `start` as Intel HEX data records, with extended linear address
+/// records wherever the top half of the address changes.
+///
+/// ```text
+/// :040200006001610236
+/// :00000001FF
+/// ```
+pub fn intel_hex(rom: &[u8], start: usize) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = start + offset;
+        if addr >> 16 != upper {
+            upper = addr >> 16;
+            out.push_str(&hex_record(0, 4, &(upper as u16).to_be_bytes()));
+        }
+        // Records don't run over into the next 64K.
+        let len = HEX_RECORD_LEN
+            .min(rom.len() - offset)
+            .min(0x10000 - (addr & 0xffff));
+        out.push_str(&hex_record(addr as u16, 0, &rom[offset..offset + len]));
+        offset += len;
+    }
+    out.push_str(&hex_record(0, 1, &[]));
+    out
+}
+
+fn hex_record(addr: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(addr.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", hex)
+}
+
+/// Write a ROM as C source for embedding it in firmware, in the style of `xxd -i`. Names
+/// that are reserved in C have `_rom` added.
+///
+/// ```text
+/// const unsigned char game[] = {
+///     0x60, 0x01, 0x61, 0x02
+/// };
+/// const unsigned int game_len = 4;
+/// ```
+pub fn c_array(rom: &[u8], name: &str) -> String {
+    let name = unreserved(name, C_RESERVED.contains(&name));
+    format!(
+        "const unsigned char {}[] = {{\n{}}};\nconst unsigned int {}_len = {};\n",
+        name,
+        array_bytes(rom),
+        name,
+        rom.len()
+    )
+}
+
+/// Write a ROM as a Rust constant, named in upper case. Rust keywords and `_` have `_rom`
+/// added, as for C.
+///
+/// ```text
+/// pub const GAME: [u8; 4] = [
+///     0x60, 0x01, 0x61, 0x02
+/// ];
+/// ```
+pub fn rust_array(rom: &[u8], name: &str) -> String {
+    let name = unreserved(name, name == "_" || RUST_RESERVED.contains(&name));
+    format!(
+        "pub const {}: [u8; {}] = [\n{}];\n",
+        name.to_ascii_uppercase(),
+        rom.len(),
+        array_bytes(rom)
+    )
+}
+
+/// An array's name, with `_rom` added if it's reserved.
+fn unreserved(name: &str, reserved: bool) -> String {
+    if reserved {
+        format!("{}_rom", name)
+    } else {
+        String::from(name)
+    }
+}
+
+/// The bytes of an array, a line at a time.
+fn array_bytes(rom: &[u8]) -> String {
+    let lines: Vec<String> = rom
+        .chunks(ARRAY_LINE_LEN)
+        .map(|chunk| {
+            let bytes: Vec<String> = chunk.iter().map(|b| format!("{:#04x}", b)).collect();
+            format!("    {}", bytes.join(", "))
+        })
+        .collect();
+    let mut out = lines.join(",\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Make a name for the array holding a ROM from the name of its file, replacing anything
+/// that can't be in an identifier.
+pub fn identifier(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .map_or(String::new(), |s| s.to_string_lossy().into_owned());
+    let mut name: String = stem
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        name.insert(0, '_');
+    }
+    name
+}
+
+/// Disassemble a program into Octo source for a cartridge, naming its code and data
+/// with the program's own labels.
+pub fn octo_source(assembled: &Assembled) -> String {
+    let lines = disassemble_traced(
+        &assembled.rom,
+        assembled.start,
+        false,
+        &symbols(assembled),
+        Syntax::Octo,
+    );
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Make an Octo cartridge holding some Octo source: a GIF of a cartridge whose pixels
+/// also carry the source, which Octo loads and runs when the GIF is opened.
+///
+/// The source is stored as JSON alongside Octo's default options. Its length as four
+/// big-endian bytes and then its UTF-8 bytes are spread over the low two bits of each
+/// pixel's colour index, most significant bits first, through as many frames of the
+/// picture as it takes.
+pub fn cartridge(program: &str) -> Vec<u8> {
+    let payload = json!({"options": octo_options(), "program": program}).to_string();
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(payload.as_bytes());
+    let bits: Vec<u8> = bytes
+        .iter()
+        .flat_map(|b| [b >> 6, b >> 4, b >> 2, *b].map(|pair| pair & 3))
+        .collect();
+
+    let mut gif = b"GIF89a".to_vec();
+    gif.extend((CART_WIDTH as u16).to_le_bytes());
+    gif.extend((CART_HEIGHT as u16).to_le_bytes());
+    // A global table of 16 colours, with 8 bits for each component.
+    gif.extend([0xf3, 0, 0]);
+    for colour in CART_COLOURS {
+        for _ in 0..4 {
+            gif.extend(colour);
+        }
+    }
+    let label = label();
+    for frame in bits.chunks(label.len()) {
+        // Each frame is shown for a second.
+        gif.extend([0x21, 0xf9, 4, 0, 100, 0, 0, 0]);
+        gif.push(0x2c);
+        gif.extend([0, 0, 0, 0]);
+        gif.extend((CART_WIDTH as u16).to_le_bytes());
+        gif.extend((CART_HEIGHT as u16).to_le_bytes());
+        gif.push(0);
+        let pixels: Vec<u8> = label
+            .iter()
+            .enumerate()
+            .map(|(i, colour)| colour << 2 | frame.get(i).unwrap_or(&0))
+            .collect();
+        gif.push(4);
+        for block in lzw(&pixels, 4).chunks(255) {
+            gif.push(block.len() as u8);
+            gif.extend(block);
+        }
+        gif.push(0);
+    }
+    gif.push(0x3b);
+    gif
+}
+
+/// The options Octo gives a new program.
+fn octo_options() -> serde_json::Value {
+    json!({
+        "tickrate": 20,
+        "fillColor": "#FFCC00",
+        "fillColor2": "#FF6600",
+        "blendColor": "#662200",
+        "backgroundColor": "#996600",
+        "buzzColor": "#FFAA00",
+        "quietColor": "#000000",
+        "shiftQuirks": false,
+        "loadStoreQuirks": false,
+        "vfOrderQuirks": false,
+        "clipQuirks": false,
+        "vBlankQuirks": false,
+        "jumpQuirks": false,
+        "logicQuirks": false,
+        "screenRotation": 0,
+        "maxSize": 3584,
+        "touchInputMode": "none",
+        "fontStyle": "octo",
+    })
+}
+
+/// The picture on a cartridge, as one of the four label colours for each pixel: a
+/// cartridge with a blank label and grips along the bottom.
+fn label() -> Vec<u8> {
+    let mut pixels = vec![0; CART_WIDTH * CART_HEIGHT];
+    let mut fill = |x: (usize, usize), y: (usize, usize), colour: u8| {
+        for row in y.0..y.1 {
+            pixels[row * CART_WIDTH + x.0..row * CART_WIDTH + x.1].fill(colour);
+        }
+    };
+    fill((16, 144), (8, 120), 1);
+    fill((28, 132), (20, 80), 2);
+    for grip in 0..5 {
+        let x = 40 + grip * 18;
+        fill((x, x + 8), (96, 112), 3);
+    }
+    pixels
+}
+
+/// Compress pixels with GIF's variable-length LZW, starting from codes one bit wider than
+/// `min_size`.
+fn lzw(pixels: &[u8], min_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_size;
+    let end = clear + 1;
+    let mut out = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = min_size + 1;
+    out.write(clear, size);
+    let mut prefix = None;
+    for &pixel in pixels {
+        let code = match prefix {
+            Some(code) => code,
+            None => {
+                prefix = Some(pixel as u16);
+                continue;
+            }
+        };
+        if let Some(&longer) = table.get(&(code, pixel)) {
+            prefix = Some(longer);
+            continue;
+        }
+        out.write(code, size);
+        if next < 0x1000 {
+            table.insert((code, pixel), next);
+            // The decoder widens its codes once it has a code that needs the next bit.
+            if next == 1 << size {
+                size += 1;
+            }
+            next += 1;
+        } else {
+            // Start again once every 12-bit code is used.
+            out.write(clear, size);
+            table.clear();
+            next = end + 1;
+            size = min_size + 1;
+        }
+        prefix = Some(pixel as u16);
+    }
+    if let Some(code) = prefix {
+        out.write(code, size);
+    }
+    out.write(end, size);
+    out.finish()
+}
+
+/// Packs codes into bytes from the least significant bit up.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod output_tests {
+    use super::*;
+    use crate::c8asc::assemble_sources;
+    use crate::c8asc::diagnostic::Sources;
+    use crate::c8asc::octo::assemble_octo;
+    use rstest::*;
+
+    /// Decode GIF LZW data back into pixels.
+    fn unlzw(data: &[u8], min_size: u8) -> Vec<u8> {
+        let clear = 1usize << min_size;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut size = min_size + 1;
+        let (mut acc, mut bits, mut pos) = (0u32, 0, 0);
+        let mut prev: Option<Vec<u8>> = None;
+        let mut out = Vec::new();
+        loop {
+            while bits < size {
+                acc |= (data[pos] as u32) << bits;
+                pos += 1;
+                bits += 8;
+            }
+            let code = (acc & ((1 << size) - 1)) as usize;
+            acc >>= size;
+            bits -= size;
+            if code == clear {
+                table = (0..clear).map(|c| vec![c as u8]).collect();
+                table.extend([Vec::new(), Vec::new()]);
+                size = min_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == clear + 1 {
+                return out;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) => [prev.clone(), vec![prev[0]]].concat(),
+                (None, None) => panic!("bad code {}", code),
+            };
+            if let Some(prev) = prev {
+                if table.len() < 0x1000 {
+                    table.push([prev, vec![entry[0]]].concat());
+                }
+                if table.len() == 1 << size && size < 12 {
+                    size += 1;
+                }
+            }
+            out.extend(&entry);
+            prev = Some(entry);
+        }
+    }
+
+    /// Read the pixels of every frame of a GIF written by `cartridge`.
+    fn frames(gif: &[u8]) -> Vec<Vec<u8>> {
+        assert_eq!(b"GIF89a", &gif[..6]);
+        let mut pos = 13 + 16 * 3;
+        let mut frames = Vec::new();
+        loop {
+            match gif[pos] {
+                0x21 => pos += 8,
+                0x2c => {
+                    let min_size = gif[pos + 10];
+                    pos += 11;
+                    let mut data = Vec::new();
+                    while gif[pos] != 0 {
+                        data.extend(&gif[pos + 1..pos + 1 + gif[pos] as usize]);
+                        pos += 1 + gif[pos] as usize;
+                    }
+                    pos += 1;
+                    frames.push(unlzw(&data, min_size));
+                }
+                0x3b => return frames,
+                b => panic!("unexpected block {:#x}", b),
+            }
+        }
+    }
+
+    /// Read the payload of a cartridge the way Octo does.
+    fn payload(gif: &[u8]) -> serde_json::Value {
+        let pixels: Vec<u8> = frames(gif).concat();
+        let bytes: Vec<u8> = pixels
+            .chunks(4)
+            .map(|p| p.iter().fold(0, |b, pixel| b << 2 | (pixel & 3)))
+            .collect();
+        let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+        serde_json::from_slice(&bytes[4..4 + len]).unwrap()
+    }
+
+    #[rstest]
+    fn test_intel_hex() {
+        let rom: Vec<u8> = (0..18).collect();
+        let expected = "\
+:10020000000102030405060708090A0B0C0D0E0F76
+:020210001011CB
+:00000001FF
+";
+        assert_eq!(expected, intel_hex(&rom, 0x200));
+    }
+
+    #[rstest]
+    fn test_intel_hex_past_64k() {
+        let expected = "\
+:02FFFE000102FE
+:020000040001F9
+:0100000003FC
+:00000001FF
+";
+        assert_eq!(expected, intel_hex(&[1, 2, 3], 0xfffe));
+    }
+
+    #[rstest]
+    fn test_c_array() {
+        let rom: Vec<u8> = (0..14).collect();
+        let expected = "\
+const unsigned char game[] = {
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
+    0x0c, 0x0d
+};
+const unsigned int game_len = 14;
+";
+        assert_eq!(expected, c_array(&rom, "game"));
+    }
+
+    #[rstest]
+    fn test_rust_array() {
+        let expected = "pub const GAME: [u8; 2] = [\n    0x00, 0xe0\n];\n";
+        assert_eq!(expected, rust_array(&[0x00, 0xe0], "game"));
+    }
+
+    #[rstest]
+    #[case(
+        "main",
+        "const unsigned char main_rom[] = {\n};\nconst unsigned int main_rom_len = 0;\n"
+    )]
+    #[case(
+        "int",
+        "const unsigned char int_rom[] = {\n};\nconst unsigned int int_rom_len = 0;\n"
+    )]
+    fn test_c_array_reserved(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(expected, c_array(&[], name));
+    }
+
+    #[rstest]
+    #[case("type", "pub const TYPE_ROM: [u8; 0] = [\n];\n")]
+    #[case("_", "pub const __ROM: [u8; 0] = [\n];\n")]
+    #[case("main", "pub const MAIN: [u8; 0] = [\n];\n")]
+    fn test_rust_array_reserved(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(expected, rust_array(&[], name));
+    }
+
+    #[rstest]
+    #[case("roms/pong.c8asm", "pong")]
+    #[case("my game.8o", "my_game")]
+    #[case("2048.c8asm", "_2048")]
+    fn test_identifier(#[case] path: &str, #[case] expected: &str) {
+        assert_eq!(expected, identifier(path));
+    }
+
+    #[rstest]
+    #[case::raw("raw", Ok(OutputFormat::Raw))]
+    #[case::octo("octo", Ok(OutputFormat::Octo))]
+    #[case::unknown(
+        "elf",
+        Err(String::from("Unknown format 'elf' (known formats: raw, hex, c, rust, octo)"))
+    )]
+    fn test_parse(#[case] name: &str, #[case] expected: Result<OutputFormat, String>) {
+        assert_eq!(expected, name.parse());
+    }
+
+    #[rstest]
+    fn test_lzw_round_trip() {
+        // Enough varied pixels to fill the code table and start it again.
+        let pixels: Vec<u8> = (0..40000u32).map(|i| (i * i / 7 % 16) as u8).collect();
+        assert_eq!(pixels, unlzw(&lzw(&pixels, 4), 4));
+    }
+
+    #[rstest]
+    fn test_cartridge() {
+        let program = ": main\n\tv0 := 1\n\tloop again\n";
+        let cart = cartridge(program);
+        let frames = frames(&cart);
+        assert_eq!(1, frames.len());
+        assert_eq!(CART_WIDTH * CART_HEIGHT, frames[0].len());
+        let label: Vec<u8> = frames[0].iter().map(|p| p >> 2).collect();
+        assert_eq!(label, super::label());
+        let payload = payload(&cart);
+        assert_eq!(program, payload["program"]);
+        assert_eq!(20, payload["options"]["tickrate"]);
+    }
+
+    #[rstest]
+    fn test_long_cartridge() {
+        let program = "# filler\n".repeat(1000);
+        let cart = cartridge(&program);
+        assert_eq!(3, frames(&cart).len());
+        assert_eq!(program, payload(&cart)["program"]);
+    }
+
+    #[rstest]
+    fn test_octo_source() {
+        let src = "$start mov V0, 1\n$top jmp $top\n";
+        let mut sources = Sources::new();
+        let file = sources.add("", src);
+        let assembled = assemble_sources(&mut sources, file, &|_| unreachable!()).unwrap();
+        let octo = octo_source(&assembled);
+        assert!(octo.contains(": top\n"));
+        assert_eq!(assembled.rom, assemble_octo(&octo).unwrap());
+    }
+
+    #[rstest]
+    fn test_keyword_label_cartridge() {
+        let src = "$loop call $if\njmp $loop\n$if retn\n";
+        let mut sources = Sources::new();
+        let file = sources.add("", src);
+        let assembled = assemble_sources(&mut sources, file, &|_| unreachable!()).unwrap();
+        let cart = cartridge(&octo_source(&assembled));
+        let program = payload(&cart)["program"].as_str().unwrap().to_string();
+        assert!(program.contains(": loop_\n"));
+        assert_eq!(assembled.rom, assemble_octo(&program).unwrap());
+    }
+}
@@ -0,0 +1,842 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::diagnostic::{Diagnostic, FileId, Sources, Span};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Reads the file at a path, for `include` and `incbin`.
+pub type Loader<'l> = &'l dyn Fn(&Path) -> io::Result<Vec<u8>>;
+
+/// Deepest nesting of macro uses, to stop a macro that uses itself.
+const MAX_DEPTH: usize = 32;
+
+/// Words that can't be used as macro names.
+pub(crate) const KEYWORDS: [&str; 45] = [
+    "mc", "clr", "retn", "jmp", "call", "skipeq", "skipne", "mov", "add", "or", "and", "xor",
+    "sub", "rshift", "subr", "lshift", "jmpv", "rand", "draw", "skipkeq", "skipkne", "input",
+    "sprite", "bcd", "store", "load", "data:", "equ", "const", "macro", "endm", "include",
+    "incbin", "org", "align", "fill", "space", "reserve", "byte", "word", "text", "charmap", "I",
+    "D", "S",
+];
+
+/// Where a byte of expanded source came from: an offset in one of the original files,
+/// and the macro use that produced it, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Origin {
+    file: FileId,
+    offset: usize,
+    expansion: Option<usize>,
+}
+
+/// Where the code on a line of source ends and its comment, if any, starts. A `;` or
+/// `//` inside quotes doesn't start a comment.
+pub(crate) fn comment_start(line: &str) -> usize {
+    let mut quote = None;
+    for (pos, c) in line.char_indices() {
+        match (c, quote) {
+            ('\'' | '"', None) => quote = Some(c),
+            (_, Some(q)) if c == q => quote = None,
+            (';', None) => return pos,
+            ('/', None) if line[pos..].starts_with("//") => return pos,
+            _ => (),
+        }
+    }
+    line.len()
+}
+
+/// A line of expanded source, with the origin of each byte.
+#[derive(Debug, Clone, Default)]
+struct Line {
+    text: String,
+    origins: Vec<Origin>,
+}
+
+impl Line {
+    /// Split the text of `file` into lines.
+    fn split(text: &str, file: FileId) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        for line in text.split_inclusive('\n') {
+            lines.push(Line {
+                text: String::from(line),
+                origins: (offset..offset + line.len())
+                    .map(|offset| Origin {
+                        file,
+                        offset,
+                        expansion: None,
+                    })
+                    .collect(),
+            });
+            offset += line.len();
+        }
+        lines
+    }
+
+    fn push_str(&mut self, s: &str, origin: Origin) {
+        self.text.push_str(s);
+        self.origins.resize(self.text.len(), origin);
+    }
+
+    fn append(&mut self, other: &Line) {
+        self.text.push_str(&other.text);
+        self.origins.extend_from_slice(&other.origins);
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Line {
+        Line {
+            text: String::from(&self.text[start..end]),
+            origins: self.origins[start..end].to_vec(),
+        }
+    }
+
+    /// Where the code on the line ends and its comment, if any, starts.
+    fn code_end(&self) -> usize {
+        comment_start(&self.text)
+    }
+
+    /// The code on the line after byte `start`, without any comment.
+    fn code_from(&self, start: usize) -> &str {
+        &self.text[start..self.code_end().max(start)]
+    }
+
+    /// The byte range of each whitespace-separated word before any comment.
+    fn words(&self) -> Vec<(usize, usize)> {
+        let mut words = Vec::new();
+        let mut start = None;
+        let code = self.code_from(0);
+        for (pos, c) in code.char_indices().chain([(code.len(), ' ')]) {
+            match (c.is_whitespace(), start) {
+                (true, Some(s)) => {
+                    words.push((s, pos));
+                    start = None;
+                }
+                (false, None) => start = Some(pos),
+                _ => (),
+            }
+        }
+        words
+    }
+
+    fn word(&self, range: (usize, usize)) -> &str {
+        &self.text[range.0..range.1]
+    }
+
+    /// The span of original source covered by `start..end`.
+    fn span(&self, start: usize, end: usize) -> Span {
+        let first = self.origins[start];
+        let last = self.origins[end.max(start + 1) - 1];
+        let end = if last.expansion == first.expansion
+            && last.file == first.file
+            && last.offset >= first.offset
+        {
+            last.offset + 1
+        } else {
+            first.offset + 1
+        };
+        Span::new(first.file, first.offset, end)
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Line>,
+    /// The name in the definition.
+    def: Span,
+}
+
+/// One use of a macro.
+struct Expansion {
+    name: String,
+    call: Span,
+    def: Span,
+    parent: Option<usize>,
+}
+
+/// Source with every file included and every macro expanded, able to point diagnostics
+/// in it back at the original sources.
+pub struct Expanded {
+    pub text: String,
+    file: FileId,
+    origins: Vec<Origin>,
+    expansions: Vec<Expansion>,
+}
+
+impl Expanded {
+    /// Move a diagnostic on the expanded source to the original source. One found inside
+    /// a macro gains notes pointing at the macro use and definition.
+    pub fn map(&self, mut diag: Diagnostic) -> Diagnostic {
+        let mut notes = Vec::new();
+        if let Some(span) = diag.span {
+            let (mapped, mut expansion) = self.map_span(span);
+            diag.span = Some(mapped);
+            if let Some(e) = expansion {
+                notes.push((
+                    Some(self.expansions[e].def),
+                    format!("macro `{}` is defined here", self.expansions[e].name),
+                ));
+            }
+            while let Some(e) = expansion {
+                let exp = &self.expansions[e];
+                notes.push((
+                    Some(exp.call),
+                    format!("in this use of macro `{}`", exp.name),
+                ));
+                expansion = exp.parent;
+            }
+        }
+        for (span, _) in &mut diag.notes {
+            *span = span.map(|s| self.map_span(s).0);
+        }
+        diag.notes.append(&mut notes);
+        diag
+    }
+
+    /// Move a span on the expanded source to the original source.
+    pub fn origin(&self, span: Span) -> Span {
+        self.map_span(span).0
+    }
+
+    fn map_span(&self, span: Span) -> (Span, Option<usize>) {
+        if span.file != self.file {
+            return (span, None);
+        }
+        let first = self.origins[span.start.min(self.origins.len() - 1)];
+        let end = match span.end.checked_sub(1).and_then(|e| self.origins.get(e)) {
+            Some(last)
+                if last.expansion == first.expansion
+                    && last.file == first.file
+                    && last.offset >= first.offset =>
+            {
+                last.offset + 1
+            }
+            _ => first.offset + (span.end > span.start) as usize,
+        };
+        (Span::new(first.file, first.offset, end), first.expansion)
+    }
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_label(word: &str) -> bool {
+    matches!(word.strip_prefix('$'), Some(name) if is_name(name) && !name.starts_with('_'))
+}
+
+/// Split `text` at its top-level commas, giving the trimmed range of each piece.
+fn split_args(text: &str) -> Vec<(usize, usize)> {
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+    for (pos, c) in text.char_indices().chain([(text.len(), ',')]) {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth <= 0 => {
+                let arg = &text[start..pos];
+                let lead = arg.len() - arg.trim_start().len();
+                args.push((start + lead, start + arg.trim_end().len()));
+                start = pos + 1;
+            }
+            _ => (),
+        }
+    }
+    args
+}
+
+/// Whether an argument can be substituted as it is, rather than in parentheses.
+fn is_single_token(arg: &str) -> bool {
+    arg.chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '#')
+        || (arg.len() == 3 && arg.starts_with('\'') && arg.ends_with('\''))
+}
+
+struct Expander<'s, 'l> {
+    sources: &'s mut Sources,
+    load: Loader<'l>,
+    /// Files being included, innermost last.
+    including: Vec<PathBuf>,
+    macros: HashMap<String, Macro>,
+    expansions: Vec<Expansion>,
+    diags: Vec<Diagnostic>,
+}
+
+/// Resolve `.` and `..` in a path without looking at the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normal.file_name().is_some() => {
+                normal.pop();
+            }
+            _ => normal.push(component),
+        }
+    }
+    normal
+}
+
+/// The range of the quoted file name in `text`, without its quotes.
+fn quoted(text: &str) -> Option<(usize, usize)> {
+    let trimmed = text.trim();
+    let start = text.len() - text.trim_start().len();
+    if trimmed.len() >= 2 && trimmed.starts_with('"') && trimmed.ends_with('"') {
+        Some((start + 1, start + trimmed.len() - 1))
+    } else {
+        None
+    }
+}
+
+impl Expander<'_, '_> {
+    fn error(&mut self, message: String, span: Span) {
+        self.diags.push(Diagnostic::error(message, Some(span)));
+    }
+
+    fn expand_lines(&mut self, lines: Vec<Line>, depth: usize, out: &mut Vec<Line>) {
+        let mut lines = lines.into_iter();
+        while let Some(line) = lines.next() {
+            let words = line.words();
+            let first = words.first().map(|w| line.word(*w));
+            if first == Some("macro") {
+                let mut body = Vec::new();
+                let mut closed = false;
+                for body_line in lines.by_ref() {
+                    let words = body_line.words();
+                    if words.first().map(|w| body_line.word(*w)) == Some("endm") {
+                        closed = true;
+                        break;
+                    }
+                    body.push(body_line);
+                }
+                if closed {
+                    self.define(&line, &words, body);
+                } else {
+                    let span = line.span(words[0].0, words[0].1);
+                    self.error(String::from("macro is never closed with `endm`"), span);
+                }
+                continue;
+            }
+            if first == Some("include") {
+                self.include(&line, &words, depth, out);
+                continue;
+            }
+            if first == Some("endm") {
+                let span = line.span(words[0].0, words[0].1);
+                self.error(String::from("`endm` outside of a macro"), span);
+                continue;
+            }
+            // The macro name may follow a label, which is given to the first line of
+            // the expansion.
+            let name_at = match first {
+                Some(word) if is_label(word) => 1,
+                _ => 0,
+            };
+            match words.get(name_at) {
+                Some(w) if line.word(*w) == "incbin" => self.incbin(&line, &words, name_at, out),
+                Some(w) if self.macros.contains_key(line.word(*w)) => {
+                    self.expand(&line, &words, name_at, depth, out)
+                }
+                _ => out.push(line),
+            }
+        }
+    }
+
+    /// Find and read the file named after word `at` of a directive on `line`, relative to
+    /// the file containing the line.
+    fn load_named(
+        &mut self,
+        line: &Line,
+        words: &[(usize, usize)],
+        at: usize,
+    ) -> Option<(PathBuf, Span, Vec<u8>)> {
+        let (_, directive_end) = words[at];
+        let (start, end) = match quoted(line.code_from(directive_end)) {
+            Some((start, end)) => (directive_end + start, directive_end + end),
+            None => {
+                let span = line.span(words[at].0, directive_end);
+                self.error(String::from("expected a file name in quotes"), span);
+                return None;
+            }
+        };
+        let span = line.span(start - 1, end + 1);
+        let including = Path::new(&self.sources.get(span.file).name);
+        let path = normalize(
+            &including
+                .parent()
+                .unwrap_or(Path::new(""))
+                .join(&line.text[start..end]),
+        );
+        match (self.load)(&path) {
+            Ok(bytes) => Some((path, span, bytes)),
+            Err(e) => {
+                self.error(format!("can't read {}: {}", path.display(), e), span);
+                None
+            }
+        }
+    }
+
+    /// Replace an `include` line with the lines of the file it names.
+    fn include(
+        &mut self,
+        line: &Line,
+        words: &[(usize, usize)],
+        depth: usize,
+        out: &mut Vec<Line>,
+    ) {
+        let (path, span, bytes) = match self.load_named(line, words, 0) {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        if let Some(first) = self.including.iter().position(|p| *p == path) {
+            let chain: Vec<String> = self.including[first..]
+                .iter()
+                .chain([&path])
+                .map(|p| p.display().to_string())
+                .collect();
+            let message = format!("include cycle: {}", chain.join(" → "));
+            return self.error(message, span);
+        }
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => {
+                let message = format!(
+                    "{} is not a text file (use incbin for binary data)",
+                    path.display()
+                );
+                return self.error(message, span);
+            }
+        };
+        let file = self.sources.add(&path.to_string_lossy(), &text);
+        self.including.push(path);
+        self.expand_lines(Line::split(&text, file), depth, out);
+        self.including.pop();
+    }
+
+    /// Replace an `incbin` line, whose directive is word `at`, with the bytes of the file
+    /// it names.
+    fn incbin(&mut self, line: &Line, words: &[(usize, usize)], at: usize, out: &mut Vec<Line>) {
+        let (_, span, bytes) = match self.load_named(line, words, at) {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        if bytes.is_empty() {
+            return self.error(String::from("included file is empty"), span);
+        }
+        let origin = line.origins[words[at].0];
+        let mut data = line.slice(0, words[at].0);
+        data.push_str("data: ", origin);
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        data.push_str(&hex, origin);
+        data.push_str("\n", origin);
+        out.push(data);
+    }
+
+    /// Record the macro defined by `header` and `body`.
+    fn define(&mut self, header: &Line, words: &[(usize, usize)], body: Vec<Line>) {
+        let header_span = header.span(words[0].0, words[0].1);
+        let (name_start, name_end) = match words.get(1) {
+            Some(word) => *word,
+            None => return self.error(String::from("expected a macro name"), header_span),
+        };
+        let name = header.word((name_start, name_end));
+        let def = header.span(name_start, name_end);
+        if !is_name(name) || KEYWORDS.contains(&name) {
+            return self.error(format!("`{}` can't be used as a macro name", name), def);
+        }
+        let mut params = Vec::new();
+        let rest = header.code_from(name_end);
+        if !rest.trim().is_empty() {
+            for (start, end) in split_args(rest) {
+                let param = &rest[start..end];
+                let span = header.span(name_end + start, name_end + end);
+                if !is_label(param) {
+                    return self.error(
+                        format!("macro parameters are written like labels, not `{}`", param),
+                        span,
+                    );
+                }
+                if params.iter().any(|p| p == param) {
+                    return self.error(format!("duplicate parameter: {}", param), span);
+                }
+                params.push(String::from(param));
+            }
+        }
+        if let Some(first) = self.macros.get(name) {
+            let diag = Diagnostic::error(format!("duplicate macro: {}", name), Some(def))
+                .with_note(Some(first.def), String::from("first defined here"));
+            return self.diags.push(diag);
+        }
+        self.macros
+            .insert(String::from(name), Macro { params, body, def });
+    }
+
+    /// Expand the use of a macro on `line`, whose name is word `name_at`.
+    fn expand(
+        &mut self,
+        line: &Line,
+        words: &[(usize, usize)],
+        name_at: usize,
+        depth: usize,
+        out: &mut Vec<Line>,
+    ) {
+        let (name_start, name_end) = words[name_at];
+        let name = line.word(words[name_at]);
+        let call = line.span(name_start, line.code_from(0).trim_end().len());
+        let mac = &self.macros[name];
+        if depth >= MAX_DEPTH {
+            let diag = Diagnostic::error(
+                format!("macros are nested too deeply (does `{}` use itself?)", name),
+                Some(call),
+            );
+            return self.diags.push(diag);
+        }
+        let rest = line.code_from(name_end);
+        let args: Vec<Line> = if rest.trim().is_empty() {
+            Vec::new()
+        } else {
+            split_args(rest)
+                .into_iter()
+                .map(|(s, e)| line.slice(name_end + s, name_end + e))
+                .collect()
+        };
+        if args.len() != mac.params.len() {
+            let diag = Diagnostic::error(
+                format!(
+                    "macro `{}` takes {} argument{} but {} {} given",
+                    name,
+                    mac.params.len(),
+                    if mac.params.len() == 1 { "" } else { "s" },
+                    args.len(),
+                    if args.len() == 1 { "was" } else { "were" }
+                ),
+                Some(call),
+            )
+            .with_note(Some(mac.def), format!("macro `{}` is defined here", name));
+            return self.diags.push(diag);
+        }
+
+        let index = self.expansions.len();
+        self.expansions.push(Expansion {
+            name: String::from(name),
+            call,
+            def: mac.def,
+            parent: line.origins[name_start].expansion,
+        });
+        // Arguments keep pointing at the call site but are now part of this expansion.
+        let args: Vec<Line> = args
+            .into_iter()
+            .map(|mut arg| {
+                for origin in &mut arg.origins {
+                    origin.expansion = Some(index);
+                }
+                arg
+            })
+            .collect();
+        let args: HashMap<&str, &Line> = mac.params.iter().map(|p| p.as_str()).zip(&args).collect();
+        // Labels defined in the body are renamed for each use so they don't clash.
+        let locals: HashSet<&str> = mac
+            .body
+            .iter()
+            .filter_map(|l| l.words().first().map(|w| l.word(*w)))
+            .filter(|w| is_label(w) && !args.contains_key(w))
+            .collect();
+
+        let mut expanded = Vec::new();
+        for body_line in &mac.body {
+            let mut new_line = Line::default();
+            // Only the code is searched for parameters; the comment is kept as it is.
+            let text = body_line.code_from(0);
+            let mut pos = 0;
+            while pos < text.len() {
+                let origin = Origin {
+                    expansion: Some(index),
+                    ..body_line.origins[pos]
+                };
+                let end = text[pos + 1..]
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .map_or(text.len(), |e| pos + 1 + e);
+                let word = &text[pos..end];
+                if !text[pos..].starts_with('$') || !is_label(word) {
+                    let len = text[pos..].chars().next().unwrap().len_utf8();
+                    new_line.push_str(&text[pos..pos + len], origin);
+                    pos += len;
+                    continue;
+                }
+                if let Some(arg) = args.get(word) {
+                    if is_single_token(&arg.text) {
+                        new_line.append(arg);
+                    } else {
+                        new_line.push_str("(", origin);
+                        new_line.append(arg);
+                        new_line.push_str(")", origin);
+                    }
+                } else if locals.contains(word) {
+                    new_line.push_str(&format!("{}@{}", word, index + 1), origin);
+                } else {
+                    new_line.push_str(word, origin);
+                }
+                pos = end;
+            }
+            new_line.append(&body_line.slice(text.len(), body_line.text.len()));
+            expanded.push(new_line);
+        }
+        if name_at == 1 {
+            let mut label = line.slice(0, name_start);
+            match expanded.first_mut() {
+                Some(first) => {
+                    label.append(first);
+                    *first = label;
+                }
+                None => {
+                    let span = line.span(words[0].0, words[0].1);
+                    self.error(
+                        format!("macro `{}` is empty, so can't be labelled", name),
+                        span,
+                    );
+                }
+            }
+        }
+        self.expand_lines(expanded, depth + 1, out);
+    }
+}
+
+/// Include every file and expand every macro in the source of `file`, adding included
+/// files to `sources`.
+///
+/// A macro is defined by the lines between `macro name $param, ...` and `endm`, and
+/// used by a line giving its name and arguments. Files named by `include` and `incbin`
+/// are found relative to the file naming them and read with `load`. Problems found are
+/// added to `diags`.
+pub fn expand(
+    sources: &mut Sources,
+    file: FileId,
+    load: Loader,
+    diags: &mut Vec<Diagnostic>,
+) -> Expanded {
+    let input = sources.get(file).text.clone();
+    let lines = Line::split(&input, file);
+    let mut expander = Expander {
+        including: vec![normalize(Path::new(&sources.get(file).name))],
+        sources,
+        load,
+        macros: HashMap::new(),
+        expansions: Vec::new(),
+        diags: Vec::new(),
+    };
+    let mut out = Vec::new();
+    expander.expand_lines(lines, 0, &mut out);
+    diags.append(&mut expander.diags);
+
+    let mut text = Line::default();
+    for mut line in out {
+        if !line.text.ends_with('\n') {
+            let origin = line.origins.last().copied().unwrap_or(Origin {
+                file,
+                offset: input.len(),
+                expansion: None,
+            });
+            line.push_str("\n", origin);
+        }
+        text.append(&line);
+    }
+    // A span at the very end of the source.
+    text.origins.push(Origin {
+        file,
+        offset: input.len(),
+        expansion: None,
+    });
+    Expanded {
+        text: text.text,
+        file,
+        origins: text.origins,
+        expansions: expander.expansions,
+    }
+}
+
+#[cfg(test)]
+mod preprocess_tests {
+    use super::*;
+    use rstest::*;
+
+    fn no_files(path: &Path) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            path.display().to_string(),
+        ))
+    }
+
+    fn files(path: &Path) -> io::Result<Vec<u8>> {
+        match path.to_str() {
+            Some("lib/util.c8") => Ok(Vec::from(
+                "macro two\nclr\nclr\nendm\ninclude \"font.c8\"\n",
+            )),
+            Some("lib/font.c8") => Ok(Vec::from("$font incbin \"../font.bin\"\n")),
+            Some("font.bin") => Ok(vec![0xf0, 0x90]),
+            Some("loop.c8") => Ok(Vec::from("include \"./main.c8\"\n")),
+            Some("main.c8") => Ok(Vec::from("include \"loop.c8\"\n")),
+            Some("empty.bin") => Ok(Vec::new()),
+            Some("bad.c8") => Ok(vec![0xff]),
+            _ => no_files(path),
+        }
+    }
+
+    fn expand_with(input: &str, load: Loader) -> (String, Vec<String>) {
+        let mut sources = Sources::new();
+        let file = sources.add("main.c8", input);
+        let mut diags = Vec::new();
+        let expanded = expand(&mut sources, file, load, &mut diags);
+        (
+            expanded.text,
+            diags.into_iter().map(|d| d.message).collect(),
+        )
+    }
+
+    fn expand_text(input: &str) -> (String, Vec<String>) {
+        expand_with(input, &no_files)
+    }
+
+    #[rstest]
+    fn test_include() {
+        let src = "include \"lib/util.c8\"\ntwo\n";
+        let expected = "$font data: f090\nclr\nclr\n";
+        assert_eq!((String::from(expected), vec![]), expand_with(src, &files));
+    }
+
+    #[rstest]
+    #[case("include \"loop.c8\"\n", "include cycle: main.c8 → loop.c8 → main.c8")]
+    #[case("include \"main.c8\"\n", "include cycle: main.c8 → main.c8")]
+    #[case("include \"none.c8\"\n", "can't read none.c8: none.c8")]
+    #[case("include none.c8\n", "expected a file name in quotes")]
+    #[case(
+        "include \"bad.c8\"\n",
+        "bad.c8 is not a text file (use incbin for binary data)"
+    )]
+    #[case("incbin \"empty.bin\"\n", "included file is empty")]
+    fn test_include_errors(#[case] src: &str, #[case] expected: &str) {
+        assert_eq!(vec![String::from(expected)], expand_with(src, &files).1);
+    }
+
+    #[rstest]
+    fn test_map_into_included_file() {
+        let mut sources = Sources::new();
+        let file = sources.add("main.c8", "clr\ninclude \"lib/font.c8\"\n");
+        let expanded = expand(&mut sources, file, &files, &mut Vec::new());
+        assert_eq!("clr\n$font data: f090\n", expanded.text);
+        let diag = Diagnostic::error(String::from("e"), Some(Span::new(file, 4, 9)));
+        assert_eq!("lib/font.c8", sources.get(1).name);
+        assert_eq!(Some(Span::new(1, 0, 5)), expanded.map(diag).span);
+    }
+
+    #[rstest]
+    fn test_no_macros() {
+        let src = "clr\n$a jmp $a";
+        assert_eq!((String::from("clr\n$a jmp $a\n"), vec![]), expand_text(src));
+    }
+
+    #[rstest]
+    fn test_expand() {
+        let src = "macro put $x, $y, $n\nmov I, $n\ndraw $x, $y, 5\nendm\nput V1, V2, $a + 5\n";
+        let expected = "mov I, ($a + 5)\ndraw V1, V2, 5\n";
+        assert_eq!((String::from(expected), vec![]), expand_text(src));
+    }
+
+    #[rstest]
+    fn test_comments() {
+        let src = "macro put $x ; draws $x\ndraw $x, $x, 5 // $x twice\nendm ; done\nput V1 ; go\n";
+        let expected = "draw V1, V1, 5 // $x twice\n";
+        assert_eq!((String::from(expected), vec![]), expand_text(src));
+    }
+
+    #[rstest]
+    #[case("clr ; note", 4)]
+    #[case("clr // note", 4)]
+    #[case("mov V0, ';' ; note", 12)]
+    #[case("include \"a;b\"", 13)]
+    #[case("clr", 3)]
+    fn test_code_end(#[case] text: &str, #[case] expected: usize) {
+        assert_eq!(expected, Line::split(text, 0)[0].code_end());
+    }
+
+    #[rstest]
+    fn test_local_labels() {
+        let src = "macro wait\n$l skipeq V0, 0\njmp $l\nendm\nwait\n$w wait\n";
+        let expected = "$l@1 skipeq V0, 0\njmp $l@1\n$w $l@2 skipeq V0, 0\njmp $l@2\n";
+        assert_eq!((String::from(expected), vec![]), expand_text(src));
+    }
+
+    #[rstest]
+    fn test_nested() {
+        let src = "macro one $r\nadd $r, 1\nendm\nmacro two $r\none $r\none $r\nendm\ntwo V3\n";
+        let expected = "add V3, 1\nadd V3, 1\n";
+        assert_eq!((String::from(expected), vec![]), expand_text(src));
+    }
+
+    #[rstest]
+    #[case("macro m $a\nendm\nm\n", "macro `m` takes 1 argument but 0 were given")]
+    #[case(
+        "macro m\nendm\nm 1, 2\n",
+        "macro `m` takes 0 arguments but 2 were given"
+    )]
+    #[case(
+        "macro m\nm\nendm\nm\n",
+        "macros are nested too deeply (does `m` use itself?)"
+    )]
+    #[case("macro mov\nendm\n", "`mov` can't be used as a macro name")]
+    #[case(
+        "macro m x\nendm\n",
+        "macro parameters are written like labels, not `x`"
+    )]
+    #[case("macro m\nendm\nmacro m\nendm\n", "duplicate macro: m")]
+    #[case("endm\n", "`endm` outside of a macro")]
+    #[case("macro m\nclr\n", "macro is never closed with `endm`")]
+    fn test_errors(#[case] src: &str, #[case] expected: &str) {
+        assert_eq!(vec![String::from(expected)], expand_text(src).1);
+    }
+
+    #[rstest]
+    fn test_map_into_definition() {
+        let src = "macro m $v\nmov V0, $v\nendm\nclr\nm #100\n";
+        let mut sources = Sources::new();
+        sources.add("", src);
+        let mut diags = Vec::new();
+        let expanded = expand(&mut sources, 0, &no_files, &mut diags);
+        assert_eq!("clr\nmov V0, #100\n", expanded.text);
+        // The operand comes from the argument at the call site.
+        let arg = expanded.text.find("#100").unwrap();
+        let diag = Diagnostic::error(String::from("e"), Some(Span::new(0, arg, arg + 4)));
+        let mapped = expanded.map(diag);
+        assert_eq!(Some(Span::new(0, 33, 37)), mapped.span);
+        assert_eq!(
+            vec![
+                (
+                    Some(Span::new(0, 6, 7)),
+                    String::from("macro `m` is defined here")
+                ),
+                (
+                    Some(Span::new(0, 31, 37)),
+                    String::from("in this use of macro `m`")
+                ),
+            ],
+            mapped.notes
+        );
+        // The mnemonic comes from the body.
+        let diag = Diagnostic::error(String::from("e"), Some(Span::new(0, 4, 7)));
+        assert_eq!(Some(Span::new(0, 11, 14)), expanded.map(diag).span);
+    }
+}
@@ -0,0 +1,237 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use std::str::FromStr;
+
+/// An RGB colour.
+pub type Rgb = (u8, u8, u8);
+
+/// The colours used to draw the display.
+///
+/// Four colours are held, as in XO-CHIP palettes: the background, the first plane, the
+/// second plane, and both planes together. Only the background and first plane are drawn;
+/// the other two are reserved for when XO-CHIP's second plane is emulated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colours: [Rgb; 4],
+}
+
+/// The built-in palettes, selectable by name.
+pub const PALETTES: [(&str, Palette); 6] = [
+    (
+        "mono",
+        Palette {
+            colours: [
+                (0x00, 0x00, 0x00),
+                (0xff, 0xff, 0xff),
+                (0xaa, 0xaa, 0xaa),
+                (0x55, 0x55, 0x55),
+            ],
+        },
+    ),
+    (
+        "inverse",
+        Palette {
+            colours: [
+                (0xff, 0xff, 0xff),
+                (0x00, 0x00, 0x00),
+                (0x55, 0x55, 0x55),
+                (0xaa, 0xaa, 0xaa),
+            ],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            colours: [
+                (0x1a, 0x0d, 0x00),
+                (0xff, 0xb0, 0x00),
+                (0xcc, 0x6a, 0x00),
+                (0xff, 0xe0, 0x80),
+            ],
+        },
+    ),
+    (
+        "green",
+        Palette {
+            colours: [
+                (0x00, 0x14, 0x00),
+                (0x33, 0xff, 0x33),
+                (0x1a, 0x99, 0x1a),
+                (0xb3, 0xff, 0xb3),
+            ],
+        },
+    ),
+    (
+        "lcd",
+        Palette {
+            colours: [
+                (0x9b, 0xbc, 0x0f),
+                (0x0f, 0x38, 0x0f),
+                (0x30, 0x62, 0x30),
+                (0x8b, 0xac, 0x0f),
+            ],
+        },
+    ),
+    (
+        "octo",
+        Palette {
+            colours: [
+                (0x99, 0x66, 0x00),
+                (0xff, 0xcc, 0x00),
+                (0xff, 0x66, 0x00),
+                (0x66, 0x22, 0x00),
+            ],
+        },
+    ),
+];
+
+impl Palette {
+    /// Look up one of the built-in palettes by name.
+    pub fn named(name: &str) -> Option<Palette> {
+        PALETTES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, p)| *p)
+    }
+
+    /// Colour of pixels that are switched off.
+    pub fn background(&self) -> Rgb {
+        self.colours[0]
+    }
+
+    /// Colour of pixels that are switched on.
+    pub fn foreground(&self) -> Rgb {
+        self.colours[1]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PALETTES[0].1
+    }
+}
+
+/// Parse a colour written as `rrggbb`, optionally prefixed with `#`.
+pub fn parse_colour(s: &str) -> Result<Rgb, String> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid colour '{}': expected six hex digits", s));
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok((channel(0), channel(2), channel(4)))
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Accepts either the name of a built-in palette or a comma-separated list of two or
+    /// four colours. With two colours the reserved XO-CHIP plane colours are derived from
+    /// them; four are accepted so that XO-CHIP palettes, such as those in the ROM database,
+    /// can be given as they are.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(p) = Palette::named(s) {
+            return Ok(p);
+        }
+
+        let colours = s
+            .split(',')
+            .map(parse_colour)
+            .collect::<Result<Vec<Rgb>, String>>()
+            .map_err(|e| {
+                let names: Vec<&str> = PALETTES.iter().map(|(n, _)| *n).collect();
+                format!("{} (known palettes: {})", e, names.join(", "))
+            })?;
+
+        match colours[..] {
+            [bg, fg] => Ok(Palette {
+                colours: [bg, fg, blend(bg, fg, 0.66), blend(bg, fg, 0.33)],
+            }),
+            [bg, fg, p2, both] => Ok(Palette {
+                colours: [bg, fg, p2, both],
+            }),
+            _ => Err(format!(
+                "Expected 2 or 4 colours in palette, found {}",
+                colours.len()
+            )),
+        }
+    }
+}
+
+/// Linearly interpolate between two colours, where `t` of 0 gives `from` and 1 gives `to`.
+pub fn blend(from: Rgb, to: Rgb, t: f32) -> Rgb {
+    let t = t.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    (mix(from.0, to.0), mix(from.1, to.1), mix(from.2, to.2))
+}
+
+#[cfg(test)]
+mod palette_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("#ff8000", (0xff, 0x80, 0x00))]
+    #[case("102030", (0x10, 0x20, 0x30))]
+    #[case("#AbCdEf", (0xab, 0xcd, 0xef))]
+    fn test_parse_colour(#[case] input: &str, #[case] expected: Rgb) {
+        assert_eq!(expected, parse_colour(input).unwrap());
+    }
+
+    #[rstest]
+    #[case("#ff80")]
+    #[case("#ff800000")]
+    #[case("#gg8000")]
+    fn test_parse_colour_fail(#[case] input: &str) {
+        assert!(parse_colour(input).is_err());
+    }
+
+    #[rstest]
+    #[case("mono")]
+    #[case("AMBER")]
+    #[case("lcd")]
+    fn test_named_palette(#[case] name: &str) {
+        assert!(name.parse::<Palette>().is_ok());
+    }
+
+    #[rstest]
+    fn test_two_colour_palette() {
+        let p: Palette = "#000000,#ffffff".parse().unwrap();
+        assert_eq!((0, 0, 0), p.background());
+        assert_eq!((0xff, 0xff, 0xff), p.foreground());
+    }
+
+    #[rstest]
+    fn test_four_colour_palette() {
+        let p: Palette = "#000000,#ff0000,#00ff00,#0000ff".parse().unwrap();
+        assert_eq!(
+            [(0, 0, 0), (0xff, 0, 0), (0, 0xff, 0), (0, 0, 0xff)],
+            p.colours
+        );
+    }
+
+    #[rstest]
+    #[case("nonsense")]
+    #[case("#000000")]
+    #[case("#000000,#111111,#222222")]
+    fn test_bad_palette(#[case] input: &str) {
+        assert!(input.parse::<Palette>().is_err());
+    }
+
+    #[rstest]
+    fn test_blend() {
+        assert_eq!((0, 0, 0), blend((0, 0, 0), (200, 100, 50), 0.0));
+        assert_eq!((100, 50, 25), blend((0, 0, 0), (200, 100, 50), 0.5));
+        assert_eq!((200, 100, 50), blend((0, 0, 0), (200, 100, 50), 1.0));
+    }
+}
@@ -0,0 +1,128 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use crate::machine::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Where the CHIP-8 display sits inside the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    /// Size of one CHIP-8 pixel in window pixels.
+    pub scale: u32,
+    /// Horizontal offset of the display from the left of the window.
+    pub x: i32,
+    /// Vertical offset of the display from the top of the window.
+    pub y: i32,
+}
+
+impl Viewport {
+    /// Fit the display into a window of the given size, using the largest integer scale
+    /// that fits and centring the result so any spare space is letterboxed.
+    pub fn fit(window_width: u32, window_height: u32) -> Viewport {
+        let scale = (window_width / DISPLAY_WIDTH as u32)
+            .min(window_height / DISPLAY_HEIGHT as u32)
+            .max(1);
+        let width = scale * DISPLAY_WIDTH as u32;
+        let height = scale * DISPLAY_HEIGHT as u32;
+        Viewport {
+            scale,
+            x: (window_width as i32 - width as i32) / 2,
+            y: (window_height as i32 - height as i32) / 2,
+        }
+    }
+
+    /// Window coordinates of the top-left corner of a display pixel.
+    pub fn pixel_origin(&self, x: usize, y: usize) -> (i32, i32) {
+        (
+            self.x + (x as u32 * self.scale) as i32,
+            self.y + (y as u32 * self.scale) as i32,
+        )
+    }
+}
+
+/// Tracks the brightness of each pixel so that pixels fade out over a few frames rather than
+/// switching off at once, which hides the flicker caused by sprites being erased and redrawn.
+pub struct Phosphor {
+    levels: Vec<f32>,
+    decay: f32,
+}
+
+impl Phosphor {
+    /// Create a phosphor where unlit pixels lose `decay` of their brightness each frame.
+    pub fn new(decay: f32) -> Phosphor {
+        Phosphor {
+            levels: vec![0.0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            decay: decay.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Advance one frame. Returns whether the pixel's brightness changed.
+    pub fn update(&mut self, idx: usize, lit: bool) -> bool {
+        let old = self.levels[idx];
+        let new = if lit {
+            1.0
+        } else if old < 0.05 {
+            0.0
+        } else {
+            old * (1.0 - self.decay)
+        };
+        self.levels[idx] = new;
+        new != old
+    }
+
+    /// Current brightness of a pixel between 0 and 1.
+    pub fn level(&self, idx: usize) -> f32 {
+        self.levels[idx]
+    }
+}
+
+#[cfg(test)]
+mod screen_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(640, 320, Viewport { scale: 10, x: 0, y: 0 })]
+    #[case(800, 320, Viewport { scale: 10, x: 80, y: 0 })]
+    #[case(650, 400, Viewport { scale: 10, x: 5, y: 40 })]
+    #[case(1920, 1080, Viewport { scale: 30, x: 0, y: 60 })]
+    #[case(10, 10, Viewport { scale: 1, x: -27, y: -11 })]
+    fn test_fit(#[case] w: u32, #[case] h: u32, #[case] expected: Viewport) {
+        assert_eq!(expected, Viewport::fit(w, h));
+    }
+
+    #[rstest]
+    fn test_pixel_origin() {
+        let v = Viewport {
+            scale: 4,
+            x: 10,
+            y: 2,
+        };
+        assert_eq!((10, 2), v.pixel_origin(0, 0));
+        assert_eq!((22, 10), v.pixel_origin(3, 2));
+    }
+
+    #[rstest]
+    fn test_phosphor_fades() {
+        let mut p = Phosphor::new(0.5);
+        assert!(p.update(0, true));
+        assert_eq!(1.0, p.level(0));
+        assert!(!p.update(0, true));
+        assert!(p.update(0, false));
+        assert_eq!(0.5, p.level(0));
+        for _ in 0..10 {
+            p.update(0, false);
+        }
+        assert_eq!(0.0, p.level(0));
+        assert!(!p.update(0, false));
+    }
+}
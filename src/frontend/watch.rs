@@ -0,0 +1,82 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Polls a file's modification time to notice when it has been rewritten.
+pub struct FileWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+    interval: Duration,
+    last_check: Instant,
+}
+
+impl FileWatcher {
+    /// Watch the given file, checking it at most once per `interval`.
+    pub fn new(path: &str, interval: Duration) -> FileWatcher {
+        FileWatcher {
+            path: path.to_string(),
+            modified: modification_time(path),
+            interval,
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Returns true once each time the file's modification time changes.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < self.interval {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let modified = modification_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn modification_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use rstest::*;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[rstest]
+    fn test_detects_change() {
+        let path = std::env::temp_dir().join(format!("rchip8-watch-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        File::create(path).unwrap().write_all(&[1]).unwrap();
+
+        let mut w = FileWatcher::new(path, Duration::from_secs(0));
+        assert!(!w.changed());
+
+        let f = File::options().write(true).open(path).unwrap();
+        f.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert!(w.changed());
+        assert!(!w.changed());
+
+        fs::remove_file(path).unwrap();
+        assert!(!w.changed());
+    }
+}
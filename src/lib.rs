@@ -1,3 +1,5 @@
 pub mod c8asc;
+pub mod frontend;
 pub mod game8;
 pub mod machine;
+pub mod romdb;
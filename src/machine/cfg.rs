@@ -0,0 +1,336 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::{
+    disassemble::disassemble_labelled,
+    insts::Chip8Inst,
+    labels::{generate_labels, Symbols},
+    trace::CodeMap,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control passes from one basic block to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// Execution runs on into the next block.
+    Next,
+    /// A `jmp` to the block.
+    Jump,
+    /// A skip instruction skipped the following instruction.
+    Skip,
+}
+
+/// A run of instructions with a single entry and a single exit.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub insts: Vec<(usize, Chip8Inst)>,
+    /// Blocks control can pass to when this block ends.
+    pub successors: Vec<(usize, Edge)>,
+    /// Subroutines called from this block.
+    pub calls: Vec<usize>,
+}
+
+/// The control-flow graph of the reachable code in a ROM.
+pub struct ControlFlow {
+    /// Basic blocks by start address.
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    /// Blocks making up each subroutine, by entry address. The ROM's entry point is
+    /// included as a subroutine. Blocks shared by several subroutines belong to the first.
+    pub subroutines: BTreeMap<usize, Vec<usize>>,
+    /// Ranges of bytes never reached as code, as start and end (exclusive) addresses.
+    pub unreached: Vec<(usize, usize)>,
+    labels: Symbols,
+}
+
+impl ControlFlow {
+    /// Split a ROM loaded at `start` into basic blocks by following control flow.
+    pub fn build(rom: &[u8], start: usize) -> ControlFlow {
+        let map = CodeMap::trace(rom, start);
+
+        let mut leaders = BTreeSet::from([start]);
+        for (pc, inst) in map.insts() {
+            match inst {
+                Chip8Inst::Jump(nnn) | Chip8Inst::SubCall(nnn) => {
+                    leaders.insert(nnn);
+                }
+                _ if is_skip(inst) => {
+                    leaders.insert(pc + 2);
+                    leaders.insert(pc + 4);
+                }
+                _ => (),
+            }
+        }
+        leaders.retain(|addr| map.inst(*addr).is_some());
+
+        let mut blocks = BTreeMap::new();
+        for leader in &leaders {
+            let mut block = BasicBlock {
+                insts: Vec::new(),
+                successors: Vec::new(),
+                calls: Vec::new(),
+            };
+            let mut pc = *leader;
+            while let Some(inst) = map.inst(pc) {
+                block.insts.push((pc, inst));
+                match inst {
+                    Chip8Inst::Jump(nnn) => block.successors.push((nnn, Edge::Jump)),
+                    Chip8Inst::SubReturn | Chip8Inst::JumpReg(_) => (),
+                    _ if is_skip(inst) => {
+                        block.successors.push((pc + 2, Edge::Next));
+                        block.successors.push((pc + 4, Edge::Skip));
+                    }
+                    _ => {
+                        if let Chip8Inst::SubCall(nnn) = inst {
+                            block.calls.push(nnn);
+                        }
+                        pc += 2;
+                        if leaders.contains(&pc) {
+                            block.successors.push((pc, Edge::Next));
+                            break;
+                        }
+                        continue;
+                    }
+                }
+                break;
+            }
+            block.successors.retain(|(addr, _)| leaders.contains(addr));
+            blocks.insert(*leader, block);
+        }
+
+        let mut entries = vec![start];
+        let calls: BTreeSet<usize> = blocks
+            .values()
+            .flat_map(|b| b.calls.iter().copied())
+            .filter(|addr| *addr != start && blocks.contains_key(addr))
+            .collect();
+        entries.extend(calls);
+        let mut owned = BTreeSet::new();
+        let mut subroutines = BTreeMap::new();
+        for entry in entries {
+            let mut members = Vec::new();
+            let mut pending = vec![entry];
+            while let Some(addr) = pending.pop() {
+                if owned.insert(addr) {
+                    members.push(addr);
+                    pending.extend(blocks[&addr].successors.iter().map(|(a, _)| *a));
+                }
+            }
+            members.sort_unstable();
+            subroutines.insert(entry, members);
+        }
+
+        let mut covered = vec![false; rom.len()];
+        for (pc, _) in map.insts() {
+            covered[pc - start] = true;
+            covered[pc - start + 1] = true;
+        }
+        let mut unreached = Vec::new();
+        let mut i = 0;
+        while i < covered.len() {
+            if covered[i] {
+                i += 1;
+                continue;
+            }
+            let from = i;
+            while i < covered.len() && !covered[i] {
+                i += 1;
+            }
+            unreached.push((start + from, start + i));
+        }
+
+        let lines = map.insts().map(|(pc, _)| pc).collect();
+        let labels = generate_labels(&map, &lines, &Symbols::new());
+        ControlFlow {
+            blocks,
+            subroutines,
+            unreached,
+            labels,
+        }
+    }
+
+    /// Render the graph in Graphviz DOT format, with each subroutine in its own cluster.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for (entry, members) in &self.subroutines {
+            writeln!(out, "    subgraph cluster_{:03x} {{", entry).unwrap();
+            writeln!(out, "        label=\"{}\";", escape(&self.name(*entry))).unwrap();
+            for addr in members {
+                writeln!(
+                    out,
+                    "        b{:03x} [label=\"{}\"];",
+                    addr,
+                    escape(&self.block_text(*addr))
+                )
+                .unwrap();
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+
+        for (from, (start, end)) in self.unreached.iter().enumerate() {
+            writeln!(
+                out,
+                "    d{} [shape=note, style=dashed, label=\"unreached {:#05x}-{:#05x}\\n{} bytes\"];",
+                from,
+                start,
+                end - 1,
+                end - start
+            )
+            .unwrap();
+        }
+
+        for (addr, block) in &self.blocks {
+            for (to, edge) in &block.successors {
+                let attrs = match edge {
+                    Edge::Next => "",
+                    Edge::Jump => " [label=\"jmp\"]",
+                    Edge::Skip => " [label=\"skip\"]",
+                };
+                writeln!(out, "    b{:03x} -> b{:03x}{};", addr, to, attrs).unwrap();
+            }
+            for to in &block.calls {
+                if self.blocks.contains_key(to) {
+                    writeln!(
+                        out,
+                        "    b{:03x} -> b{:03x} [style=dashed, label=\"call\"];",
+                        addr, to
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// Name of a subroutine for display.
+    fn name(&self, entry: usize) -> String {
+        match self.labels.get(&entry) {
+            Some(label) => label.clone(),
+            None => format!("{:#05x}", entry),
+        }
+    }
+
+    /// Disassembly of a block as left-justified DOT label lines.
+    fn block_text(&self, addr: usize) -> String {
+        let mut text = String::new();
+        if let Some(label) = self.labels.get(&addr) {
+            text.push_str(label);
+            text.push('\n');
+        }
+        for (pc, inst) in &self.blocks[&addr].insts {
+            let line = format!("{:#06x}  {}", pc, disassemble_labelled(*inst, &self.labels));
+            text.push_str(&line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+pub(crate) fn is_skip(inst: Chip8Inst) -> bool {
+    matches!(
+        inst,
+        Chip8Inst::SkipEqConst(..)
+            | Chip8Inst::SkipNeqConst(..)
+            | Chip8Inst::SkipEqReg(..)
+            | Chip8Inst::SkipNeqReg(..)
+            | Chip8Inst::SkipEqKey(_)
+            | Chip8Inst::SkipNeqKey(_)
+    )
+}
+
+/// Escape text for a DOT string, ending each line with `\l` to left-justify it.
+fn escape(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\l"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod cfg_tests {
+    use super::*;
+    use rstest::*;
+
+    // 200: call #20c
+    // 202: skipeq V0, #01
+    // 204: add V0, #01
+    // 206: jmp #202
+    // 208: unreached data
+    // 20c: mov V1, #02
+    // 20e: retn
+    #[fixture]
+    fn rom() -> Vec<u8> {
+        vec![
+            0x22, 0x0c, 0x30, 0x01, 0x70, 0x01, 0x12, 0x02, 0xff, 0xff, 0xff, 0xff, 0x61, 0x02,
+            0x00, 0xee,
+        ]
+    }
+
+    #[rstest]
+    fn test_blocks(rom: Vec<u8>) {
+        let cfg = ControlFlow::build(&rom, 0x200);
+        let starts: Vec<usize> = cfg.blocks.keys().copied().collect();
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206, 0x20c], starts);
+
+        assert_eq!(vec![(0x202, Edge::Next)], cfg.blocks[&0x200].successors);
+        assert_eq!(vec![0x20c], cfg.blocks[&0x200].calls);
+        assert_eq!(
+            vec![(0x204, Edge::Next), (0x206, Edge::Skip)],
+            cfg.blocks[&0x202].successors
+        );
+        assert_eq!(vec![(0x206, Edge::Next)], cfg.blocks[&0x204].successors);
+        assert_eq!(vec![(0x202, Edge::Jump)], cfg.blocks[&0x206].successors);
+        assert_eq!(2, cfg.blocks[&0x20c].insts.len());
+        assert!(cfg.blocks[&0x20c].successors.is_empty());
+    }
+
+    #[rstest]
+    fn test_subroutines_and_unreached(rom: Vec<u8>) {
+        let cfg = ControlFlow::build(&rom, 0x200);
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206], cfg.subroutines[&0x200]);
+        assert_eq!(vec![0x20c], cfg.subroutines[&0x20c]);
+        assert_eq!(vec![(0x208, 0x20c)], cfg.unreached);
+    }
+
+    #[rstest]
+    fn test_to_dot(rom: Vec<u8>) {
+        let dot = ControlFlow::build(&rom, 0x200).to_dot();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("subgraph cluster_20c {\n        label=\"$sub_20c\";"));
+        assert!(dot.contains("0x0200  call    $sub_20c\\l"));
+        assert!(dot.contains("    b202 -> b206 [label=\"skip\"];\n"));
+        assert!(dot.contains("    b206 -> b202 [label=\"jmp\"];\n"));
+        assert!(dot.contains("    b200 -> b20c [style=dashed, label=\"call\"];\n"));
+        assert!(dot.contains("unreached 0x208-0x20b\\n4 bytes"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[rstest]
+    #[case("a\"b", "a\\\"b")]
+    #[case("x\\y\n", "x\\\\y\\l")]
+    fn test_escape(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(expected, escape(text));
+    }
+}
@@ -0,0 +1,254 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::labels::Symbols;
+use std::fmt;
+
+/// The source line that some bytes of a ROM were assembled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub addr: usize,
+    pub len: usize,
+    /// Index into the list of files.
+    pub file: usize,
+    /// 1-based line number.
+    pub line: usize,
+    pub text: String,
+}
+
+/// Where each part of a ROM came from in its source, as written by `c8asc --debug-info`.
+///
+/// The file is line-based text:
+///
+/// ```text
+/// file game.c8asm
+/// line 200 2 0 3 $start input V0
+/// label 200 $start
+/// ```
+///
+/// `file` lines name the source files in order, counting from 0. `line` lines give the
+/// hex address and length of some bytes, then the file and line number they came from
+/// and the text of that line. `label` lines give the address of each label.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub files: Vec<String>,
+    pub lines: Vec<SourceLine>,
+    pub labels: Vec<(usize, String)>,
+}
+
+impl DebugInfo {
+    pub fn parse(text: &str) -> Result<DebugInfo, String> {
+        let mut info = DebugInfo::default();
+        for (i, line) in text.lines().enumerate() {
+            let err = |msg: &str| format!("line {}: {}", i + 1, msg);
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+            match kind {
+                "file" => info.files.push(String::from(rest)),
+                "line" => {
+                    let mut fields = rest.splitn(5, ' ');
+                    let mut next = |radix| {
+                        fields
+                            .next()
+                            .and_then(|f| usize::from_str_radix(f, radix).ok())
+                            .ok_or_else(|| err("expected an address, length, file and line"))
+                    };
+                    let (addr, len, file, line) = (next(16)?, next(10)?, next(10)?, next(10)?);
+                    if file >= info.files.len() {
+                        return Err(err("no such file"));
+                    }
+                    info.lines.push(SourceLine {
+                        addr,
+                        len,
+                        file,
+                        line,
+                        text: String::from(fields.next().unwrap_or("")),
+                    });
+                }
+                "label" => match rest.split_once(' ') {
+                    Some((addr, name)) => {
+                        let addr = usize::from_str_radix(addr, 16)
+                            .map_err(|_| err("expected an address and a name"))?;
+                        info.labels.push((addr, String::from(name)));
+                    }
+                    None => return Err(err("expected an address and a name")),
+                },
+                _ => return Err(err(&format!("unknown record: {}", kind))),
+            }
+        }
+        Ok(info)
+    }
+
+    /// The source line the byte at `addr` came from.
+    pub fn source_line(&self, addr: usize) -> Option<&SourceLine> {
+        self.lines
+            .iter()
+            .find(|l| l.addr <= addr && addr < l.addr + l.len)
+    }
+
+    /// Describe an address by its source location, e.g. `game.c8asm:14 (#20a)`, or just
+    /// the address if it didn't come from the source.
+    pub fn describe(&self, addr: usize) -> String {
+        match self.source_line(addr) {
+            Some(l) => format!("{}:{} (#{:03x})", self.files[l.file], l.line, addr),
+            None => format!("#{:03x}", addr),
+        }
+    }
+
+    /// Find the address of a label, with or without its `$`, or of the first code on a
+    /// line given as `file:line`. The file may be named by the end of its path.
+    pub fn resolve(&self, location: &str) -> Result<usize, String> {
+        if let Some((file, line)) = location
+            .rsplit_once(':')
+            .and_then(|(f, l)| Some((f, l.parse::<usize>().ok()?)))
+        {
+            let matches_file = |name: &str| {
+                name == file || name.ends_with(&format!("/{}", file.trim_start_matches("./")))
+            };
+            return self
+                .lines
+                .iter()
+                .filter(|l| l.line == line && matches_file(&self.files[l.file]))
+                .map(|l| l.addr)
+                .min()
+                .ok_or_else(|| format!("no code at {}", location));
+        }
+        let name = format!("${}", location.strip_prefix('$').unwrap_or(location));
+        self.labels
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(addr, _)| *addr)
+            .ok_or_else(|| format!("no label named {}", name))
+    }
+
+    /// Labels to use in a disassembly, naming each address by its first label. Labels
+    /// local to a use of a macro are left out.
+    pub fn symbols(&self) -> Symbols {
+        let mut symbols = Symbols::new();
+        for (addr, name) in &self.labels {
+            if !name.contains('@') {
+                symbols.entry(*addr).or_insert_with(|| name.clone());
+            }
+        }
+        symbols
+    }
+}
+
+impl fmt::Display for DebugInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for file in &self.files {
+            writeln!(f, "file {}", file)?;
+        }
+        for l in &self.lines {
+            writeln!(
+                f,
+                "line {:03x} {} {} {} {}",
+                l.addr, l.len, l.file, l.line, l.text
+            )?;
+        }
+        for (addr, name) in &self.labels {
+            writeln!(f, "label {:03x} {}", addr, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod debug_info_tests {
+    use super::*;
+    use rstest::*;
+
+    #[fixture]
+    fn info() -> DebugInfo {
+        DebugInfo {
+            files: vec![String::from("game.c8asm"), String::from("lib/font.c8asm")],
+            lines: vec![
+                SourceLine {
+                    addr: 0x200,
+                    len: 2,
+                    file: 0,
+                    line: 3,
+                    text: String::from("$start  clr"),
+                },
+                SourceLine {
+                    addr: 0x202,
+                    len: 4,
+                    file: 1,
+                    line: 1,
+                    text: String::from("$font   byte 1, 2, 3, 4"),
+                },
+            ],
+            labels: vec![
+                (0x200, String::from("$start")),
+                (0x202, String::from("$font")),
+                (0x202, String::from("$w@1")),
+            ],
+        }
+    }
+
+    #[rstest]
+    fn test_round_trip(info: DebugInfo) {
+        let text = info.to_string();
+        assert!(text.starts_with("file game.c8asm\nfile lib/font.c8asm\nline 200 2 0 3 $start"));
+        assert_eq!(Ok(info), DebugInfo::parse(&text));
+    }
+
+    #[rstest]
+    #[case(0x200, "game.c8asm:3 (#200)")]
+    #[case(0x205, "lib/font.c8asm:1 (#205)")]
+    #[case(0x206, "#206")]
+    fn test_describe(info: DebugInfo, #[case] addr: usize, #[case] expected: &str) {
+        assert_eq!(expected, info.describe(addr));
+    }
+
+    #[rstest]
+    #[case("$font", Ok(0x202))]
+    #[case("start", Ok(0x200))]
+    #[case("game.c8asm:3", Ok(0x200))]
+    #[case("font.c8asm:1", Ok(0x202))]
+    #[case("game.c8asm:4", Err("no code at game.c8asm:4"))]
+    #[case("$end", Err("no label named $end"))]
+    fn test_resolve(
+        info: DebugInfo,
+        #[case] location: &str,
+        #[case] expected: Result<usize, &str>,
+    ) {
+        assert_eq!(expected.map_err(String::from), info.resolve(location));
+    }
+
+    #[rstest]
+    fn test_symbols(info: DebugInfo) {
+        let expected: Symbols = [
+            (0x200, String::from("$start")),
+            (0x202, String::from("$font")),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(expected, info.symbols());
+    }
+
+    #[rstest]
+    #[case("line 200 2 0 3 clr", "line 1: no such file")]
+    #[case(
+        "file a\nline 200 x 0 3",
+        "line 2: expected an address, length, file and line"
+    )]
+    #[case("label 200", "line 1: expected an address and a name")]
+    #[case("frob 1", "line 1: unknown record: frob")]
+    fn test_parse_errors(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(Err(String::from(expected)), DebugInfo::parse(text));
+    }
+}
@@ -106,7 +106,7 @@ impl Chip8Machine {
 #[cfg(test)]
 mod decode_tests {
     use super::*;
-    use crate::machine::{Chip8Mode, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+    use crate::machine::{frame::Frame, Chip8Mode};
     use rstest::*;
     use std::sync::atomic::AtomicBool;
     use std::sync::{Arc, Condvar, Mutex};
@@ -118,8 +118,7 @@ mod decode_tests {
             Chip8Mode::Modern,
             Arc::new(Mutex::new(0)),
             Arc::new(Mutex::new(0)),
-            Arc::new(Mutex::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT])),
-            Arc::new([NEW_BOOL; DISPLAY_WIDTH * DISPLAY_HEIGHT]),
+            Arc::new(Mutex::new(Frame::new())),
             Arc::new([NEW_BOOL; 16]),
             Arc::new((Mutex::new(None), Condvar::new())),
         )
@@ -177,4 +176,17 @@ mod decode_tests {
     ) {
         assert!(Chip8Machine::decode(code).is_err());
     }
+
+    #[rstest]
+    fn test_opcode_decodes_to_same_instruction() {
+        for code in 0..=0xffffu16 {
+            if let Ok(inst) = Chip8Machine::decode(code) {
+                let opcode = inst.opcode();
+                assert_eq!(inst, Chip8Machine::decode(opcode).unwrap());
+                if code & 0xf000 != 0xe000 {
+                    assert_eq!(code, opcode);
+                }
+            }
+        }
+    }
 }
@@ -11,32 +11,77 @@
 // You should have received a copy of the GNU General Public License along with rchip8.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use crate::machine::insts::Chip8Inst;
+use crate::machine::{
+    insts::Chip8Inst,
+    labels::{generate_labels, Symbols},
+    trace::CodeMap,
+    Chip8Machine, DEFAULT_START,
+};
+use std::collections::BTreeSet;
 
+/// Most bytes written on one `data:` line.
+const DATA_LINE_LEN: usize = 8;
+
+/// Words with a meaning in Octo that could also be c8asm label names.
+const OCTO_RESERVED: [&str; 33] = [
+    "again",
+    "audio",
+    "bcd",
+    "begin",
+    "bighex",
+    "buzzer",
+    "clear",
+    "delay",
+    "else",
+    "end",
+    "exit",
+    "hex",
+    "hires",
+    "i",
+    "if",
+    "jump",
+    "jump0",
+    "key",
+    "load",
+    "loadflags",
+    "long",
+    "loop",
+    "lores",
+    "native",
+    "pitch",
+    "plane",
+    "random",
+    "return",
+    "save",
+    "saveflags",
+    "sprite",
+    "then",
+    "while",
+];
+
+/// Disassemble a single instruction into c8asm syntax, optionally prefixed with its address.
 pub fn disassemble(pc: Option<usize>, inst: Chip8Inst) -> String {
     let s = match inst {
         Chip8Inst::ClearScreen => "clr".to_string(),
-        Chip8Inst::Display(x, y, height) => format!("draw    V{:x}, V{:x}, {:x}", x, y, height),
-        Chip8Inst::MachineInst(nnn) => {
-            let hi = (nnn & 0xff00) >> 8;
-            let lo = nnn & 0xff;
-            format!("data:   {:02x} {:02x}", hi, lo)
+        Chip8Inst::Display(x, y, height) => {
+            format!("draw    V{:x}, V{:x}, #{:x}", x, y, height)
         }
-        Chip8Inst::Jump(nnn) => format!("jmp     {:03x}", nnn),
-        Chip8Inst::JumpReg(nnn) => format!("jmpv    {:03x}", nnn),
-        Chip8Inst::SubCall(nnn) => format!("call    {:03x}", nnn),
+        Chip8Inst::MachineInst(nnn) => format!("mc      #{:03x}", nnn),
+        Chip8Inst::Jump(nnn) => format!("jmp     #{:03x}", nnn),
+        Chip8Inst::JumpReg(nnn) => format!("jmpv    #{:03x}", nnn),
+        Chip8Inst::SubCall(nnn) => format!("call    #{:03x}", nnn),
         Chip8Inst::SubReturn => "retn".to_string(),
-        Chip8Inst::SkipEqConst(x, nn) => format!("skipeq  V{:x}, {:02x}", x, nn),
-        Chip8Inst::SkipNeqConst(x, nn) => format!("skipne  V{:x}, {:02x}", x, nn),
+        Chip8Inst::SkipEqConst(x, nn) => format!("skipeq  V{:x}, #{:02x}", x, nn),
+        Chip8Inst::SkipNeqConst(x, nn) => format!("skipne  V{:x}, #{:02x}", x, nn),
         Chip8Inst::SkipEqReg(x, y) => format!("skipeq  V{:x}, V{:x}", x, y),
         Chip8Inst::SkipNeqReg(x, y) => format!("skipne  V{:x}, V{:x}", x, y),
-        Chip8Inst::RegSet(x, nn) => format!("mov     V{:x}, {:02x}", x, nn),
-        Chip8Inst::RegAddNoCarry(x, nn) => format!("add     V{:x}, {:02x}", x, nn),
+        Chip8Inst::RegSet(x, nn) => format!("mov     V{:x}, #{:02x}", x, nn),
+        Chip8Inst::RegAddNoCarry(x, nn) => format!("add     V{:x}, #{:02x}", x, nn),
         Chip8Inst::Assign(x, y) => format!("mov     V{:x}, V{:x}", x, y),
         Chip8Inst::BinOr(x, y) => format!("or      V{:x}, V{:x}", x, y),
         Chip8Inst::BinAnd(x, y) => format!("and     V{:x}, V{:x}", x, y),
         Chip8Inst::BinXor(x, y) => format!("xor     V{:x}, V{:x}", x, y),
-        Chip8Inst::ArithAdd(x, y) => format!("addc    V{:x}, V{:x}", x, y),
+        Chip8Inst::ArithAdd(x, y) => format!("add     V{:x}, V{:x}", x, y),
         Chip8Inst::ArithSub(x, y) => format!("sub     V{:x}, V{:x}", x, y),
         Chip8Inst::ArithSubReverse(x, y) => format!("subr    V{:x}, V{:x}", x, y),
         Chip8Inst::ShiftLeft(x, y) => format!("lshift  V{:x}, V{:x}", x, y),
@@ -44,21 +89,454 @@ pub fn disassemble(pc: Option<usize>, inst: Chip8Inst) -> String {
         Chip8Inst::ReadDelay(x) => format!("mov     V{:x}, D", x),
         Chip8Inst::SetDelay(x) => format!("mov     D, V{:x}", x),
         Chip8Inst::SetSound(x) => format!("mov     S, V{:x}", x),
-        Chip8Inst::SetIndex(nnn) => format!("mov     I, {:03x}", nnn),
-        Chip8Inst::AddIndex(nnn) => format!("add     I, {:03x}", nnn),
-        Chip8Inst::Random(x, nn) => format!("rand    V{:x}, {:02x}", x, nn),
-        Chip8Inst::SkipEqKey(x) => format!("skipeqk V{:x}", x),
-        Chip8Inst::SkipNeqKey(x) => format!("skipnek V{:x}", x),
-        Chip8Inst::GetKey(x) => format!("read    V{:x}", x),
-        Chip8Inst::LoadFont(x) => format!("font    V{:x}", x),
+        Chip8Inst::SetIndex(nnn) => format!("mov     I, #{:03x}", nnn),
+        Chip8Inst::AddIndex(x) => format!("add     I, V{:x}", x),
+        Chip8Inst::Random(x, nn) => format!("rand    V{:x}, #{:02x}", x, nn),
+        Chip8Inst::SkipEqKey(x) => format!("skipkeq V{:x}", x),
+        Chip8Inst::SkipNeqKey(x) => format!("skipkne V{:x}", x),
+        Chip8Inst::GetKey(x) => format!("input   V{:x}", x),
+        Chip8Inst::LoadFont(x) => format!("sprite  V{:x}", x),
         Chip8Inst::BCDConvert(x) => format!("bcd     V{:x}", x),
-        Chip8Inst::StoreMem(x) => format!("str     V{:x}", x),
+        Chip8Inst::StoreMem(x) => format!("store   V{:x}", x),
         Chip8Inst::LoadMem(x) => format!("load    V{:x}", x),
     };
 
+    with_address(pc, s)
+}
+
+/// Disassemble raw bytes as a `data:` line.
+pub fn disassemble_data(pc: Option<usize>, bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    with_address(pc, format!("data:   {}", hex))
+}
+
+/// Source syntax to disassemble into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// The c8asm language accepted by c8asc by default.
+    C8asm,
+    /// Octo, which c8asc accepts with `--octo`.
+    Octo,
+}
+
+/// A line of disassembly: its address, the instruction or `None` for data, and its length.
+type Line = (usize, Option<Chip8Inst>, usize);
+
+/// Disassemble a whole ROM loaded at `start`, one line per word.
+///
+/// Without addresses the output is a program that c8asc assembles back into the same
+/// bytes: words that don't decode, or that decode to an instruction with a different
+/// canonical opcode, are written as data, as is a trailing odd byte.
+pub fn disassemble_rom(rom: &[u8], start: usize, addresses: bool, syntax: Syntax) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pc = start;
+    for buf in rom.chunks(2) {
+        let code = match buf {
+            [hi, lo] => Some((*hi as u16) << 8 | *lo as u16),
+            _ => None,
+        };
+        let inst = match code.map(|c| (c, Chip8Machine::decode(c))) {
+            Some((c, Ok(inst))) if inst.opcode() == c => Some(inst),
+            _ => None,
+        };
+        lines.push((pc, inst, buf.len()));
+        pc += buf.len();
+    }
+    render(rom, start, lines, &Symbols::new(), addresses, syntax)
+}
+
+/// Disassemble a ROM loaded at `start` by following control flow from the entry point.
+///
+/// Bytes that are never reached as code are written as data, starting a new line at each
+/// address loaded into I so that sprites stand out. Targets of jumps, calls and `mov I`
+/// are labelled, using names from `symbols` where given. Like `disassemble_rom`, the
+/// output without addresses assembles back into the same bytes. Octo places programs at
+/// 200, so labels are only used in Octo syntax for ROMs loaded there.
+pub fn disassemble_traced(
+    rom: &[u8],
+    start: usize,
+    addresses: bool,
+    symbols: &Symbols,
+    syntax: Syntax,
+) -> Vec<String> {
+    let map = CodeMap::trace(rom, start);
+    let lines = layout(rom, start, &map);
+    let labels = if start == DEFAULT_START || syntax == Syntax::C8asm {
+        let starts = lines.iter().map(|(addr, _, _)| *addr).collect();
+        generate_labels(&map, &starts, symbols)
+    } else {
+        Symbols::new()
+    };
+    render(rom, start, lines, &labels, addresses, syntax)
+}
+
+fn render(
+    rom: &[u8],
+    start: usize,
+    lines: Vec<Line>,
+    labels: &Symbols,
+    addresses: bool,
+    syntax: Syntax,
+) -> Vec<String> {
+    let width = labels.values().map(|l| l.len() + 1).max().unwrap_or(0);
+    let octo_labels;
+    let labels = match syntax {
+        Syntax::C8asm => labels,
+        Syntax::Octo => {
+            octo_labels = octo_names(labels, start);
+            &octo_labels
+        }
+    };
+    let mut out = Vec::new();
+    // Programs are assembled at 200 unless told otherwise.
+    if syntax == Syntax::C8asm && start != DEFAULT_START && !addresses {
+        out.push(format!(
+            "{:width$}org     #{:03x}",
+            "",
+            start,
+            width = width
+        ));
+    }
+    for (pc, inst, len) in lines {
+        let addr = if addresses { Some(pc) } else { None };
+        let bytes = &rom[pc - start..pc - start + len];
+        let label = labels.get(&pc);
+        match syntax {
+            Syntax::C8asm => {
+                let body = match inst {
+                    Some(inst) => disassemble_labelled(inst, labels),
+                    None => disassemble_data(None, bytes),
+                };
+                let label = label.map_or("", String::as_str);
+                out.push(with_address(
+                    addr,
+                    format!("{:width$}{}", label, body, width = width),
+                ));
+            }
+            Syntax::Octo => {
+                // Octo starts programs at main
+                if pc == start {
+                    out.push(with_address(addr, String::from(": main")));
+                }
+                if let Some(label) = label.filter(|l| !(pc == start && *l == "$main")) {
+                    out.push(with_address(addr, format!(": {}", &label[1..])));
+                }
+                let body = match inst {
+                    Some(inst) => disassemble_octo(inst, labels),
+                    None => octo_data(bytes),
+                };
+                out.push(with_address(addr, format!("\t{}", body)));
+            }
+        }
+    }
+    out
+}
+
+/// Rename labels that Octo would read as something else, or that would clash with the
+/// `main` placed at the start of the program, by adding `_` until the name is free.
+fn octo_names(labels: &Symbols, start: usize) -> Symbols {
+    let mut taken: BTreeSet<String> = labels.values().cloned().collect();
+    let reserved = |label: &str| {
+        let name = &label[1..];
+        OCTO_RESERVED.contains(&name)
+            || (name.len() == 2
+                && name.starts_with(['v', 'V'])
+                && name[1..].chars().all(|c| c.is_ascii_hexdigit()))
+    };
+    labels
+        .iter()
+        .map(|(addr, label)| {
+            let mut label = label.clone();
+            if reserved(&label) || (label == "$main" && *addr != start) {
+                while reserved(&label) || taken.contains(&label) {
+                    label.push('_');
+                }
+                taken.insert(label.clone());
+            }
+            (*addr, label)
+        })
+        .collect()
+}
+
+/// Split a traced ROM into lines of the disassembly, giving the address, instruction (or
+/// `None` for data) and length of each.
+fn layout(rom: &[u8], start: usize, map: &CodeMap) -> Vec<Line> {
+    let end = start + rom.len();
+    let mut lines = Vec::new();
+    let mut pc = start;
+    while pc < end {
+        if let Some(inst) = map.inst(pc) {
+            let code = (rom[pc - start] as u16) << 8 | rom[pc - start + 1] as u16;
+            if inst.opcode() == code {
+                lines.push((pc, Some(inst), 2));
+                pc += 2;
+                continue;
+            }
+        }
+        let mut next = pc + 1;
+        while next < end
+            && next - pc < DATA_LINE_LEN
+            && map.inst(next).is_none()
+            && !map.is_data_ref(next)
+        {
+            next += 1;
+        }
+        lines.push((pc, None, next - pc));
+        pc = next;
+    }
+    lines
+}
+
+/// Disassemble an instruction, naming its target address if it has a label.
+pub(crate) fn disassemble_labelled(inst: Chip8Inst, labels: &Symbols) -> String {
+    let target = match inst {
+        Chip8Inst::Jump(nnn)
+        | Chip8Inst::SubCall(nnn)
+        | Chip8Inst::JumpReg(nnn)
+        | Chip8Inst::SetIndex(nnn) => labels.get(&nnn),
+        _ => None,
+    };
+    match (inst, target) {
+        (Chip8Inst::Jump(_), Some(l)) => format!("jmp     {}", l),
+        (Chip8Inst::SubCall(_), Some(l)) => format!("call    {}", l),
+        (Chip8Inst::JumpReg(_), Some(l)) => format!("jmpv    {}", l),
+        (Chip8Inst::SetIndex(_), Some(l)) => format!("mov     I, {}", l),
+        _ => disassemble(None, inst),
+    }
+}
+
+/// Disassemble an instruction into Octo syntax, naming its target address if it has a
+/// label.
+pub fn disassemble_octo(inst: Chip8Inst, labels: &Symbols) -> String {
+    let addr = |nnn: usize| match labels.get(&nnn) {
+        Some(label) => String::from(&label[1..]),
+        None => format!("0x{:03x}", nnn),
+    };
+    match inst {
+        Chip8Inst::ClearScreen => "clear".to_string(),
+        Chip8Inst::Display(x, y, n) => format!("sprite v{:x} v{:x} 0x{:x}", x, y, n),
+        Chip8Inst::MachineInst(nnn) => octo_data(&(nnn as u16).to_be_bytes()),
+        Chip8Inst::Jump(nnn) => format!("jump {}", addr(nnn)),
+        Chip8Inst::JumpReg(nnn) => format!("jump0 {}", addr(nnn)),
+        Chip8Inst::SubCall(nnn) => match labels.get(&nnn) {
+            Some(label) => String::from(&label[1..]),
+            None => format!(":call 0x{:03x}", nnn),
+        },
+        Chip8Inst::SubReturn => "return".to_string(),
+        Chip8Inst::SkipEqConst(x, nn) => format!("if v{:x} != 0x{:02x} then", x, nn),
+        Chip8Inst::SkipNeqConst(x, nn) => format!("if v{:x} == 0x{:02x} then", x, nn),
+        Chip8Inst::SkipEqReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+        Chip8Inst::SkipNeqReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+        Chip8Inst::RegSet(x, nn) => format!("v{:x} := 0x{:02x}", x, nn),
+        Chip8Inst::RegAddNoCarry(x, nn) => format!("v{:x} += 0x{:02x}", x, nn),
+        Chip8Inst::Assign(x, y) => format!("v{:x} := v{:x}", x, y),
+        Chip8Inst::BinOr(x, y) => format!("v{:x} |= v{:x}", x, y),
+        Chip8Inst::BinAnd(x, y) => format!("v{:x} &= v{:x}", x, y),
+        Chip8Inst::BinXor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+        Chip8Inst::ArithAdd(x, y) => format!("v{:x} += v{:x}", x, y),
+        Chip8Inst::ArithSub(x, y) => format!("v{:x} -= v{:x}", x, y),
+        Chip8Inst::ArithSubReverse(x, y) => format!("v{:x} =- v{:x}", x, y),
+        Chip8Inst::ShiftLeft(x, y) => format!("v{:x} <<= v{:x}", x, y),
+        Chip8Inst::ShiftRight(x, y) => format!("v{:x} >>= v{:x}", x, y),
+        Chip8Inst::ReadDelay(x) => format!("v{:x} := delay", x),
+        Chip8Inst::SetDelay(x) => format!("delay := v{:x}", x),
+        Chip8Inst::SetSound(x) => format!("buzzer := v{:x}", x),
+        Chip8Inst::SetIndex(nnn) => format!("i := {}", addr(nnn)),
+        Chip8Inst::AddIndex(x) => format!("i += v{:x}", x),
+        Chip8Inst::Random(x, nn) => format!("v{:x} := random 0x{:02x}", x, nn),
+        Chip8Inst::SkipEqKey(x) => format!("if v{:x} -key then", x),
+        Chip8Inst::SkipNeqKey(x) => format!("if v{:x} key then", x),
+        Chip8Inst::GetKey(x) => format!("v{:x} := key", x),
+        Chip8Inst::LoadFont(x) => format!("i := hex v{:x}", x),
+        Chip8Inst::BCDConvert(x) => format!("bcd v{:x}", x),
+        Chip8Inst::StoreMem(x) => format!("save v{:x}", x),
+        Chip8Inst::LoadMem(x) => format!("load v{:x}", x),
+    }
+}
+
+/// Raw bytes as an Octo byte list.
+fn octo_data(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("0x{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn with_address(pc: Option<usize>, s: String) -> String {
     if let Some(pc_val) = pc {
         format!("{:#06x}    {}", pc_val, s)
     } else {
-        format!("{}", s)
+        s
+    }
+}
+
+#[cfg(test)]
+mod disassemble_tests {
+    use super::*;
+    use crate::c8asc::{assemble, octo::assemble_octo};
+    use crate::machine::labels::parse_symbols;
+    use rstest::*;
+
+    #[rstest]
+    #[case(0x00e0, "clr")]
+    #[case(0x0123, "mc      #123")]
+    #[case(0x8ab4, "add     Va, Vb")]
+    #[case(0xf31e, "add     I, V3")]
+    #[case(0xe59e, "skipkeq V5")]
+    #[case(0xd12f, "draw    V1, V2, #f")]
+    #[case(0x6c07, "mov     Vc, #07")]
+    fn test_disassemble(#[case] code: u16, #[case] expected: &str) {
+        let inst = Chip8Machine::decode(code).unwrap();
+        assert_eq!(expected, disassemble(None, inst));
+    }
+
+    #[rstest]
+    fn test_disassemble_rom() {
+        let rom = [0x00, 0xe0, 0xe1, 0x0a, 0x5a, 0xb1, 0x12];
+        let expected = vec![
+            "0x0300    clr",
+            "0x0302    data:   e10a",
+            "0x0304    data:   5ab1",
+            "0x0306    data:   12",
+        ];
+        assert_eq!(expected, disassemble_rom(&rom, 0x300, true, Syntax::C8asm));
+    }
+
+    #[rstest]
+    fn test_disassemble_traced() {
+        // mov I, #209; jmp #20d; padding; sprite at an odd address; clr at an odd address
+        let rom = [
+            0xa2, 0x09, 0x12, 0x0d, 0x00, 0x00, 0x00, 0x00, 0xff, 0x3c, 0x42, 0x81, 0xff, 0x00,
+            0xe0,
+        ];
+        let expected = vec![
+            "         mov     I, $spr_209",
+            "         jmp     $jmp_20d",
+            "         data:   00000000ff",
+            "$spr_209 data:   3c4281ff",
+            "$jmp_20d clr",
+        ];
+        assert_eq!(
+            expected,
+            disassemble_traced(&rom, 0x200, false, &Symbols::new(), Syntax::C8asm)
+        );
+    }
+
+    #[rstest]
+    fn test_disassemble_traced_symbols() {
+        // call #204; jmp #202; retn
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xee];
+        let symbols = [(0x204, String::from("$draw"))].into_iter().collect();
+        let expected = vec![
+            "0x0200              call    $draw",
+            "0x0202    $loop_202 jmp     $loop_202",
+            "0x0204    $draw     retn",
+        ];
+        assert_eq!(
+            expected,
+            disassemble_traced(&rom, 0x200, true, &symbols, Syntax::C8asm)
+        );
+    }
+
+    #[rstest]
+    fn test_labels_away_from_200() {
+        let rom = [0x16, 0x00];
+        let source = disassemble_traced(&rom, 0x600, false, &Symbols::new(), Syntax::C8asm);
+        assert_eq!(
+            vec!["          org     #600", "$loop_600 jmp     $loop_600"],
+            source
+        );
+        assert_eq!(rom.to_vec(), assemble(&source.join("\n")).unwrap());
+        assert_eq!(
+            vec!["\tjump 0x600"],
+            disassemble_traced(&rom, 0x600, false, &Symbols::new(), Syntax::Octo)[1..]
+        );
+    }
+
+    #[rstest]
+    fn test_round_trip_traced() {
+        let rom: Vec<u8> = (0..=0xffffu16).flat_map(|c| c.to_be_bytes()).collect();
+        for start in [0x200, 0x201] {
+            let source =
+                disassemble_traced(&rom[..0xdff], start, false, &Symbols::new(), Syntax::C8asm)
+                    .join("\n");
+            assert_eq!(rom[..0xdff].to_vec(), assemble(&source).unwrap());
+        }
+    }
+
+    #[rstest]
+    fn test_round_trip_every_opcode() {
+        let rom: Vec<u8> = (0..=0xffffu16).flat_map(|c| c.to_be_bytes()).collect();
+        // In pieces that fit in memory.
+        for chunk in rom.chunks(0xe00) {
+            let source = disassemble_rom(chunk, 0x200, false, Syntax::C8asm).join("\n");
+            assert_eq!(chunk, assemble(&source).unwrap());
+        }
+    }
+
+    #[rstest]
+    fn test_round_trip_odd_length() {
+        let rom = [0x6a, 0x02, 0xff];
+        let source = disassemble_rom(&rom, 0x200, false, Syntax::C8asm).join("\n");
+        assert_eq!(rom.to_vec(), assemble(&source).unwrap());
+    }
+
+    #[rstest]
+    fn test_disassemble_octo() {
+        // call #208; mov I, #20c; jmp #200; 0nnn; retn; skipeq V1, #02; sprite
+        let rom = [
+            0x22, 0x08, 0xa2, 0x0c, 0x12, 0x00, 0x01, 0x23, 0x00, 0xee, 0x31, 0x02, 0x3c, 0x42,
+        ];
+        let expected = vec![
+            ": main",
+            ": loop_200",
+            "\tsub_208",
+            "\ti := spr_20c",
+            "\tjump loop_200",
+            "\t0x01 0x23",
+            ": sub_208",
+            "\treturn",
+            "\t0x31 0x02",
+            ": spr_20c",
+            "\t0x3c 0x42",
+        ];
+        assert_eq!(
+            expected,
+            disassemble_traced(&rom, 0x200, false, &Symbols::new(), Syntax::Octo)
+        );
+    }
+
+    #[rstest]
+    fn test_octo_reserved_names() {
+        // call #206; jmp #204; jmp #200 (the loop); retn
+        let rom = [0x22, 0x06, 0x12, 0x04, 0x12, 0x00, 0x00, 0xee];
+        let symbols = parse_symbols("200 loop\n204 if\n206 vA\n202 main").unwrap();
+        let expected = vec![
+            ": main",
+            ": loop_",
+            "\tvA_",
+            ": main_",
+            "\tjump if_",
+            ": if_",
+            "\tjump loop_",
+            ": vA_",
+            "\treturn",
+        ];
+        let source = disassemble_traced(&rom, 0x200, false, &symbols, Syntax::Octo);
+        assert_eq!(expected, source);
+        assert_eq!(rom.to_vec(), assemble_octo(&source.join("\n")).unwrap());
+    }
+
+    #[rstest]
+    fn test_round_trip_octo() {
+        let rom: Vec<u8> = (0..=0xffffu16).flat_map(|c| c.to_be_bytes()).collect();
+        let source = disassemble_rom(&rom, 0x200, false, Syntax::Octo).join("\n");
+        assert_eq!(rom, assemble_octo(&source).unwrap());
+        let traced = disassemble_traced(&rom[..0xdff], 0x200, false, &Symbols::new(), Syntax::Octo);
+        assert_eq!(
+            rom[..0xdff].to_vec(),
+            assemble_octo(&traced.join("\n")).unwrap()
+        );
     }
 }
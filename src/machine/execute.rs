@@ -12,8 +12,8 @@
 // If not, see <https://www.gnu.org/licenses/>.
 
 use super::carry_borrow::*;
+use super::frame::{DirtyRect, Display};
 use super::insts::Chip8Inst;
-use super::Display;
 use super::{Chip8Machine, Chip8Mode, DISPLAY_HEIGHT, DISPLAY_WIDTH, FONT_BASE};
 use std::sync::atomic::Ordering;
 
@@ -24,10 +24,8 @@ impl Chip8Machine {
             Chip8Inst::MachineInst(_) => (),
             Chip8Inst::ClearScreen => {
                 let mut dsp = self.display.lock().unwrap();
-                dsp.fill(false);
-                for i in 0..DISPLAY_WIDTH * DISPLAY_HEIGHT {
-                    self.redraw[i].store(true, Ordering::Release);
-                }
+                dsp.pixels.fill(false);
+                dsp.mark(DirtyRect::FULL);
             }
             Chip8Inst::SubCall(n) => {
                 self.stack.push(self.prog_counter);
@@ -108,15 +106,20 @@ impl Chip8Machine {
                 self.registers[0xf] = 0;
 
                 let mut dsp = self.display.lock().unwrap();
+                dsp.mark(DirtyRect {
+                    x,
+                    y,
+                    width: (DISPLAY_WIDTH - x).min(8),
+                    height: (DISPLAY_HEIGHT - y).min(n as usize),
+                });
                 'rows: for i in 0..n {
                     let b = self.memory[self.index_reg + i as usize];
                     'cols: for j in 0..8 {
                         let px = b & (0x1 << (7 - j));
                         let idx = get_pixel_index(x, y);
-                        if set_display_pixel(&mut dsp, idx, px != 0) {
+                        if set_display_pixel(&mut dsp.pixels, idx, px != 0) {
                             self.registers[0xf] = 1;
                         }
-                        self.redraw[idx].store(true, Ordering::Release);
 
                         x += 1;
                         if x >= DISPLAY_WIDTH {
@@ -167,8 +170,10 @@ impl Chip8Machine {
                 let (lock, cvar) = &*self.current_key;
                 let mut key = lock.lock().unwrap();
                 key = cvar.wait(key).unwrap();
-                if key.is_some() {
-                    self.registers[x] = key.unwrap();
+                match *key {
+                    Some(k) => self.registers[x] = k,
+                    // Woken without a key press, so wait again next cycle
+                    None => self.prog_counter -= 2,
                 }
             }
             Chip8Inst::LoadFont(x) => {
@@ -230,6 +235,7 @@ fn set_display_pixel(display: &mut Display, idx: usize, px: bool) -> bool {
 #[cfg(test)]
 mod execute_tests {
     use super::*;
+    use crate::machine::frame::Frame;
     use rstest::*;
     use std::sync::atomic::AtomicBool;
     use std::sync::{Arc, Condvar, Mutex};
@@ -241,8 +247,7 @@ mod execute_tests {
             Chip8Mode::Modern,
             Arc::new(Mutex::new(0)),
             Arc::new(Mutex::new(0)),
-            Arc::new(Mutex::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT])),
-            Arc::new([NEW_BOOL; DISPLAY_WIDTH * DISPLAY_HEIGHT]),
+            Arc::new(Mutex::new(Frame::new())),
             Arc::new([NEW_BOOL; 16]),
             Arc::new((Mutex::new(None), Condvar::new())),
         )
@@ -257,6 +262,30 @@ mod execute_tests {
         assert_eq!(77, vm.registers[x]);
     }
 
+    #[rstest]
+    fn test_display_marks_dirty(mut vm: Chip8Machine) {
+        vm.display
+            .lock()
+            .unwrap()
+            .take(&mut [false; DISPLAY_WIDTH * DISPLAY_HEIGHT]);
+        vm.registers[0x0] = 60;
+        vm.registers[0x1] = 2;
+        vm.index_reg = FONT_BASE;
+
+        vm.execute(Chip8Inst::Display(0x0, 0x1, 5));
+
+        let mut pixels = [false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        let dirty = vm.display.lock().unwrap().take(&mut pixels);
+        let expected = DirtyRect {
+            x: 60,
+            y: 2,
+            width: 4,
+            height: 5,
+        };
+        assert_eq!(Some(expected), dirty);
+        assert!(pixels[2 * DISPLAY_WIDTH + 60]);
+    }
+
     #[rstest]
     fn test_bcd_convert(mut vm: Chip8Machine) {
         vm.registers[0x0] = 0xd4;
@@ -0,0 +1,164 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Type representing the display pixels.
+pub type Display = [bool; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+
+/// A rectangular region of the display, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    /// The whole display.
+    pub const FULL: DirtyRect = DirtyRect {
+        x: 0,
+        y: 0,
+        width: DISPLAY_WIDTH,
+        height: DISPLAY_HEIGHT,
+    };
+
+    /// The smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        DirtyRect {
+            x,
+            y,
+            width: right - x,
+            height: bottom - y,
+        }
+    }
+}
+
+/// The display pixels shared between the machine and the frontend, along with the region
+/// that has changed since the frontend last took a copy.
+pub struct Frame {
+    pub pixels: Display,
+    dirty: Option<DirtyRect>,
+}
+
+impl Frame {
+    /// A blank frame which needs drawing in full.
+    pub fn new() -> Frame {
+        Frame {
+            pixels: [false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            dirty: Some(DirtyRect::FULL),
+        }
+    }
+
+    /// Record that a region of the display has changed.
+    pub fn mark(&mut self, rect: DirtyRect) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        self.dirty = Some(match self.dirty {
+            Some(d) => d.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Copy the changed region into `dest`, returning which region was copied.
+    ///
+    /// This is the only access the frontend needs, so it holds the lock once per frame for
+    /// as little time as possible.
+    pub fn take(&mut self, dest: &mut Display) -> Option<DirtyRect> {
+        let dirty = self.dirty.take()?;
+        for row in dirty.y..dirty.y + dirty.height {
+            let start = row * DISPLAY_WIDTH + dirty.x;
+            dest[start..start + dirty.width]
+                .copy_from_slice(&self.pixels[start..start + dirty.width]);
+        }
+        Some(dirty)
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame::new()
+    }
+}
+
+#[cfg(test)]
+mod frame_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(
+        DirtyRect { x: 0, y: 0, width: 8, height: 5 },
+        DirtyRect { x: 4, y: 2, width: 8, height: 5 },
+        DirtyRect { x: 0, y: 0, width: 12, height: 7 }
+    )]
+    #[case(
+        DirtyRect { x: 10, y: 10, width: 2, height: 2 },
+        DirtyRect { x: 0, y: 20, width: 1, height: 1 },
+        DirtyRect { x: 0, y: 10, width: 12, height: 11 }
+    )]
+    fn test_union(#[case] a: DirtyRect, #[case] b: DirtyRect, #[case] expected: DirtyRect) {
+        assert_eq!(expected, a.union(&b));
+        assert_eq!(expected, b.union(&a));
+    }
+
+    #[rstest]
+    fn test_new_frame_is_dirty() {
+        let mut f = Frame::new();
+        let mut dest = [true; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        assert_eq!(Some(DirtyRect::FULL), f.take(&mut dest));
+        assert!(dest.iter().all(|px| !px));
+        assert_eq!(None, f.take(&mut dest));
+    }
+
+    #[rstest]
+    fn test_take_copies_dirty_region() {
+        let mut f = Frame::new();
+        let mut dest = [false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        f.take(&mut dest);
+
+        f.pixels[DISPLAY_WIDTH + 1] = true;
+        f.pixels[DISPLAY_WIDTH * 3 + 5] = true;
+        f.mark(DirtyRect {
+            x: 0,
+            y: 0,
+            width: 4,
+            height: 4,
+        });
+
+        let rect = f.take(&mut dest).unwrap();
+        assert_eq!(4, rect.width);
+        assert!(dest[DISPLAY_WIDTH + 1]);
+        assert!(!dest[DISPLAY_WIDTH * 3 + 5]);
+    }
+
+    #[rstest]
+    fn test_empty_mark_ignored() {
+        let mut f = Frame::new();
+        let mut dest = [false; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+        f.take(&mut dest);
+        f.mark(DirtyRect {
+            x: 3,
+            y: 3,
+            width: 0,
+            height: 5,
+        });
+        assert_eq!(None, f.take(&mut dest));
+    }
+}
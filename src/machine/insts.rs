@@ -11,7 +11,7 @@
 // You should have received a copy of the GNU General Public License along with rchip8.
 // If not, see <https://www.gnu.org/licenses/>.
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Chip8Inst {
     /// Set all display bits to false.
     ClearScreen,
@@ -93,3 +93,51 @@ pub enum Chip8Inst {
     /// Read several locations in memory into sequential registers.
     LoadMem(usize),
 }
+
+impl Chip8Inst {
+    /// The canonical opcode for this instruction.
+    ///
+    /// Decoding is not one-to-one (`Ex0A` is accepted as `GetKey` as well as `Fx0A`), so
+    /// this does not always give back the word the instruction was decoded from.
+    pub fn opcode(&self) -> u16 {
+        let xy = |x: usize, y: usize| (x as u16) << 8 | (y as u16) << 4;
+        let xnn = |x: usize, nn: u8| (x as u16) << 8 | nn as u16;
+        match *self {
+            Chip8Inst::ClearScreen => 0x00e0,
+            Chip8Inst::Display(x, y, n) => 0xd000 | xy(x, y) | n as u16,
+            Chip8Inst::MachineInst(nnn) => nnn as u16,
+            Chip8Inst::Jump(nnn) => 0x1000 | nnn as u16,
+            Chip8Inst::JumpReg(nnn) => 0xb000 | nnn as u16,
+            Chip8Inst::SubCall(nnn) => 0x2000 | nnn as u16,
+            Chip8Inst::SubReturn => 0x00ee,
+            Chip8Inst::SkipEqConst(x, nn) => 0x3000 | xnn(x, nn),
+            Chip8Inst::SkipNeqConst(x, nn) => 0x4000 | xnn(x, nn),
+            Chip8Inst::SkipEqReg(x, y) => 0x5000 | xy(x, y),
+            Chip8Inst::SkipNeqReg(x, y) => 0x9000 | xy(x, y),
+            Chip8Inst::RegSet(x, nn) => 0x6000 | xnn(x, nn),
+            Chip8Inst::RegAddNoCarry(x, nn) => 0x7000 | xnn(x, nn),
+            Chip8Inst::Assign(x, y) => 0x8000 | xy(x, y),
+            Chip8Inst::BinOr(x, y) => 0x8001 | xy(x, y),
+            Chip8Inst::BinAnd(x, y) => 0x8002 | xy(x, y),
+            Chip8Inst::BinXor(x, y) => 0x8003 | xy(x, y),
+            Chip8Inst::ArithAdd(x, y) => 0x8004 | xy(x, y),
+            Chip8Inst::ArithSub(x, y) => 0x8005 | xy(x, y),
+            Chip8Inst::ShiftRight(x, y) => 0x8006 | xy(x, y),
+            Chip8Inst::ArithSubReverse(x, y) => 0x8007 | xy(x, y),
+            Chip8Inst::ShiftLeft(x, y) => 0x800e | xy(x, y),
+            Chip8Inst::ReadDelay(x) => 0xf007 | xnn(x, 0),
+            Chip8Inst::SetDelay(x) => 0xf015 | xnn(x, 0),
+            Chip8Inst::SetSound(x) => 0xf018 | xnn(x, 0),
+            Chip8Inst::SetIndex(nnn) => 0xa000 | nnn as u16,
+            Chip8Inst::AddIndex(x) => 0xf01e | xnn(x, 0),
+            Chip8Inst::Random(x, nn) => 0xc000 | xnn(x, nn),
+            Chip8Inst::SkipEqKey(x) => 0xe09e | xnn(x, 0),
+            Chip8Inst::SkipNeqKey(x) => 0xe0a1 | xnn(x, 0),
+            Chip8Inst::GetKey(x) => 0xf00a | xnn(x, 0),
+            Chip8Inst::LoadFont(x) => 0xf029 | xnn(x, 0),
+            Chip8Inst::BCDConvert(x) => 0xf033 | xnn(x, 0),
+            Chip8Inst::StoreMem(x) => 0xf055 | xnn(x, 0),
+            Chip8Inst::LoadMem(x) => 0xf065 | xnn(x, 0),
+        }
+    }
+}
@@ -0,0 +1,184 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::{insts::Chip8Inst, trace::CodeMap};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Names given to addresses in a disassembly.
+pub type Symbols = BTreeMap<usize, String>;
+
+/// Generate labels for the targets of jumps, calls and `mov I` in traced code.
+///
+/// Calls give `$sub_nnn`, backward jumps `$loop_nnn`, forward jumps `$jmp_nnn`, `jmpv`
+/// tables `$tbl_nnn` and data loaded into I `$spr_nnn`. Only addresses in `lines`, where
+/// a line of the disassembly starts, are labelled. Names from `symbols` replace the
+/// generated ones and may label any line; a generated name that one of them has taken is
+/// given a `_` on the end.
+pub fn generate_labels(map: &CodeMap, lines: &BTreeSet<usize>, symbols: &Symbols) -> Symbols {
+    let mut ranked: BTreeMap<usize, (u8, String)> = BTreeMap::new();
+    let mut add = |addr: usize, rank: u8, prefix: &str| {
+        if lines.contains(&addr) {
+            let entry = ranked.entry(addr).or_insert((u8::MAX, String::new()));
+            if rank < entry.0 {
+                *entry = (rank, format!("${}_{:03x}", prefix, addr));
+            }
+        }
+    };
+    for (addr, inst) in map.insts() {
+        match inst {
+            Chip8Inst::SubCall(nnn) => add(nnn, 0, "sub"),
+            Chip8Inst::Jump(nnn) if nnn <= addr => add(nnn, 1, "loop"),
+            Chip8Inst::Jump(nnn) => add(nnn, 2, "jmp"),
+            Chip8Inst::JumpReg(nnn) => add(nnn, 3, "tbl"),
+            Chip8Inst::SetIndex(nnn) => add(nnn, 4, "spr"),
+            _ => (),
+        }
+    }
+
+    let mut labels: Symbols = ranked.into_iter().map(|(a, (_, n))| (a, n)).collect();
+    let named: Symbols = symbols
+        .iter()
+        .filter(|(addr, _)| lines.contains(addr))
+        .map(|(addr, name)| (*addr, name.clone()))
+        .collect();
+    // A generated name taken by a symbol elsewhere has `_` added until it's free.
+    let taken: BTreeSet<&String> = named.values().collect();
+    for (addr, name) in labels.iter_mut() {
+        if !named.contains_key(addr) {
+            while taken.contains(name) {
+                name.push('_');
+            }
+        }
+    }
+    labels.extend(named);
+    labels
+}
+
+/// Parse a symbol file naming addresses for the disassembler.
+///
+/// Each line holds a hex address and a label name, e.g. `2a4 main_loop`. The address may
+/// be written with `#` or `0x` and the name with or without its `$`. Blank lines and
+/// anything after a `;` are ignored.
+pub fn parse_symbols(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Symbols::new();
+    let mut names = BTreeSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |msg: String| format!("line {}: {}", i + 1, msg);
+        let mut fields = line.split_whitespace();
+        let (addr, name) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(addr), Some(name), None) => (addr, name),
+            _ => return Err(err(String::from("expected an address and a name"))),
+        };
+
+        let digits = addr
+            .strip_prefix('#')
+            .or_else(|| addr.strip_prefix("0x"))
+            .unwrap_or(addr);
+        let addr = usize::from_str_radix(digits, 16)
+            .ok()
+            .filter(|a| *a <= 0xfff)
+            .ok_or_else(|| err(format!("bad address: {}", addr)))?;
+
+        let name = format!("${}", name.strip_prefix('$').unwrap_or(name));
+        let mut chars = name[1..].chars();
+        let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(err(format!("bad label name: {}", name)));
+        }
+        if !names.insert(name.clone()) {
+            return Err(err(format!("duplicate label: {}", name)));
+        }
+        if symbols.insert(addr, name).is_some() {
+            return Err(err(format!("address {:03x} is already named", addr)));
+        }
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod labels_tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    fn test_generate_labels() {
+        // call #20a; mov I, #20e; jmp #20c; padding; retn; jmp #200; data
+        let rom = [
+            0x22, 0x0a, 0xa2, 0x0e, 0x12, 0x0c, 0xff, 0xff, 0xff, 0xff, 0x00, 0xee, 0x12, 0x00,
+            0xff, 0xff,
+        ];
+        let map = CodeMap::trace(&rom, 0x200);
+        let lines = (0x200..0x210).step_by(2).collect();
+        let labels = generate_labels(&map, &lines, &Symbols::new());
+        let expected: Symbols = [
+            (0x200, "$loop_200"),
+            (0x20a, "$sub_20a"),
+            (0x20c, "$jmp_20c"),
+            (0x20e, "$spr_20e"),
+        ]
+        .into_iter()
+        .map(|(a, n)| (a, String::from(n)))
+        .collect();
+        assert_eq!(expected, labels);
+    }
+
+    #[rstest]
+    fn test_symbols_override() {
+        let rom = [0x22, 0x04, 0x12, 0x00, 0x00, 0xee];
+        let map = CodeMap::trace(&rom, 0x200);
+        let lines = [0x200, 0x202, 0x204].into_iter().collect();
+        let symbols = parse_symbols("204 draw_player\n202 $main\n300 missing").unwrap();
+        let labels = generate_labels(&map, &lines, &symbols);
+        assert_eq!(Some("$draw_player"), labels.get(&0x204).map(String::as_str));
+        assert_eq!(Some("$main"), labels.get(&0x202).map(String::as_str));
+        assert_eq!(Some("$loop_200"), labels.get(&0x200).map(String::as_str));
+        assert_eq!(None, labels.get(&0x300));
+    }
+
+    #[rstest]
+    fn test_symbols_take_generated_name() {
+        // call #204; jmp #200; retn
+        let rom = [0x22, 0x04, 0x12, 0x00, 0x00, 0xee];
+        let map = CodeMap::trace(&rom, 0x200);
+        let lines = [0x200, 0x202, 0x204].into_iter().collect();
+        let symbols = parse_symbols("202 sub_204\n").unwrap();
+        let labels = generate_labels(&map, &lines, &symbols);
+        assert_eq!(Some("$sub_204"), labels.get(&0x202).map(String::as_str));
+        assert_eq!(Some("$sub_204_"), labels.get(&0x204).map(String::as_str));
+    }
+
+    #[rstest]
+    fn test_parse_symbols() {
+        let text = "; player routines\n#2a4 loop   ; main loop\n\n0x31c $draw\n";
+        let symbols = parse_symbols(text).unwrap();
+        assert_eq!(2, symbols.len());
+        assert_eq!("$loop", symbols[&0x2a4]);
+        assert_eq!("$draw", symbols[&0x31c]);
+    }
+
+    #[rstest]
+    #[case("2a4", "line 1: expected an address and a name")]
+    #[case("zz loop", "line 1: bad address: zz")]
+    #[case("1000 loop", "line 1: bad address: 1000")]
+    #[case("2a4 1loop", "line 1: bad label name: $1loop")]
+    #[case("2a4 a\n2a6 a", "line 2: duplicate label: $a")]
+    #[case("2a4 a\n2a4 b", "line 2: address 2a4 is already named")]
+    fn test_parse_symbols_errors(#[case] text: &str, #[case] expected: &str) {
+        assert_eq!(Err(String::from(expected)), parse_symbols(text));
+    }
+}
@@ -11,8 +11,11 @@
 // You should have received a copy of the GNU General Public License along with rchip8.
 // If not, see <https://www.gnu.org/licenses/>.
 
-use std::fs::File;
-use std::io::Read;
+use frame::{DirtyRect, Frame};
+use insts::Chip8Inst;
+use rom::{read_rom_file, RomError};
+use std::collections::BTreeSet;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::sync::{atomic::AtomicBool, Arc, Condvar, Mutex};
 use std::thread;
 use std::time::Duration;
@@ -27,6 +30,12 @@ pub const DISPLAY_HEIGHT: usize = 32;
 
 pub const FONT_BASE: usize = 0x050;
 
+/// Address programs are loaded at unless told otherwise.
+pub const DEFAULT_START: usize = 0x200;
+
+/// Total memory available to the machine.
+pub const MEMORY_SIZE: usize = 4096;
+
 pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -52,20 +61,48 @@ pub enum Chip8Mode {
     Modern,
 }
 
-/// Type representing the display pixels.
-type Display = [bool; DISPLAY_WIDTH * DISPLAY_HEIGHT];
-type RedrawArr = [AtomicBool; DISPLAY_WIDTH * DISPLAY_HEIGHT];
+/// Commands that control a machine running on another thread.
+#[derive(Debug, PartialEq)]
+pub enum Chip8Command {
+    /// Stop executing instructions until resumed.
+    Pause,
+    /// Continue executing instructions after a pause.
+    Resume,
+    /// Execute the given number of instructions and then pause.
+    Step(usize),
+    /// Run at the given multiple of the normal clock speed.
+    SetSpeed(f64),
+    /// Return to the power-on state and restart the current ROM.
+    Reset,
+    /// Replace the current ROM and restart.
+    LoadRom(Vec<u8>),
+}
+
+/// Things a machine running on another thread reports back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Event {
+    /// Paused by a command or at the end of a step, before the instruction at an address.
+    Paused(usize),
+    /// Paused at a breakpoint, before the instruction at an address.
+    Breakpoint(usize),
+    /// Paused at an address holding an opcode that isn't an instruction.
+    InvalidInstruction(usize, u16),
+    /// Executed the instruction at an address, reported only while tracing.
+    Executed(usize, Chip8Inst),
+}
 
 pub struct Chip8Machine {
     /// Flags whether to run in original or modern mode.
     mode: Chip8Mode,
 
     /// Total memory available to the machine.
-    memory: [u8; 4096],
+    memory: [u8; MEMORY_SIZE],
     /// Address stack for subroutines.
     stack: Vec<usize>,
     /// Address of next instruction to run.
     prog_counter: usize,
+    /// Address the ROM is loaded at and execution starts from.
+    start_addr: usize,
 
     /// 8-bit registers.
     registers: [u8; 16],
@@ -78,12 +115,22 @@ pub struct Chip8Machine {
     sound_timer: Arc<Mutex<u8>>,
 
     /// The current state of the display pixels.
-    display: Arc<Mutex<Display>>,
-    redraw: Arc<RedrawArr>,
+    display: Arc<Mutex<Frame>>,
     /// Key state
     key_state: Arc<[AtomicBool; 16]>,
     /// The current key being pressed along with its condition variable.
     current_key: Arc<(Mutex<Option<u8>>, Condvar)>,
+
+    /// The ROM that was last loaded, kept so the machine can be reset.
+    rom: Vec<u8>,
+
+    /// Addresses to pause at before executing the instruction there.
+    breakpoints: BTreeSet<usize>,
+    /// Whether to report every instruction executed.
+    tracing: bool,
+    /// Where the machine last paused, so that carrying on from there doesn't stop at a
+    /// breakpoint straight away.
+    stopped_at: Option<usize>,
 }
 
 impl Chip8Machine {
@@ -91,16 +138,16 @@ impl Chip8Machine {
         mode: Chip8Mode,
         delay_timer: Arc<Mutex<u8>>,
         sound_timer: Arc<Mutex<u8>>,
-        display: Arc<Mutex<Display>>,
-        redraw: Arc<RedrawArr>,
+        display: Arc<Mutex<Frame>>,
         key_state: Arc<[AtomicBool; 16]>,
         current_key: Arc<(Mutex<Option<u8>>, Condvar)>,
     ) -> Chip8Machine {
         let mut vm = Chip8Machine {
             mode,
-            memory: [0; 4096],
+            memory: [0; MEMORY_SIZE],
             stack: Vec::new(),
-            prog_counter: 0x200,
+            prog_counter: DEFAULT_START,
+            start_addr: DEFAULT_START,
             registers: [0; 16],
             index_reg: 0,
             delay_timer,
@@ -108,27 +155,166 @@ impl Chip8Machine {
             display,
             key_state,
             current_key,
-            redraw,
+            rom: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            tracing: false,
+            stopped_at: None,
         };
 
         vm.memory[FONT_BASE..FONT_BASE + 80].copy_from_slice(&FONT[..]);
         vm
     }
 
+    /// Change the address ROMs are loaded at, for example 0x600 for ETI-660 programs.
+    ///
+    /// This resets the machine, so should be done before loading a ROM. The address must
+    /// be after the font and leave room for at least one instruction before the end of
+    /// memory.
+    pub fn set_start_address(&mut self, addr: usize) -> Result<(), RomError> {
+        if addr < FONT_BASE + FONT.len() || addr > MEMORY_SIZE - 2 {
+            return Err(RomError::BadStartAddress(addr));
+        }
+        self.start_addr = addr;
+        self.rom.clear();
+        self.reset();
+        Ok(())
+    }
+
     /// Load the contents of the specified file into the machine's memory.
-    pub fn load_rom(&mut self, filename: &str) -> std::io::Result<()> {
-        let mut f = File::open(filename)?;
-        f.read(&mut self.memory[self.prog_counter..])?;
+    ///
+    /// See `read_rom_file` for the sources understood besides plain files.
+    pub fn load_rom(&mut self, filename: &str) -> Result<(), RomError> {
+        let rom = read_rom_file(filename)?;
+        self.load_rom_bytes(&rom)
+    }
+
+    /// Load a ROM into the machine's memory at the start address and reset the machine.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        let max = MEMORY_SIZE - self.start_addr;
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        } else if rom.len() > max {
+            return Err(RomError::TooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.rom = rom.to_vec();
+        self.reset();
         Ok(())
     }
 
+    /// Return the machine to its power-on state with the current ROM loaded.
+    pub fn reset(&mut self) {
+        self.memory = [0; MEMORY_SIZE];
+        self.memory[FONT_BASE..FONT_BASE + 80].copy_from_slice(&FONT[..]);
+        self.memory[self.start_addr..self.start_addr + self.rom.len()].copy_from_slice(&self.rom);
+        self.stack.clear();
+        self.prog_counter = self.start_addr;
+        self.registers = [0; 16];
+        self.index_reg = 0;
+        self.stopped_at = None;
+
+        *self.delay_timer.lock().unwrap() = 0;
+        *self.sound_timer.lock().unwrap() = 0;
+        let mut dsp = self.display.lock().unwrap();
+        dsp.pixels.fill(false);
+        dsp.mark(DirtyRect::FULL);
+    }
+
+    /// Pause `run_program` before executing the instruction at any of these addresses.
+    pub fn set_breakpoints(&mut self, addrs: BTreeSet<usize>) {
+        self.breakpoints = addrs;
+    }
+
+    /// Report every instruction executed by `run_program`.
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.tracing = tracing;
+    }
+
     /// Start the VM running its currently loaded program.
-    pub fn run_program(&mut self, frequency: Duration) {
+    ///
+    /// The machine runs until `commands` is disconnected, finishing any steps it was asked
+    /// to take first, and reports where it pauses on `events`. A machine blocked waiting
+    /// for a key press is woken by notifying the key condition variable, so senders should
+    /// do that after each command to have it handled promptly.
+    pub fn run_program(
+        &mut self,
+        frequency: Duration,
+        commands: Receiver<Chip8Command>,
+        events: Sender<Chip8Event>,
+    ) {
+        let mut paused = false;
+        let mut steps = 0;
+        let mut speed = 1.0;
+        // Reports are dropped if nobody is listening.
+        let report = |event| {
+            let _ = events.send(event);
+        };
         loop {
+            loop {
+                let cmd = if paused && steps == 0 {
+                    commands.recv().map_err(|_| TryRecvError::Disconnected)
+                } else {
+                    commands.try_recv()
+                };
+                match cmd {
+                    Ok(Chip8Command::Pause) => {
+                        paused = true;
+                        self.stopped_at = Some(self.prog_counter);
+                        report(Chip8Event::Paused(self.prog_counter));
+                    }
+                    Ok(Chip8Command::Resume) => {
+                        paused = false;
+                        steps = 0;
+                    }
+                    Ok(Chip8Command::Step(n)) => {
+                        paused = true;
+                        steps = n;
+                    }
+                    Ok(Chip8Command::SetSpeed(s)) => speed = s,
+                    Ok(Chip8Command::Reset) => self.reset(),
+                    Ok(Chip8Command::LoadRom(rom)) => {
+                        if let Err(e) = self.load_rom_bytes(&rom) {
+                            log::warn!("Couldn't load ROM: {}", e);
+                        }
+                    }
+                    Err(TryRecvError::Disconnected) if steps == 0 => return,
+                    Err(_) => break,
+                }
+            }
+
+            let addr = self.prog_counter;
+            if self.breakpoints.contains(&addr) && self.stopped_at != Some(addr) {
+                self.stopped_at = Some(addr);
+                paused = true;
+                steps = 0;
+                report(Chip8Event::Breakpoint(addr));
+                continue;
+            }
+            self.stopped_at = None;
+
             let opcode = self.fetch();
-            let inst = self.decode_run(opcode).unwrap();
+            let inst = match Chip8Machine::decode(opcode) {
+                Ok(inst) => inst,
+                Err(()) => {
+                    self.prog_counter = addr;
+                    paused = true;
+                    steps = 0;
+                    report(Chip8Event::InvalidInstruction(addr, opcode));
+                    continue;
+                }
+            };
             self.execute(inst);
-            thread::sleep(frequency);
+            if self.tracing {
+                report(Chip8Event::Executed(addr, inst));
+            }
+            if steps == 1 {
+                self.stopped_at = Some(self.prog_counter);
+                report(Chip8Event::Paused(self.prog_counter));
+            }
+            steps = steps.saturating_sub(1);
+            thread::sleep(frequency.div_f64(speed));
         }
     }
 
@@ -142,10 +328,16 @@ impl Chip8Machine {
 }
 
 pub mod carry_borrow;
+pub mod cfg;
+pub mod debug_info;
 pub mod decode;
 pub mod disassemble;
 pub mod execute;
+pub mod frame;
 pub mod insts;
+pub mod labels;
+pub mod rom;
+pub mod trace;
 
 #[cfg(test)]
 mod vm_tests {
@@ -159,8 +351,7 @@ mod vm_tests {
             Chip8Mode::Modern,
             Arc::new(Mutex::new(0)),
             Arc::new(Mutex::new(0)),
-            Arc::new(Mutex::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT])),
-            Arc::new([NEW_BOOL; DISPLAY_WIDTH * DISPLAY_HEIGHT]),
+            Arc::new(Mutex::new(Frame::new())),
             Arc::new([NEW_BOOL; 16]),
             Arc::new((Mutex::new(None), Condvar::new())),
         )
@@ -213,6 +404,162 @@ mod vm_tests {
         assert_eq!(expected, code);
     }
 
+    #[rstest]
+    fn test_load_rom_bytes_start_address(mut vm: Chip8Machine) {
+        vm.set_start_address(0x600).unwrap();
+        vm.load_rom_bytes(&[0xab, 0xcd]).unwrap();
+
+        assert_eq!(0x600, vm.prog_counter);
+        assert_eq!(0xab, vm.memory[0x600]);
+        assert_eq!(0xcd, vm.memory[0x601]);
+        assert_eq!(0x00, vm.memory[0x200]);
+    }
+
+    #[rstest]
+    fn test_load_rom_bytes_full(mut vm: Chip8Machine) {
+        let rom = [0x55; MEMORY_SIZE - DEFAULT_START];
+        vm.load_rom_bytes(&rom).unwrap();
+        assert_eq!(0x55, vm.memory[MEMORY_SIZE - 1]);
+    }
+
+    #[rstest]
+    fn test_load_rom_bytes_too_large(mut vm: Chip8Machine) {
+        let rom = [0x55; MEMORY_SIZE - DEFAULT_START + 1];
+        assert!(matches!(
+            vm.load_rom_bytes(&rom),
+            Err(RomError::TooLarge {
+                size: 3585,
+                max: 3584
+            })
+        ));
+    }
+
+    #[rstest]
+    fn test_load_rom_bytes_empty(mut vm: Chip8Machine) {
+        assert!(matches!(vm.load_rom_bytes(&[]), Err(RomError::Empty)));
+    }
+
+    #[rstest]
+    fn test_bad_start_address(mut vm: Chip8Machine) {
+        assert!(vm.set_start_address(0x1000).is_err());
+        assert!(matches!(
+            vm.set_start_address(0xfff),
+            Err(RomError::BadStartAddress(0xfff))
+        ));
+        assert!(matches!(
+            vm.set_start_address(0x10),
+            Err(RomError::BadStartAddress(0x10))
+        ));
+        assert!(vm.set_start_address(FONT_BASE + 79).is_err());
+        assert!(vm.set_start_address(FONT_BASE + 80).is_ok());
+    }
+
+    #[rstest]
+    fn test_last_start_address(mut vm: Chip8Machine) {
+        vm.set_start_address(0xffe).unwrap();
+        vm.load_rom_bytes(&[0x00]).unwrap();
+        assert_eq!(0x0000, vm.fetch());
+    }
+
+    #[rstest]
+    fn test_reset(#[from(vm_with_rom)] mut vm: Chip8Machine) {
+        vm.prog_counter = 0x300;
+        vm.registers[3] = 7;
+        vm.memory[0x201] = 0xff;
+        vm.stack.push(0x204);
+        vm.reset();
+
+        assert_eq!(0x200, vm.prog_counter);
+        assert_eq!(0, vm.registers[3]);
+        assert_eq!(0x34, vm.memory[0x201]);
+        assert!(vm.stack.is_empty());
+    }
+
+    #[rstest]
+    fn test_step_command(#[from(vm_with_rom)] mut vm: Chip8Machine) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        tx.send(Chip8Command::Pause).unwrap();
+        tx.send(Chip8Command::Step(1)).unwrap();
+        drop(tx);
+
+        vm.run_program(Duration::from_nanos(0), rx, std::sync::mpsc::channel().0);
+        assert_eq!(0x234, vm.prog_counter);
+    }
+
+    #[rstest]
+    fn test_load_rom_command(mut vm: Chip8Machine) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        tx.send(Chip8Command::LoadRom(vec![0x13, 0x00])).unwrap();
+        tx.send(Chip8Command::Step(1)).unwrap();
+        drop(tx);
+
+        vm.run_program(Duration::from_nanos(0), rx, std::sync::mpsc::channel().0);
+        assert_eq!(0x300, vm.prog_counter);
+        assert_eq!(0x13, vm.memory[0x200]);
+    }
+
+    /// Run the machine through `commands` and collect what it reports.
+    fn run(vm: &mut Chip8Machine, commands: Vec<Chip8Command>) -> Vec<Chip8Event> {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (event_tx, event_rx) = std::sync::mpsc::channel();
+        for cmd in commands {
+            tx.send(cmd).unwrap();
+        }
+        drop(tx);
+        vm.run_program(Duration::from_nanos(0), rx, event_tx);
+        event_rx.try_iter().collect()
+    }
+
+    #[rstest]
+    fn test_step_reports_pause(mut vm: Chip8Machine) {
+        vm.load_rom_bytes(&[0x60, 0x01, 0x60, 0x02]).unwrap();
+        let events = run(&mut vm, vec![Chip8Command::Pause, Chip8Command::Step(2)]);
+        assert_eq!(
+            vec![Chip8Event::Paused(0x200), Chip8Event::Paused(0x204)],
+            events
+        );
+    }
+
+    #[rstest]
+    fn test_breakpoint(mut vm: Chip8Machine) {
+        // mov V0, 1; add V0, 1; jmp 202
+        vm.load_rom_bytes(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x02])
+            .unwrap();
+        vm.set_breakpoints(BTreeSet::from([0x202]));
+        let events = run(&mut vm, vec![Chip8Command::Step(1)]);
+        assert_eq!(vec![Chip8Event::Paused(0x202)], events);
+
+        // Carrying on runs past the breakpoint, then stops at it again.
+        let events = run(&mut vm, vec![Chip8Command::Step(10)]);
+        assert_eq!(vec![Chip8Event::Breakpoint(0x202)], events);
+        assert_eq!(2, vm.registers[0]);
+        let events = run(&mut vm, vec![Chip8Command::Step(10)]);
+        assert_eq!(vec![Chip8Event::Breakpoint(0x202)], events);
+        assert_eq!(3, vm.registers[0]);
+    }
+
+    #[rstest]
+    fn test_invalid_instruction(mut vm: Chip8Machine) {
+        vm.load_rom_bytes(&[0x60, 0x01, 0xff, 0xff]).unwrap();
+        let events = run(&mut vm, vec![Chip8Command::Step(5)]);
+        assert_eq!(vec![Chip8Event::InvalidInstruction(0x202, 0xffff)], events);
+        assert_eq!(0x202, vm.prog_counter);
+    }
+
+    #[rstest]
+    fn test_tracing(mut vm: Chip8Machine) {
+        vm.load_rom_bytes(&[0x60, 0x01, 0xff, 0xff]).unwrap();
+        vm.set_tracing(true);
+        let events = run(&mut vm, vec![Chip8Command::Step(5)]);
+        assert_eq!(
+            vec![
+                Chip8Event::Executed(0x200, Chip8Inst::RegSet(0, 1)),
+                Chip8Event::InvalidInstruction(0x202, 0xffff),
+            ],
+            events
+        );
+    }
+
     #[rstest]
     fn test_successive_fetch(#[from(vm_with_rom)] mut vm: Chip8Machine) {
         assert_eq!(0x1234, vm.fetch());
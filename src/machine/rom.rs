@@ -0,0 +1,185 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek};
+
+/// File extensions recognised as ROMs when searching inside an archive.
+const ROM_EXTENSIONS: [&str; 5] = [".ch8", ".c8", ".sc8", ".xo8", ".rom"];
+
+/// Reasons a ROM could not be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    /// The ROM could not be read.
+    Io(io::Error),
+    /// The ROM contained no data.
+    Empty,
+    /// The ROM does not fit in memory after the start address.
+    TooLarge { size: usize, max: usize },
+    /// The requested start address is inside the font or leaves no room for an instruction
+    /// in memory.
+    BadStartAddress(usize),
+    /// The ROM could not be found in an archive.
+    Archive(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(e) => write!(f, "{}", e),
+            RomError::Empty => write!(f, "ROM is empty"),
+            RomError::TooLarge { size, max } => write!(
+                f,
+                "ROM is {} bytes but only {} bytes are available from the start address",
+                size, max
+            ),
+            RomError::BadStartAddress(addr) => {
+                write!(
+                    f,
+                    "Start address {:#05x} is inside the font or leaves no room for an instruction",
+                    addr
+                )
+            }
+            RomError::Archive(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(e: io::Error) -> Self {
+        RomError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for RomError {
+    fn from(e: zip::result::ZipError) -> Self {
+        RomError::Archive(format!("Couldn't read archive: {}", e))
+    }
+}
+
+/// Read the contents of a ROM.
+///
+/// A filename of `-` reads from standard input, and a `.zip` file is searched for a single
+/// ROM to extract.
+pub fn read_rom_file(filename: &str) -> Result<Vec<u8>, RomError> {
+    let mut buf = Vec::new();
+    if filename == "-" {
+        io::stdin().lock().read_to_end(&mut buf)?;
+    } else if filename.to_ascii_lowercase().ends_with(".zip") {
+        buf = read_rom_archive(File::open(filename)?)?;
+    } else {
+        File::open(filename)?.read_to_end(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+/// Extract a ROM from a zip archive.
+///
+/// An archive holding a single file is taken to be that ROM. Otherwise exactly one file
+/// must have a recognised ROM extension.
+pub fn read_rom_archive<R: Read + Seek>(reader: R) -> Result<Vec<u8>, RomError> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(String::from)
+        .collect();
+
+    let candidates: Vec<&String> = if files.len() == 1 {
+        files.iter().collect()
+    } else {
+        files
+            .iter()
+            .filter(|name| {
+                let lower = name.to_ascii_lowercase();
+                ROM_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+            })
+            .collect()
+    };
+
+    match candidates[..] {
+        [name] => {
+            let mut buf = Vec::new();
+            archive.by_name(name)?.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+        [] => Err(RomError::Archive(String::from("No ROM found in archive"))),
+        _ => Err(RomError::Archive(format!(
+            "Archive contains several ROMs: {}",
+            candidates
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod rom_tests {
+    use super::*;
+    use rstest::*;
+    use std::io::{Cursor, Write};
+
+    fn make_zip(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut w = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            w.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            w.write_all(data).unwrap();
+        }
+        let mut c = w.finish().unwrap();
+        c.set_position(0);
+        c
+    }
+
+    #[rstest]
+    fn test_read_file() {
+        assert_eq!(8, read_rom_file("test/test.ch8").unwrap().len());
+    }
+
+    #[rstest]
+    fn test_read_missing_file() {
+        assert!(matches!(
+            read_rom_file("test/missing.ch8"),
+            Err(RomError::Io(_))
+        ));
+    }
+
+    #[rstest]
+    fn test_archive_single_file() {
+        let z = make_zip(&[("README", &[1, 2, 3])]);
+        assert_eq!(vec![1, 2, 3], read_rom_archive(z).unwrap());
+    }
+
+    #[rstest]
+    fn test_archive_picks_rom() {
+        let z = make_zip(&[("readme.txt", &[9]), ("games/pong.ch8", &[0x12, 0x00])]);
+        assert_eq!(vec![0x12, 0x00], read_rom_archive(z).unwrap());
+    }
+
+    #[rstest]
+    fn test_archive_no_rom() {
+        let z = make_zip(&[("readme.txt", &[9]), ("notes.txt", &[8])]);
+        assert!(matches!(read_rom_archive(z), Err(RomError::Archive(_))));
+    }
+
+    #[rstest]
+    fn test_archive_several_roms() {
+        let z = make_zip(&[("a.ch8", &[1]), ("b.ch8", &[2])]);
+        assert!(matches!(read_rom_archive(z), Err(RomError::Archive(_))));
+    }
+}
@@ -0,0 +1,143 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use super::{insts::Chip8Inst, Chip8Machine};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Which parts of a ROM are code, found by following control flow from the entry point.
+///
+/// Jumps, calls, skips and returns are followed; the targets of `jmpv` depend on V0 so are
+/// not. Instructions may start at odd addresses. Addresses loaded into I are recorded as
+/// data references.
+pub struct CodeMap {
+    start: usize,
+    insts: BTreeMap<usize, Chip8Inst>,
+    data_refs: BTreeSet<usize>,
+}
+
+impl CodeMap {
+    /// Trace a ROM loaded at `start`, beginning execution at `start`.
+    pub fn trace(rom: &[u8], start: usize) -> CodeMap {
+        let mut map = CodeMap {
+            start,
+            insts: BTreeMap::new(),
+            data_refs: BTreeSet::new(),
+        };
+        let end = start + rom.len();
+        let mut pending = vec![start];
+
+        while let Some(mut pc) = pending.pop() {
+            while pc >= start && pc + 1 < end && !map.insts.contains_key(&pc) {
+                let code = (rom[pc - start] as u16) << 8 | rom[pc - start + 1] as u16;
+                let inst = match Chip8Machine::decode(code) {
+                    Ok(inst) => inst,
+                    Err(_) => break,
+                };
+                map.insts.insert(pc, inst);
+                match inst {
+                    Chip8Inst::Jump(nnn) => {
+                        pending.push(nnn);
+                        break;
+                    }
+                    Chip8Inst::SubCall(nnn) => pending.push(nnn),
+                    Chip8Inst::SubReturn | Chip8Inst::JumpReg(_) => break,
+                    Chip8Inst::SkipEqConst(..)
+                    | Chip8Inst::SkipNeqConst(..)
+                    | Chip8Inst::SkipEqReg(..)
+                    | Chip8Inst::SkipNeqReg(..)
+                    | Chip8Inst::SkipEqKey(_)
+                    | Chip8Inst::SkipNeqKey(_) => pending.push(pc + 4),
+                    Chip8Inst::SetIndex(nnn) => {
+                        map.data_refs.insert(nnn);
+                    }
+                    _ => (),
+                }
+                pc += 2;
+            }
+        }
+        map
+    }
+
+    /// Address the ROM was loaded at.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// The instruction starting at an address, if it was reached.
+    pub fn inst(&self, addr: usize) -> Option<Chip8Inst> {
+        self.insts.get(&addr).copied()
+    }
+
+    /// Every reachable instruction in address order.
+    pub fn insts(&self) -> impl Iterator<Item = (usize, Chip8Inst)> + '_ {
+        self.insts.iter().map(|(addr, inst)| (*addr, *inst))
+    }
+
+    /// Whether an address is loaded into I somewhere in the reachable code.
+    pub fn is_data_ref(&self, addr: usize) -> bool {
+        self.data_refs.contains(&addr)
+    }
+}
+
+#[cfg(test)]
+mod trace_tests {
+    use super::*;
+    use rstest::*;
+
+    fn addrs(map: &CodeMap) -> Vec<usize> {
+        map.insts().map(|(addr, _)| addr).collect()
+    }
+
+    #[rstest]
+    fn test_stops_at_jump() {
+        // jmp #206; data; clr
+        let rom = [0x12, 0x06, 0xff, 0xff, 0xff, 0xff, 0x00, 0xe0];
+        assert_eq!(vec![0x200, 0x206], addrs(&CodeMap::trace(&rom, 0x200)));
+    }
+
+    #[rstest]
+    fn test_follows_calls_and_skips() {
+        // call #208; skipeq V0, #01; retn; retn; clr; retn
+        let rom = [
+            0x22, 0x08, 0x30, 0x01, 0x00, 0xee, 0x00, 0xee, 0x00, 0xe0, 0x00, 0xee,
+        ];
+        let map = CodeMap::trace(&rom, 0x200);
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20a], addrs(&map));
+    }
+
+    #[rstest]
+    fn test_odd_address_code() {
+        // jmp #203; pad; clr; retn
+        let rom = [0x12, 0x03, 0xaa, 0x00, 0xe0, 0x00, 0xee];
+        let map = CodeMap::trace(&rom, 0x200);
+        assert_eq!(vec![0x200, 0x203, 0x205], addrs(&map));
+        assert_eq!(Some(Chip8Inst::ClearScreen), map.inst(0x203));
+        assert_eq!(None, map.inst(0x202));
+    }
+
+    #[rstest]
+    fn test_data_refs() {
+        // mov I, #204; jmp #202; sprite data
+        let rom = [0xa2, 0x04, 0x12, 0x02, 0x3c, 0x42];
+        let map = CodeMap::trace(&rom, 0x200);
+        assert!(map.is_data_ref(0x204));
+        assert!(!map.is_data_ref(0x202));
+        assert_eq!(vec![0x200, 0x202], addrs(&map));
+    }
+
+    #[rstest]
+    fn test_ignores_targets_outside_rom() {
+        let rom = [0x23, 0x00, 0x10, 0x00];
+        assert_eq!(vec![0x200, 0x202], addrs(&CodeMap::trace(&rom, 0x200)));
+    }
+}
@@ -13,19 +13,39 @@
 
 extern crate lalrpop_util;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use rchip8::frontend::{
+    palette::{blend, Palette, Rgb},
+    screen::{Phosphor, Viewport},
+    watch::FileWatcher,
+};
 use rchip8::machine::{
-    disassemble::disassemble, Chip8Machine, Chip8Mode, DELAY_1MHZ, DELAY_60HZ, DISPLAY_HEIGHT,
-    DISPLAY_WIDTH,
+    cfg::ControlFlow,
+    debug_info::DebugInfo,
+    disassemble::{disassemble, disassemble_rom, disassemble_traced, Syntax},
+    frame::{DirtyRect, Display, Frame},
+    labels::{parse_symbols, Symbols},
+    rom::read_rom_file,
+    Chip8Command, Chip8Event, Chip8Machine, Chip8Mode, DELAY_1MHZ, DELAY_60HZ, DISPLAY_HEIGHT,
+    DISPLAY_WIDTH, MEMORY_SIZE,
 };
+use rchip8::romdb::{RomDatabase, RomMatch};
 // use rodio::{source::SineWave, OutputStream, Sink, Source};
 use sdl2::keyboard::Keycode;
-use sdl2::{event::Event, keyboard::Scancode, pixels::Color, rect::Rect};
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::Scancode,
+    pixels::{Color, PixelFormatEnum},
+    rect::{Point, Rect},
+};
 use simple_logger::SimpleLogger;
-use std::fs::File;
-use std::io::Read;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
+    mpsc::{self, Sender},
     Arc, Condvar, Mutex,
 };
 use std::thread;
@@ -33,18 +53,113 @@ use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about=None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Chip8Args {
-    /// Path to the ROM file to run
-    rom_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Path to the ROM file to run, a zip archive containing it, or - to read from stdin
+    #[arg(required = true)]
+    rom_file: Option<String>,
     /// Run in original mode
-    #[arg(long, short)]
+    #[arg(long, short, conflicts_with = "modern")]
     original: bool,
-    /// Output addresses when disassembling (starting at 0x200)
+    /// Run in modern mode
+    #[arg(long, short)]
+    modern: bool,
+    /// Output addresses when disassembling (starting at the start address)
     #[arg(short, long)]
     addresses: bool,
+    /// Hex address to load the ROM at and start executing from, e.g. 600 for ETI-660 ROMs
+    #[arg(long, value_parser = parse_address, default_value = "200", global = true)]
+    start: usize,
     /// Disassemble the ROM instead of executing it
     #[arg(long, short)]
     disassemble: bool,
+    /// Disassemble every word in order instead of following control flow from the start
+    #[arg(long, requires = "disassemble")]
+    linear: bool,
+    /// Disassemble into Octo syntax instead of c8asm
+    #[arg(long, requires = "disassemble")]
+    octo: bool,
+    /// File naming addresses for the disassembler, one "address name" pair per line
+    #[arg(long, requires = "disassemble", conflicts_with = "linear")]
+    symbols: Option<PathBuf>,
+    /// Debug info written by c8asc --debug-info, to show source locations and label names
+    #[arg(long, conflicts_with = "linear")]
+    debug_info: Option<PathBuf>,
+    /// Pause before the code at a label or FILE:LINE, using the debug info (may be repeated)
+    #[arg(long = "break", value_name = "LOCATION", requires = "debug_info")]
+    breakpoints: Vec<String>,
+    /// Log every instruction executed, with its source location if there is debug info
+    #[arg(long, conflicts_with = "disassemble")]
+    trace: bool,
+    /// Instructions to execute per frame (60 frames per second)
+    #[arg(long, short)]
+    tickrate: Option<u32>,
+    /// Size of each CHIP-8 pixel in window pixels
+    #[arg(long, short, default_value_t = 10)]
+    scale: u32,
+    /// Start in fullscreen mode (F11 toggles fullscreen while running)
+    #[arg(long, short)]
+    fullscreen: bool,
+    /// Palette name (mono, inverse, amber, green, lcd, octo) or 2 or 4 comma-separated hex colours
+    #[arg(long, short)]
+    palette: Option<Palette>,
+    /// Draw a grid between pixels
+    #[arg(long, short)]
+    grid: bool,
+    /// Fade pixels out over several frames to reduce flicker
+    #[arg(long)]
+    fade: bool,
+    /// Reload the ROM whenever the file changes on disk
+    #[arg(long, short)]
+    watch: bool,
+    /// Directory holding chip-8-database files to use instead of the built-in database
+    #[arg(long, global = true)]
+    database: Option<PathBuf>,
+    /// Don't look the ROM up in the database to choose settings
+    #[arg(long)]
+    no_database: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show what the ROM database knows about a ROM
+    Info {
+        /// Path to the ROM file, a zip archive containing it, or - to read from stdin
+        rom_file: String,
+    },
+    /// Print the ROM's control-flow graph in Graphviz DOT format
+    Cfg {
+        /// Path to the ROM file, a zip archive containing it, or - to read from stdin
+        rom_file: String,
+    },
+}
+
+/// Options controlling how the machine is run.
+struct RunOptions {
+    mode: Chip8Mode,
+    start: usize,
+    /// Time between instructions.
+    clock: Duration,
+    /// Instructions executed per frame, for frame advance.
+    insts_per_frame: usize,
+    watch: bool,
+    /// Host keys mapped to CHIP-8 keys in addition to the usual layout.
+    keymap: Vec<(Scancode, usize)>,
+    /// Where the ROM came from in its source, empty if not known.
+    debug: DebugInfo,
+    breakpoints: BTreeSet<usize>,
+    trace: bool,
+}
+
+/// Options controlling how the display is drawn.
+struct ScreenOptions {
+    scale: u32,
+    fullscreen: bool,
+    palette: Palette,
+    grid: bool,
+    fade: bool,
 }
 
 fn main() {
@@ -52,97 +167,336 @@ fn main() {
 
     let args = Chip8Args::parse();
 
+    match &args.command {
+        Some(Command::Info { rom_file }) => {
+            run_info(rom_file, args.database.as_deref());
+            return;
+        }
+        Some(Command::Cfg { rom_file }) => {
+            let rom = load_rom_or_exit(rom_file);
+            print!("{}", ControlFlow::build(&rom, args.start).to_dot());
+            return;
+        }
+        None => (),
+    }
+
+    let rom_file = args.rom_file.as_deref().unwrap();
+    let debug = match &args.debug_info {
+        Some(path) => load_debug_info_or_exit(path),
+        None => DebugInfo::default(),
+    };
     if args.disassemble {
-        run_disassemble(&args.rom_file, args.addresses);
-    } else {
-        let mode = if args.original {
-            Chip8Mode::Original
+        // Names from a symbol file take precedence over those from the debug info.
+        let mut symbols = debug.symbols();
+        if let Some(path) = &args.symbols {
+            symbols.extend(load_symbols_or_exit(path));
+        }
+        let syntax = if args.octo {
+            Syntax::Octo
         } else {
-            Chip8Mode::Modern
+            Syntax::C8asm
         };
+        run_disassemble(
+            rom_file,
+            args.start,
+            args.addresses,
+            args.linear,
+            &symbols,
+            syntax,
+        );
+        return;
+    }
 
-        start_vm(mode, &args.rom_file);
+    let rom = load_rom_or_exit(rom_file);
+    let db = if args.no_database {
+        None
+    } else {
+        Some(load_database_or_exit(args.database.as_deref()))
+    };
+    let found = db.as_ref().and_then(|db| db.lookup(&rom));
+    if let Some(m) = &found {
+        log::info!(
+            "Recognised {} ({})",
+            m.program.title,
+            m.platform.map_or("unknown platform", |p| p.name.as_str())
+        );
     }
-}
 
-fn run_disassemble(rom_file: &str, addresses: bool) {
-    if let Ok(mut f) = File::open(rom_file) {
-        let mut buf = [0u8; 2];
-        let mut pc = 0x200;
-        while let Ok(2) = f.read(&mut buf) {
-            let code = (buf[0] as u16) << 8 | (buf[1] as u16);
-            match Chip8Machine::decode(code) {
-                Ok(inst) => {
-                    if addresses {
-                        println!("{}", disassemble(Some(pc), inst));
-                    } else {
-                        println!("{}", disassemble(None, inst));
-                    }
-                }
-                Err(_) => {
-                    if addresses {
-                        println!("{:#06x} .data   {:02X} {:02X}", pc, buf[0], buf[1]);
-                    } else {
-                        println!(".data   {:02X} {:02X}", buf[0], buf[1]);
-                    }
+    let mode = if args.original {
+        Chip8Mode::Original
+    } else if args.modern {
+        Chip8Mode::Modern
+    } else {
+        found
+            .as_ref()
+            .and_then(|m| m.mode())
+            .unwrap_or(Chip8Mode::Modern)
+    };
+
+    let tickrate = args
+        .tickrate
+        .or_else(|| found.as_ref().and_then(|m| m.tickrate()))
+        .filter(|t| *t > 0);
+    let (clock, insts_per_frame) = match tickrate {
+        Some(t) => (Duration::from_nanos(DELAY_60HZ / t as u64), t as usize),
+        None => (Duration::from_nanos(DELAY_1MHZ), INSTS_PER_FRAME),
+    };
+
+    let palette = args.palette.unwrap_or_else(|| {
+        found
+            .as_ref()
+            .and_then(|m| m.palette())
+            .and_then(|p| match p.parse() {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    log::warn!("Ignoring colours from database: {}", e);
+                    None
                 }
+            })
+            .unwrap_or_default()
+    });
+
+    let run = RunOptions {
+        mode,
+        start: args.start,
+        clock,
+        insts_per_frame,
+        watch: args.watch,
+        keymap: found.as_ref().map_or(Vec::new(), |m| keymap(&m.rom.keys)),
+        breakpoints: args
+            .breakpoints
+            .iter()
+            .map(|location| {
+                debug.resolve(location).unwrap_or_else(|e| {
+                    eprintln!("Can't break at {}: {}", location, e);
+                    std::process::exit(1);
+                })
+            })
+            .collect(),
+        debug,
+        trace: args.trace,
+    };
+    let opts = ScreenOptions {
+        scale: args.scale.max(1),
+        fullscreen: args.fullscreen,
+        palette,
+        grid: args.grid,
+        fade: args.fade,
+    };
+    start_vm(rom_file, &rom, run, opts);
+}
+
+fn load_rom_or_exit(rom_file: &str) -> Vec<u8> {
+    match read_rom_file(rom_file) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("Couldn't load ROM: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn load_database_or_exit(dir: Option<&Path>) -> RomDatabase {
+    match dir {
+        None => RomDatabase::embedded(),
+        Some(dir) => match RomDatabase::load_dir(dir) {
+            Ok(db) => db,
+            Err(e) => {
+                eprintln!("Couldn't load ROM database: {}", e);
+                std::process::exit(1);
+            }
+        },
+    }
+}
+
+/// Print everything the database says about a ROM.
+fn run_info(rom_file: &str, database: Option<&Path>) {
+    let rom = load_rom_or_exit(rom_file);
+    let db = load_database_or_exit(database);
+    match db.lookup(&rom) {
+        Some(m) => print_info(&m),
+        None => {
+            println!("SHA-1:       {}", rchip8::romdb::rom_hash(&rom));
+            println!("Not found in the ROM database");
+        }
+    }
+}
+
+fn print_info(m: &RomMatch) {
+    println!("SHA-1:       {}", m.hash);
+    println!("Title:       {}", m.program.title);
+    if !m.program.authors.is_empty() {
+        println!("Authors:     {}", m.program.authors.join(", "));
+    }
+    if let Some(release) = &m.program.release {
+        println!("Release:     {}", release);
+    }
+    if let Some(desc) = &m.program.description {
+        println!("Description: {}", desc);
+    }
+    if let Some(file) = &m.rom.file {
+        println!("File:        {}", file);
+    }
+    match m.platform {
+        Some(p) => {
+            println!("Platform:    {} ({})", p.name, p.id);
+            let quirks = m.quirks();
+            let mode = match m.mode() {
+                Some(Chip8Mode::Original) => "original",
+                _ => "modern",
+            };
+            println!("Quirks:      {}", quirks.enabled().join(", "));
+            println!("Mode:        {}", mode);
+        }
+        None => println!("Platform:    {}", m.rom.platforms.join(", ")),
+    }
+    if let Some(t) = m.tickrate() {
+        println!("Tickrate:    {}", t);
+    }
+    if !m.rom.keys.is_empty() {
+        let mut keys: Vec<String> = m
+            .rom
+            .keys
+            .iter()
+            .map(|(name, key)| format!("{}={:X}", name, key))
+            .collect();
+        keys.sort();
+        println!("Keys:        {}", keys.join(" "));
+    }
+    if let Some(p) = m.palette() {
+        println!("Colours:     {}", p);
+    }
+}
+
+/// Parse a hexadecimal address, with or without a leading `0x`.
+fn parse_address(s: &str) -> Result<usize, String> {
+    let addr = usize::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("Invalid address '{}': {}", s, e))?;
+    if addr < MEMORY_SIZE {
+        Ok(addr)
+    } else {
+        Err(format!("Address {:#x} is outside memory", addr))
+    }
+}
+
+fn load_symbols_or_exit(path: &Path) -> Symbols {
+    match std::fs::read_to_string(path).map_err(|e| e.to_string()) {
+        Ok(text) => match parse_symbols(&text) {
+            Ok(symbols) => symbols,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                std::process::exit(1);
             }
-            pc += 2;
+        },
+        Err(e) => {
+            eprintln!("Couldn't load symbols: {}", e);
+            std::process::exit(1);
         }
     }
 }
 
-fn start_vm(mode: Chip8Mode, rom_file: &str) {
+fn load_debug_info_or_exit(path: &Path) -> DebugInfo {
+    match std::fs::read_to_string(path) {
+        Ok(text) => DebugInfo::parse(&text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            std::process::exit(1);
+        }),
+        Err(e) => {
+            eprintln!("Couldn't load debug info: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Describe an address by its source location and the text of the line, if known.
+fn source_location(debug: &DebugInfo, addr: usize) -> String {
+    match debug.source_line(addr) {
+        Some(line) => format!("{}: {}", debug.describe(addr), line.text),
+        None => debug.describe(addr),
+    }
+}
+
+fn run_disassemble(
+    rom_file: &str,
+    start: usize,
+    addresses: bool,
+    linear: bool,
+    symbols: &Symbols,
+    syntax: Syntax,
+) {
+    let rom = load_rom_or_exit(rom_file);
+    let lines = if linear {
+        disassemble_rom(&rom, start, addresses, syntax)
+    } else {
+        disassemble_traced(&rom, start, addresses, symbols, syntax)
+    };
+    for line in lines {
+        println!("{}", line);
+    }
+}
+
+fn start_vm(rom_file: &str, rom: &[u8], run: RunOptions, opts: ScreenOptions) {
     // Initialise and display window
     let sdl_context = sdl2::init().unwrap();
     let video_subsys = sdl_context.video().unwrap();
 
-    let window = video_subsys
-        .window(
-            "rCHIP-8",
-            (DISPLAY_WIDTH * 10) as u32,
-            (DISPLAY_HEIGHT * 10) as u32,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_draw_color(Color::BLACK);
-    canvas.clear();
-    canvas.present();
+    let mut window_builder = video_subsys.window(
+        "rCHIP-8",
+        DISPLAY_WIDTH as u32 * opts.scale,
+        DISPLAY_HEIGHT as u32 * opts.scale,
+    );
+    window_builder.position_centered().resizable();
+    if opts.fullscreen {
+        window_builder.fullscreen_desktop();
+    }
+    let window = window_builder.build().unwrap();
+    let canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut screen = Screen::new(canvas, &texture_creator, &opts);
 
     // Create VM and load ROM
     let delay_timer = Arc::new(Mutex::new(0));
     let sound_timer = Arc::new(Mutex::new(0));
-    let display = Arc::new(Mutex::new([false; DISPLAY_WIDTH * DISPLAY_HEIGHT]));
+    let display = Arc::new(Mutex::new(Frame::new()));
     const NEW_BOOL: AtomicBool = AtomicBool::new(false);
-    let redraw = Arc::new([NEW_BOOL; DISPLAY_WIDTH * DISPLAY_HEIGHT]);
     let key_state = Arc::new([NEW_BOOL; 16]);
     let current_key = Arc::new((Mutex::new(None), Condvar::new()));
 
     let mut vm = Chip8Machine::new(
-        mode,
+        run.mode,
         delay_timer.clone(),
         sound_timer.clone(),
         display.clone(),
-        redraw.clone(),
         key_state.clone(),
         current_key.clone(),
     );
 
-    match vm.load_rom(rom_file) {
-        Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
+    if let Err(e) = vm
+        .set_start_address(run.start)
+        .and_then(|_| vm.load_rom_bytes(rom))
+    {
+        eprintln!("Couldn't load ROM: {}", e);
+        std::process::exit(1);
     }
+    vm.set_breakpoints(run.breakpoints.clone());
+    vm.set_tracing(run.trace);
 
     // Launch VM thread
+    let (cmd_tx, cmd_rx) = mpsc::channel();
+    let (event_tx, event_rx) = mpsc::channel();
+    let clock = run.clock;
     thread::Builder::new()
         .name("vm".to_string())
         .spawn(move || {
-            let freq = Duration::from_nanos(DELAY_1MHZ);
-            vm.run_program(freq);
+            vm.run_program(clock, cmd_rx, event_tx);
         })
         .unwrap();
+    let mut control = RunControl::new(cmd_tx, current_key.clone(), run.insts_per_frame);
+    let mut watcher = if run.watch && rom_file != "-" {
+        Some(FileWatcher::new(rom_file, Duration::from_secs(1)))
+    } else {
+        None
+    };
 
     // Main loop
     // let (_, audio_stream) = OutputStream::try_default().unwrap();
@@ -154,40 +508,35 @@ fn start_vm(mode: Chip8Mode, rom_file: &str) {
     let freq = Duration::from_nanos(DELAY_60HZ);
     'running: loop {
         // Decrement timers
-        if let Ok(mut delay) = delay_timer.lock() {
-            if *delay > 0 {
-                *delay -= 1;
+        for _ in 0..control.timer_ticks() {
+            if let Ok(mut delay) = delay_timer.lock() {
+                if *delay > 0 {
+                    *delay -= 1;
+                }
             }
-        }
 
-        if let Ok(mut sound) = sound_timer.lock() {
-            if *sound > 0 {
-                // audio_sink.play();
-                *sound -= 1;
-            } else {
-                // audio_sink.pause();
+            if let Ok(mut sound) = sound_timer.lock() {
+                if *sound > 0 {
+                    // audio_sink.play();
+                    *sound -= 1;
+                } else {
+                    // audio_sink.pause();
+                }
             }
         }
 
-        // Check for redraw
-        for x in 0..DISPLAY_WIDTH {
-            for y in 0..DISPLAY_HEIGHT {
-                let idx = y * DISPLAY_WIDTH + x;
-                if redraw[idx].load(Ordering::Acquire) {
-                    redraw[idx].store(false, Ordering::Release);
-                    let pixels = display.lock().unwrap();
-                    let r = Rect::new((x * 10) as i32, (y * 10) as i32, 10, 10);
-                    let color = if pixels[idx] {
-                        Color::WHITE
-                    } else {
-                        Color::BLACK
-                    };
-                    canvas.set_draw_color(color);
-                    canvas.fill_rect(r).unwrap();
-                }
-            }
+        if watcher.as_mut().is_some_and(|w| w.changed()) {
+            control.reload(rom_file);
         }
-        canvas.present();
+
+        for event in event_rx.try_iter() {
+            control.follow(event, &run.debug);
+            screen.set_title(&control.title());
+        }
+
+        // Redraw
+        screen.update(&display);
+        screen.present();
 
         // Respond to input events
         for e in events.poll_iter() {
@@ -197,10 +546,45 @@ fn start_vm(mode: Chip8Mode, rom_file: &str) {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => screen.toggle_fullscreen(),
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            kc @ (Keycode::F5
+                            | Keycode::F6
+                            | Keycode::Pause
+                            | Keycode::F7
+                            | Keycode::F8
+                            | Keycode::Minus
+                            | Keycode::Equals
+                            | Keycode::Backspace),
+                        ),
+                    repeat: false,
+                    ..
+                } => {
+                    match kc {
+                        Keycode::F5 => control.reload(rom_file),
+                        Keycode::F6 | Keycode::Pause => control.toggle_pause(),
+                        Keycode::F7 => control.step_frame(),
+                        Keycode::F8 => control.reset(),
+                        Keycode::Minus => control.change_speed(-1),
+                        Keycode::Equals => control.change_speed(1),
+                        _ => control.change_speed(0),
+                    }
+                    screen.set_title(&control.title());
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
+                    ..
+                } => screen.resize(),
                 Event::KeyDown {
                     scancode: Some(sc), ..
                 } => {
-                    if let Some(idx) = scancode_to_index(sc) {
+                    if let Some(idx) = key_index(sc, &run.keymap) {
                         key_state[idx].store(true, Ordering::Release);
                         let (lock, cvar) = &*current_key;
                         if let Ok(mut curr_key) = lock.lock() {
@@ -212,7 +596,7 @@ fn start_vm(mode: Chip8Mode, rom_file: &str) {
                 Event::KeyUp {
                     scancode: Some(sc), ..
                 } => {
-                    if let Some(idx) = scancode_to_index(sc) {
+                    if let Some(idx) = key_index(sc, &run.keymap) {
                         key_state[idx].store(false, Ordering::Release);
                     }
                     let (lock, _cvar) = &*current_key;
@@ -228,6 +612,331 @@ fn start_vm(mode: Chip8Mode, rom_file: &str) {
     }
 }
 
+/// Speeds selectable with the speed hotkeys, as multiples of the normal speed.
+const SPEEDS: [f64; 8] = [0.125, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0];
+
+/// Index of normal speed in `SPEEDS`.
+const NORMAL_SPEED: usize = 3;
+
+/// Number of instructions executed during one 60Hz frame at the default clock speed.
+const INSTS_PER_FRAME: usize = (DELAY_60HZ / DELAY_1MHZ) as usize;
+
+/// Sends hotkey commands to the VM thread and tracks the state they put it in.
+struct RunControl {
+    commands: Sender<Chip8Command>,
+    current_key: Arc<(Mutex<Option<u8>>, Condvar)>,
+    paused: bool,
+    speed: usize,
+    /// Number of instructions executed during one 60Hz frame at normal speed.
+    insts_per_frame: usize,
+    /// Fractional number of 60Hz timer ticks owed at the current speed.
+    timer_ticks: f64,
+    /// Where the machine is paused, when it has said.
+    location: Option<String>,
+}
+
+impl RunControl {
+    fn new(
+        commands: Sender<Chip8Command>,
+        current_key: Arc<(Mutex<Option<u8>>, Condvar)>,
+        insts_per_frame: usize,
+    ) -> RunControl {
+        RunControl {
+            commands,
+            current_key,
+            paused: false,
+            speed: NORMAL_SPEED,
+            insts_per_frame,
+            timer_ticks: 0.0,
+            location: None,
+        }
+    }
+
+    fn send(&self, cmd: Chip8Command) {
+        if self.commands.send(cmd).is_ok() {
+            // Wake the VM in case it is blocked waiting for a key
+            let (_, cvar) = &*self.current_key;
+            cvar.notify_all();
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.location = None;
+        self.send(if self.paused {
+            Chip8Command::Pause
+        } else {
+            Chip8Command::Resume
+        });
+    }
+
+    /// Pause and run a single frame's worth of instructions and timer ticks.
+    fn step_frame(&mut self) {
+        self.paused = true;
+        self.timer_ticks += 1.0;
+        self.send(Chip8Command::Step(self.insts_per_frame));
+    }
+
+    fn reset(&mut self) {
+        self.send(Chip8Command::Reset);
+    }
+
+    /// Keep track of the machine pausing itself and log what it reports, naming addresses
+    /// by their place in the source where `debug` knows it.
+    fn follow(&mut self, event: Chip8Event, debug: &DebugInfo) {
+        match event {
+            Chip8Event::Paused(addr) => {
+                log::info!("Paused at {}", source_location(debug, addr));
+                self.location = Some(debug.describe(addr));
+            }
+            Chip8Event::Breakpoint(addr) => {
+                log::info!("Breakpoint at {}", source_location(debug, addr));
+                self.paused = true;
+                self.location = Some(debug.describe(addr));
+            }
+            Chip8Event::InvalidInstruction(addr, opcode) => {
+                log::error!(
+                    "Invalid instruction {:#06x} at {}",
+                    opcode,
+                    source_location(debug, addr)
+                );
+                self.paused = true;
+                self.location = Some(debug.describe(addr));
+            }
+            Chip8Event::Executed(addr, inst) => {
+                log::trace!("{:<24} {}", debug.describe(addr), disassemble(None, inst));
+            }
+        }
+    }
+
+    /// Reload the ROM from disk and restart it.
+    fn reload(&mut self, rom_file: &str) {
+        if rom_file == "-" {
+            log::warn!("Can't reload a ROM read from stdin");
+            return;
+        }
+        match read_rom_file(rom_file) {
+            Ok(rom) => {
+                log::info!("Reloading {}", rom_file);
+                self.send(Chip8Command::LoadRom(rom));
+            }
+            Err(e) => log::warn!("Couldn't reload {}: {}", rom_file, e),
+        }
+    }
+
+    /// Move up or down the list of speeds, or back to normal speed if `delta` is zero.
+    fn change_speed(&mut self, delta: isize) {
+        self.speed = if delta == 0 {
+            NORMAL_SPEED
+        } else {
+            self.speed
+                .saturating_add_signed(delta)
+                .min(SPEEDS.len() - 1)
+        };
+        self.send(Chip8Command::SetSpeed(SPEEDS[self.speed]));
+    }
+
+    /// Number of times the timers should be decremented this frame.
+    fn timer_ticks(&mut self) -> usize {
+        if !self.paused {
+            self.timer_ticks += SPEEDS[self.speed];
+        }
+        let ticks = self.timer_ticks.floor();
+        self.timer_ticks -= ticks;
+        ticks as usize
+    }
+
+    fn title(&self) -> String {
+        let mut title = String::from("rCHIP-8");
+        if self.speed != NORMAL_SPEED {
+            title.push_str(&format!(" x{}", SPEEDS[self.speed]));
+        }
+        match (&self.location, self.paused) {
+            (Some(location), true) => title.push_str(&format!(" [paused at {}]", location)),
+            (None, true) => title.push_str(" [paused]"),
+            _ => (),
+        }
+        title
+    }
+}
+
+/// The emulator window and the state needed to draw the display into it.
+///
+/// The display is kept in a streaming texture the size of the CHIP-8 display. Only the
+/// region the machine reports as changed is uploaded each frame, and scaling to the window
+/// is left to the renderer, so the cost of a frame doesn't grow with the window size.
+struct Screen<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    palette: Palette,
+    viewport: Viewport,
+    grid: bool,
+    phosphor: Option<Phosphor>,
+    /// The pixels last copied from the machine.
+    pixels: Display,
+}
+
+impl<'a> Screen<'a> {
+    fn new(
+        canvas: Canvas<Window>,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        opts: &ScreenOptions,
+    ) -> Screen<'a> {
+        let texture = texture_creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                DISPLAY_WIDTH as u32,
+                DISPLAY_HEIGHT as u32,
+            )
+            .unwrap();
+        let mut screen = Screen {
+            canvas,
+            texture,
+            palette: opts.palette,
+            viewport: Viewport::fit(DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32),
+            grid: opts.grid,
+            phosphor: if opts.fade {
+                Some(Phosphor::new(0.35))
+            } else {
+                None
+            },
+            pixels: [false; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+        };
+        screen.resize();
+        screen
+    }
+
+    /// Recalculate the viewport after the window has changed size.
+    fn resize(&mut self) {
+        let (w, h) = self.canvas.output_size().unwrap();
+        self.viewport = Viewport::fit(w, h);
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let next = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(e) = window.set_fullscreen(next) {
+            log::warn!("Couldn't change fullscreen mode: {}", e);
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            log::warn!("Couldn't set window title: {}", e);
+        }
+    }
+
+    /// Take any changes from the machine's display and upload them to the texture, along
+    /// with any pixels that are still fading out.
+    fn update(&mut self, display: &Mutex<Frame>) {
+        let mut region = display.lock().unwrap().take(&mut self.pixels);
+        if let Some(phosphor) = &mut self.phosphor {
+            for (idx, &lit) in self.pixels.iter().enumerate() {
+                if phosphor.update(idx, lit) {
+                    let px = DirtyRect {
+                        x: idx % DISPLAY_WIDTH,
+                        y: idx / DISPLAY_WIDTH,
+                        width: 1,
+                        height: 1,
+                    };
+                    region = Some(region.map_or(px, |r| r.union(&px)));
+                }
+            }
+        }
+
+        if let Some(r) = region {
+            let (bg, fg) = (self.palette.background(), self.palette.foreground());
+            let pixels = &self.pixels;
+            let phosphor = &self.phosphor;
+            let rect = Rect::new(r.x as i32, r.y as i32, r.width as u32, r.height as u32);
+            self.texture
+                .with_lock(Some(rect), |buf, pitch| {
+                    for row in 0..r.height {
+                        for col in 0..r.width {
+                            let idx = (r.y + row) * DISPLAY_WIDTH + r.x + col;
+                            let level = match phosphor {
+                                Some(p) => p.level(idx),
+                                None if pixels[idx] => 1.0,
+                                None => 0.0,
+                            };
+                            let (red, green, blue) = blend(bg, fg, level);
+                            let offset = row * pitch + col * 3;
+                            buf[offset..offset + 3].copy_from_slice(&[red, green, blue]);
+                        }
+                    }
+                })
+                .unwrap();
+        }
+    }
+
+    /// Draw the display texture into the window, letterboxed, with the grid on top.
+    fn present(&mut self) {
+        let vp = self.viewport;
+        let width = vp.scale * DISPLAY_WIDTH as u32;
+        let height = vp.scale * DISPLAY_HEIGHT as u32;
+
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, Rect::new(vp.x, vp.y, width, height))
+            .unwrap();
+
+        if self.grid && vp.scale >= 4 {
+            let colour = blend(self.palette.background(), self.palette.foreground(), 0.15);
+            self.canvas.set_draw_color(sdl_colour(colour));
+            for col in 0..=DISPLAY_WIDTH {
+                let (x, y) = vp.pixel_origin(col, 0);
+                let bottom = Point::new(x, y + height as i32 - 1);
+                self.canvas.draw_line(Point::new(x, y), bottom).unwrap();
+            }
+            for row in 0..=DISPLAY_HEIGHT {
+                let (x, y) = vp.pixel_origin(0, row);
+                let right = Point::new(x + width as i32 - 1, y);
+                self.canvas.draw_line(Point::new(x, y), right).unwrap();
+            }
+        }
+
+        self.canvas.present();
+    }
+}
+
+#[inline]
+fn sdl_colour((r, g, b): Rgb) -> Color {
+    Color::RGB(r, g, b)
+}
+
+/// Map a host key to a CHIP-8 key, checking the ROM's own key bindings before the usual
+/// layout.
+fn key_index(sc: Scancode, keymap: &[(Scancode, usize)]) -> Option<usize> {
+    keymap
+        .iter()
+        .find(|(k, _)| *k == sc)
+        .map(|(_, idx)| *idx)
+        .or_else(|| scancode_to_index(sc))
+}
+
+/// Build extra key bindings from the named keys given for a ROM in the database, so that
+/// games can also be played with the arrow keys, space and return.
+fn keymap(keys: &HashMap<String, u8>) -> Vec<(Scancode, usize)> {
+    keys.iter()
+        .filter_map(|(name, key)| {
+            let sc = match name.as_str() {
+                "up" => Scancode::Up,
+                "down" => Scancode::Down,
+                "left" => Scancode::Left,
+                "right" => Scancode::Right,
+                "a" => Scancode::Space,
+                "b" => Scancode::Return,
+                _ => return None,
+            };
+            Some((sc, (*key & 0xf) as usize))
+        })
+        .collect()
+}
+
 #[inline]
 fn scancode_to_index(sc: Scancode) -> Option<usize> {
     match sc {
@@ -0,0 +1,406 @@
+// This file is part of rchip8.
+//
+// rchip8 is free software: you can redistribute it and/or modify it under the terms of
+// the GNU General Public License as published by the Free Software Foundation, either
+// version 3 of the License, or (at your option) any later version.
+//
+// rchip8 is distributed in the hope that it will be useful, but WITHOUT ANY WARRANTY;
+// without even the implied warranty of MERCHANTABILITY or FITNESS FOR A PARTICULAR
+// PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with rchip8.
+// If not, see <https://www.gnu.org/licenses/>.
+
+use crate::machine::Chip8Mode;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const EMBEDDED_PROGRAMS: &str = include_str!("data/programs.json");
+const EMBEDDED_HASHES: &str = include_str!("data/sha1-hashes.json");
+const EMBEDDED_PLATFORMS: &str = include_str!("data/platforms.json");
+
+/// The platform of programs written for modern interpreters.
+const MODERN_PLATFORM: &str = "modernChip8";
+
+/// Behaviours that differ between CHIP-8 implementations, named as in the community
+/// chip-8-database. Entries in a ROM's `quirkyPlatforms` only list the quirks that differ
+/// from the platform, so every field is optional.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quirks {
+    /// Shift instructions shift VX in place rather than VY into VX.
+    pub shift: Option<bool>,
+    /// Load and store increment I by X rather than X + 1.
+    pub memory_increment_by_x: Option<bool>,
+    /// Load and store leave I unchanged.
+    pub memory_leave_i_unchanged: Option<bool>,
+    /// Sprites wrap around the edges of the screen instead of being clipped.
+    pub wrap: Option<bool>,
+    /// BNNN jumps to XNN + VX rather than NNN + V0.
+    pub jump: Option<bool>,
+    /// Drawing waits for the vertical blank interrupt.
+    pub vblank: Option<bool>,
+    /// Logic instructions reset VF.
+    pub logic: Option<bool>,
+}
+
+impl Quirks {
+    /// Take any quirks set in `other` in preference to these ones.
+    pub fn overlay(&self, other: &Quirks) -> Quirks {
+        Quirks {
+            shift: other.shift.or(self.shift),
+            memory_increment_by_x: other.memory_increment_by_x.or(self.memory_increment_by_x),
+            memory_leave_i_unchanged: other
+                .memory_leave_i_unchanged
+                .or(self.memory_leave_i_unchanged),
+            wrap: other.wrap.or(self.wrap),
+            jump: other.jump.or(self.jump),
+            vblank: other.vblank.or(self.vblank),
+            logic: other.logic.or(self.logic),
+        }
+    }
+
+    /// The machine mode closest to these quirks. Modern mode shifts VX in place and leaves
+    /// I alone when loading and storing, so either of those quirks selects it.
+    pub fn mode(&self) -> Chip8Mode {
+        if self.shift == Some(true) || self.memory_leave_i_unchanged == Some(true) {
+            Chip8Mode::Modern
+        } else {
+            Chip8Mode::Original
+        }
+    }
+
+    /// Names of the quirks that are switched on.
+    pub fn enabled(&self) -> Vec<&'static str> {
+        [
+            ("shift", self.shift),
+            ("memoryIncrementByX", self.memory_increment_by_x),
+            ("memoryLeaveIUnchanged", self.memory_leave_i_unchanged),
+            ("wrap", self.wrap),
+            ("jump", self.jump),
+            ("vblank", self.vblank),
+            ("logic", self.logic),
+        ]
+        .iter()
+        .filter(|(_, on)| *on == Some(true))
+        .map(|(name, _)| *name)
+        .collect()
+    }
+}
+
+/// A machine that CHIP-8 programs were written for.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub quirks: Quirks,
+    pub default_tickrate: Option<u32>,
+}
+
+/// Colours a ROM was designed to be shown in.
+#[derive(Debug, Deserialize)]
+pub struct Colours {
+    /// Background and foreground colours, followed by the XO-CHIP plane colours if used.
+    pub pixels: Option<Vec<String>>,
+    pub buzzer: Option<String>,
+    pub silence: Option<String>,
+}
+
+/// Details of one particular ROM file for a program.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomInfo {
+    pub file: Option<String>,
+    /// Platforms the ROM runs on, best first.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Instructions to execute per 60Hz frame.
+    pub tickrate: Option<u32>,
+    #[serde(rename = "colors")]
+    pub colours: Option<Colours>,
+    /// CHIP-8 keys to use for logical buttons such as `up` or `a`.
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+    /// Quirks the ROM needs on particular platforms.
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, Quirks>,
+}
+
+/// A program, which may have several ROMs for different versions or platforms.
+#[derive(Debug, Deserialize)]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, RomInfo>,
+}
+
+/// Information about ROMs, in the format of the community chip-8-database.
+pub struct RomDatabase {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+/// A ROM found in the database.
+pub struct RomMatch<'a> {
+    pub hash: String,
+    pub program: &'a Program,
+    pub rom: &'a RomInfo,
+    /// The platform the ROM is best run on.
+    pub platform: Option<&'a Platform>,
+}
+
+impl<'a> RomMatch<'a> {
+    /// The platform's quirks, adjusted by any the ROM asks for on that platform.
+    pub fn quirks(&self) -> Quirks {
+        match self.platform {
+            Some(p) => match self.rom.quirky_platforms.get(&p.id) {
+                Some(q) => p.quirks.overlay(q),
+                None => p.quirks,
+            },
+            None => Quirks::default(),
+        }
+    }
+
+    /// The machine mode to run the ROM in, if its platform is known. Modern mode is meant
+    /// for programs written for modern interpreters, so their platform selects it even
+    /// though none of its quirks are set.
+    pub fn mode(&self) -> Option<Chip8Mode> {
+        let platform = self.platform?;
+        if platform.id == MODERN_PLATFORM {
+            Some(Chip8Mode::Modern)
+        } else {
+            Some(self.quirks().mode())
+        }
+    }
+
+    /// Instructions per frame, from the ROM or else its platform.
+    pub fn tickrate(&self) -> Option<u32> {
+        self.rom
+            .tickrate
+            .or_else(|| self.platform.and_then(|p| p.default_tickrate))
+    }
+
+    /// The foreground and background colours as a palette string, if the ROM has them.
+    pub fn palette(&self) -> Option<String> {
+        let pixels = self.rom.colours.as_ref()?.pixels.as_ref()?;
+        Some(pixels.join(","))
+    }
+}
+
+/// The SHA-1 hash of a ROM as a lower case hex string, as used to key the database.
+pub fn rom_hash(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl RomDatabase {
+    /// Build a database from the contents of `programs.json`, `sha1-hashes.json` and
+    /// `platforms.json`.
+    pub fn from_json(programs: &str, hashes: &str, platforms: &str) -> Result<Self, String> {
+        let programs: Vec<Program> =
+            serde_json::from_str(programs).map_err(|e| format!("programs.json: {}", e))?;
+        let hashes: HashMap<String, usize> =
+            serde_json::from_str(hashes).map_err(|e| format!("sha1-hashes.json: {}", e))?;
+        let platforms: Vec<Platform> =
+            serde_json::from_str(platforms).map_err(|e| format!("platforms.json: {}", e))?;
+
+        if let Some((hash, idx)) = hashes.iter().find(|(_, idx)| **idx >= programs.len()) {
+            return Err(format!(
+                "sha1-hashes.json: {} refers to program {} which does not exist",
+                hash, idx
+            ));
+        }
+
+        Ok(RomDatabase {
+            programs,
+            hashes,
+            platforms,
+        })
+    }
+
+    /// The database built into the program. It describes the platforms, but holds no
+    /// programs unless the chip-8-database's files have been copied into `data`.
+    pub fn embedded() -> RomDatabase {
+        RomDatabase::from_json(EMBEDDED_PROGRAMS, EMBEDDED_HASHES, EMBEDDED_PLATFORMS).unwrap()
+    }
+
+    /// Load a database from a directory laid out like the chip-8-database repository's
+    /// `database` directory.
+    pub fn load_dir(dir: &Path) -> Result<RomDatabase, String> {
+        let read = |name: &str| {
+            let path = dir.join(name);
+            fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+        RomDatabase::from_json(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|p| p.id == id)
+    }
+
+    /// Find a ROM by its contents.
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomMatch<'_>> {
+        self.lookup_hash(&rom_hash(rom))
+    }
+
+    /// Find a ROM by its SHA-1 hash.
+    pub fn lookup_hash(&self, hash: &str) -> Option<RomMatch<'_>> {
+        let program = &self.programs[*self.hashes.get(hash)?];
+        let info = program.roms.get(hash)?;
+        let platform = info.platforms.iter().find_map(|id| self.platform(id));
+        Some(RomMatch {
+            hash: String::from(hash),
+            program,
+            rom: info,
+            platform,
+        })
+    }
+}
+
+#[cfg(test)]
+mod romdb_tests {
+    use super::*;
+    use crate::c8asc::assemble;
+    use rstest::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Test",
+            "authors": ["A", "B"],
+            "roms": {
+                "9bce73d0c8b9eca4f24154f3bd3b8aa473b1c3a9": {
+                    "platforms": ["unknown", "vip"],
+                    "tickrate": 20,
+                    "colors": { "pixels": ["#000000", "#ff0000"] },
+                    "keys": { "up": 5, "a": 6 },
+                    "quirkyPlatforms": { "vip": { "shift": true } }
+                }
+            }
+        },
+        {
+            "title": "Other",
+            "roms": { "0000000000000000000000000000000000000000": { "platforms": ["vip"] } }
+        }
+    ]"##;
+
+    const HASHES: &str = r#"{
+        "9bce73d0c8b9eca4f24154f3bd3b8aa473b1c3a9": 0,
+        "0000000000000000000000000000000000000000": 1
+    }"#;
+
+    const PLATFORMS: &str = r#"[
+        {
+            "id": "vip",
+            "name": "VIP",
+            "defaultTickrate": 15,
+            "quirks": { "shift": false, "memoryLeaveIUnchanged": false, "vblank": true }
+        }
+    ]"#;
+
+    const ROM: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+    #[fixture]
+    fn db() -> RomDatabase {
+        RomDatabase::from_json(PROGRAMS, HASHES, PLATFORMS).unwrap()
+    }
+
+    #[rstest]
+    fn test_rom_hash() {
+        assert_eq!(
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            rom_hash(&[]).as_str()
+        );
+        assert_eq!(
+            "9bce73d0c8b9eca4f24154f3bd3b8aa473b1c3a9",
+            rom_hash(&ROM).as_str()
+        );
+    }
+
+    #[rstest]
+    fn test_lookup(db: RomDatabase) {
+        let m = db.lookup(&ROM).unwrap();
+        assert_eq!("Test", m.program.title);
+        assert_eq!(vec!["A", "B"], m.program.authors);
+        assert_eq!("vip", m.platform.unwrap().id);
+        assert_eq!(Some(20), m.tickrate());
+        assert_eq!(Some("#000000,#ff0000"), m.palette().as_deref());
+        assert_eq!(Some(&5), m.rom.keys.get("up"));
+    }
+
+    #[rstest]
+    fn test_lookup_missing(db: RomDatabase) {
+        assert!(db.lookup(&[0x00, 0xe0]).is_none());
+    }
+
+    #[rstest]
+    fn test_quirks_overlay(db: RomDatabase) {
+        let q = db.lookup(&ROM).unwrap().quirks();
+        assert_eq!(Some(true), q.shift);
+        assert_eq!(Some(true), q.vblank);
+        assert_eq!(Some(false), q.memory_leave_i_unchanged);
+        assert_eq!(None, q.wrap);
+        assert_eq!(vec!["shift", "vblank"], q.enabled());
+        assert!(q.mode() == Chip8Mode::Modern);
+    }
+
+    #[rstest]
+    fn test_platform_quirks(db: RomDatabase) {
+        let vip = db.platform("vip").unwrap();
+        assert!(vip.quirks.mode() == Chip8Mode::Original);
+    }
+
+    #[rstest]
+    fn test_bad_hash_index() {
+        let hashes = r#"{ "abcd": 7 }"#;
+        assert!(RomDatabase::from_json(PROGRAMS, hashes, PLATFORMS).is_err());
+    }
+
+    #[rstest]
+    fn test_embedded() {
+        let db = RomDatabase::embedded();
+        assert!(db.platform("originalChip8").is_some());
+        assert!(db.platform("superchip").unwrap().quirks.mode() == Chip8Mode::Modern);
+    }
+
+    /// A database of the example programs in `test`, on the built-in platforms.
+    #[fixture]
+    fn examples() -> RomDatabase {
+        RomDatabase::from_json(
+            include_str!("../../test/romdb/programs.json"),
+            include_str!("../../test/romdb/sha1-hashes.json"),
+            EMBEDDED_PLATFORMS,
+        )
+        .unwrap()
+    }
+
+    #[rstest]
+    fn test_modern_platform(examples: RomDatabase) {
+        let rom = assemble(include_str!("../../test/natural-numbers.c8asm")).unwrap();
+        let m = examples.lookup(&rom).unwrap();
+        assert_eq!("Natural Numbers", m.program.title);
+        assert_eq!(MODERN_PLATFORM, m.platform.unwrap().id);
+        assert!(m.mode() == Some(Chip8Mode::Modern));
+    }
+
+    #[rstest]
+    fn test_mode_without_platform() {
+        let programs = r#"[{ "title": "T", "roms": { "abcd": { "platforms": ["unknown"] } } }]"#;
+        let db = RomDatabase::from_json(programs, r#"{ "abcd": 0 }"#, PLATFORMS).unwrap();
+        assert!(db.lookup_hash("abcd").unwrap().mode().is_none());
+    }
+}